@@ -0,0 +1,212 @@
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use crate::error::PieError;
+use crate::glyph::{BULLET, CIRCLE};
+use crate::{get_installed_packages, package_root, InstalledPackages, ANDSTORE_ROOT, PIE_DATA};
+
+// A command a package offers under a shared name, e.g. vim providing vi
+// from bin/vim
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AlternativeSpec {
+    pub name: String,
+    pub path: String,
+    #[serde(default)]
+    pub priority: i32,
+}
+
+#[derive(Subcommand)]
+pub enum AlternativesAction {
+    /// Show providers of all or one shared command
+    List { name: Option<String> },
+    /// Pin a command to a specific provider
+    Set { name: String, package: String },
+    /// Go back to the highest priority provider
+    Auto { name: String },
+}
+
+struct Provider<'a> {
+    package: &'a str,
+    path: &'a str,
+    priority: i32,
+    overlay: bool,
+}
+
+fn selections_file() -> String {
+    format!("{PIE_DATA}/alternatives.json")
+}
+
+// Manual choices by command name
+fn selections() -> Result<BTreeMap<String, String>, PieError> {
+    if !Path::new(&selections_file()).exists() {
+        return Ok(BTreeMap::new());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(
+        selections_file(),
+    )?)?)
+}
+
+fn save_selections(selections: &BTreeMap<String, String>) -> Result<(), PieError> {
+    fs::create_dir_all(&PIE_DATA)?;
+    fs::write(selections_file(), serde_json::to_string_pretty(selections)?)?;
+    Ok(())
+}
+
+// Providers by command name, best first
+fn groups(installed: &InstalledPackages) -> BTreeMap<&str, Vec<Provider<'_>>> {
+    let mut groups: BTreeMap<&str, Vec<Provider>> = BTreeMap::new();
+    for pkg in installed.packages.values() {
+        for alternative in &pkg.alternatives {
+            if alternative.name.is_empty() || alternative.name.contains('/') {
+                continue;
+            }
+            groups
+                .entry(alternative.name.as_str())
+                .or_default()
+                .push(Provider {
+                    package: &pkg.name,
+                    path: &alternative.path,
+                    priority: alternative.priority,
+                    overlay: pkg.overlay,
+                });
+        }
+    }
+    for providers in groups.values_mut() {
+        providers.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.package.cmp(b.package)));
+    }
+    groups
+}
+
+fn active<'a, 'b>(providers: &'b [Provider<'a>], selection: Option<&String>) -> &'b Provider<'a> {
+    selection
+        .and_then(|package| providers.iter().find(|p| p.package == package))
+        .unwrap_or(&providers[0])
+}
+
+// Swapped in with a rename so the command never disappears mid-switch
+fn replace_link(link: &str, target: &str) -> Result<(), PieError> {
+    if fs::read_link(link).is_ok_and(|current| current == Path::new(target)) {
+        return Ok(());
+    }
+    let temp_link = format!("{link}.pie-new");
+    let _ = fs::remove_file(&temp_link);
+    symlink(target, &temp_link)?;
+    fs::rename(&temp_link, link)?;
+    Ok(())
+}
+
+// bin/<name> -> ../alternatives/<name> -> the active provider's file
+pub fn refresh(installed: &InstalledPackages) -> Result<(), PieError> {
+    let selections = selections()?;
+    let groups = groups(installed);
+    let alternatives_dir = format!("{ANDSTORE_ROOT}/alternatives");
+
+    if Path::new(&alternatives_dir).exists() {
+        for entry in fs::read_dir(&alternatives_dir)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if !groups.contains_key(name.as_str()) {
+                fs::remove_file(format!("{alternatives_dir}/{name}"))?;
+                let bin_link = format!("{ANDSTORE_ROOT}/bin/{name}");
+                if fs::read_link(&bin_link).is_ok() {
+                    fs::remove_file(&bin_link)?;
+                }
+            }
+        }
+    }
+
+    if groups.is_empty() {
+        return Ok(());
+    }
+    fs::create_dir_all(&alternatives_dir)?;
+    fs::create_dir_all(format!("{ANDSTORE_ROOT}/bin"))?;
+
+    for (name, providers) in &groups {
+        let provider = active(providers, selections.get(*name));
+        let target = format!("{}/{}", package_root(provider.overlay), provider.path);
+        replace_link(&format!("{alternatives_dir}/{name}"), &target)?;
+
+        // A real file of the same name belongs to a package, leave it alone
+        let bin_link = format!("{ANDSTORE_ROOT}/bin/{name}");
+        if fs::symlink_metadata(&bin_link).is_ok_and(|meta| !meta.is_symlink()) {
+            eprintln!("Warning: {bin_link} is not managed by pie, skipping");
+            continue;
+        }
+        replace_link(&bin_link, &format!("../alternatives/{name}"))?;
+    }
+
+    Ok(())
+}
+
+// Links pie creates for alternatives, relative to the install root
+pub fn managed_paths(installed: &InstalledPackages) -> Vec<String> {
+    groups(installed)
+        .keys()
+        .flat_map(|name| [format!("alternatives/{name}"), format!("bin/{name}")])
+        .collect()
+}
+
+pub fn run_alternatives(action: Option<AlternativesAction>) -> Result<(), PieError> {
+    let installed = get_installed_packages()?;
+    let mut selections = selections()?;
+
+    match action.unwrap_or(AlternativesAction::List { name: None }) {
+        AlternativesAction::List { name } => {
+            let groups = groups(&installed);
+            if let Some(name) = &name {
+                if !groups.contains_key(name.as_str()) {
+                    return Err(format!("No installed package provides '{name}'").into());
+                }
+            }
+            if groups.is_empty() {
+                println!("No alternatives installed");
+            }
+            for (group, providers) in &groups {
+                if name.as_ref().is_some_and(|name| name != group) {
+                    continue;
+                }
+                let selection = selections.get(*group);
+                let current = active(providers, selection).package;
+                let mode = if selection.is_some() {
+                    "manual"
+                } else {
+                    "auto"
+                };
+                println!("{group} ({mode}):");
+                for provider in providers {
+                    let marker = if provider.package == current {
+                        &BULLET
+                    } else {
+                        &CIRCLE
+                    };
+                    println!(
+                        "  {marker} {} ({}, priority {})",
+                        provider.package, provider.path, provider.priority
+                    );
+                }
+            }
+            return Ok(());
+        }
+        AlternativesAction::Set { name, package } => {
+            let provides = installed
+                .packages
+                .get(&package)
+                .is_some_and(|pkg| pkg.alternatives.iter().any(|a| a.name == name));
+            if !provides {
+                return Err(format!("'{package}' does not provide '{name}'").into());
+            }
+            println!("{name} now points to {package}");
+            selections.insert(name, package);
+        }
+        AlternativesAction::Auto { name } => {
+            selections.remove(&name);
+            println!("{name} follows the highest priority provider");
+        }
+    }
+
+    save_selections(&selections)?;
+    refresh(&installed)
+}
@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+use crate::error::PieError;
+use crate::glyph::CROSS;
+use crate::{fetch_repo, get_installed_packages, get_separator, version, Repo};
+
+#[derive(Serialize, Deserialize)]
+pub struct Advisory {
+    pub id: String,
+    pub package: String,
+    // Version range, see version::matches_range
+    pub affected: String,
+    #[serde(default = "default_severity")]
+    pub severity: String,
+    #[serde(default)]
+    pub fixed: Option<String>,
+    #[serde(default)]
+    pub summary: String,
+}
+
+fn default_severity() -> String {
+    "unknown".to_string()
+}
+
+// Advisories affecting the installed version that the repo's version is
+// no longer affected by
+pub fn fixed_advisories<'a>(repo: &'a Repo, name: &str, installed: &str) -> Vec<&'a Advisory> {
+    let Some(available) = repo.packages.get(name) else {
+        return Vec::new();
+    };
+    repo.advisories
+        .iter()
+        .filter(|advisory| {
+            advisory.package == name
+                && version::matches_range(installed, &advisory.affected)
+                && !version::matches_range(&available.version, &advisory.affected)
+        })
+        .collect()
+}
+
+// Returns false if any installed package is affected by an advisory
+pub fn audit() -> Result<bool, PieError> {
+    let repo = fetch_repo()?;
+    let installed = get_installed_packages()?;
+
+    let mut findings: Vec<_> = repo
+        .advisories
+        .iter()
+        .filter_map(|advisory| {
+            let pkg = installed.packages.get(&advisory.package)?;
+            version::matches_range(&pkg.version, &advisory.affected).then_some((pkg, advisory))
+        })
+        .collect();
+    findings.sort_by(|a, b| a.0.name.cmp(&b.0.name).then(a.1.id.cmp(&b.1.id)));
+
+    if findings.is_empty() {
+        println!(
+            "No known vulnerabilities ({} packages, {} advisories checked)",
+            installed.packages.len(),
+            repo.advisories.len()
+        );
+        return Ok(true);
+    }
+
+    println!("\n{}", get_separator());
+    println!("VULNERABLE PACKAGES");
+    println!("{}", get_separator());
+    for (pkg, advisory) in &findings {
+        println!(
+            "{CROSS} {} v{}: {} ({})",
+            pkg.name, pkg.version, advisory.id, advisory.severity
+        );
+        if !advisory.summary.is_empty() {
+            println!("  {}", advisory.summary);
+        }
+
+        let available = repo.packages.get(&pkg.name).map(|p| p.version.as_str());
+        match (&advisory.fixed, available) {
+            (Some(fixed), Some(available))
+                if version::compare_versions(available, fixed) != Ordering::Less =>
+            {
+                println!("  Fixed in v{fixed}, upgrade to v{available}");
+            }
+            (Some(fixed), _) => println!("  Fixed in v{fixed}, not yet available in the repo"),
+            (None, _) => println!("  No fix available"),
+        }
+    }
+    println!("{}", get_separator());
+    println!("{} vulnerable packages found", findings.len());
+
+    Ok(false)
+}
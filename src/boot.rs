@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path};
+
+use crate::error::PieError;
+use crate::paths;
+
+// Magisk, KernelSU and APatch all run the executables in these directories,
+// post-fs-data.d blocking early in boot and service.d in late start
+const BOOT_DIR: &str = "/data/adb";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BootStage {
+    PostFsData,
+    Service,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BootScript {
+    pub stage: BootStage,
+    // Path inside the package
+    pub path: String,
+}
+
+fn root_solution() -> Option<&'static str> {
+    [
+        ("/data/adb/magisk", "Magisk"),
+        ("/data/adb/ksu", "KernelSU"),
+        ("/data/adb/ap", "APatch"),
+    ]
+    .into_iter()
+    .find(|(dir, _)| Path::new(&paths::on_device(dir)).exists())
+    .map(|(_, name)| name)
+}
+
+// Copies the declared scripts out of the package into the root manager's
+// boot directories and returns where they went, for the database
+pub fn install_scripts(
+    name: &str,
+    scripts: &[BootScript],
+    contents: &[String],
+    root: &str,
+) -> Result<Vec<String>, PieError> {
+    if scripts.is_empty() {
+        return Ok(Vec::new());
+    }
+    if paths::termux() {
+        eprintln!("Warning: boot scripts of {name} need root and were not installed");
+        return Ok(Vec::new());
+    }
+    let Some(solution) = root_solution() else {
+        eprintln!("Warning: no root solution found, boot scripts of {name} were not installed");
+        return Ok(Vec::new());
+    };
+
+    let mut installed = Vec::new();
+    for script in scripts {
+        let relative = Path::new(&script.path);
+        if !contents.contains(&script.path)
+            || relative
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            eprintln!(
+                "Warning: boot script '{}' of {name} is not part of the package, skipped",
+                script.path
+            );
+            continue;
+        }
+
+        let dir = match script.stage {
+            BootStage::PostFsData => format!("{}/post-fs-data.d", paths::on_device(BOOT_DIR)),
+            BootStage::Service => format!("{}/service.d", paths::on_device(BOOT_DIR)),
+        };
+        let file_name = relative
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let target = format!("{dir}/pie-{name}-{file_name}");
+
+        fs::create_dir_all(&dir)?;
+        fs::copy(Path::new(root).join(relative), &target)?;
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o755))?;
+        installed.push(target);
+    }
+
+    if !installed.is_empty() {
+        println!("Installed {} boot scripts for {solution}", installed.len());
+    }
+    Ok(installed)
+}
+
+pub fn remove_scripts<'a>(scripts: impl IntoIterator<Item = &'a String>) -> Result<(), PieError> {
+    for script in scripts {
+        if Path::new(script).exists() {
+            fs::remove_file(script)?;
+        }
+    }
+    Ok(())
+}
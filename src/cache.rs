@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::NamedTempFile;
+
+use crate::config::{self, PackagePin, RepoConfig};
+use crate::error::PieError;
+use crate::{boot, parse_size, sha256_file, InstalledPackage, Repo, PIE_DATA};
+
+pub const DEFAULT_KEEP_VERSIONS: usize = 2;
+pub const DEFAULT_CACHE_MAX_SIZE: &str = "512MB";
+
+fn index_cache_path(repo: &str) -> String {
+    format!("{PIE_DATA}/cache/index/{repo}.json")
+}
+
+// Written by `pie update`, so commands that must stay fast or work offline
+// can read the index without touching the network
+pub fn save_indices(indices: &[(RepoConfig, Vec<u8>)]) -> Result<(), PieError> {
+    fs::create_dir_all(format!("{PIE_DATA}/cache/index"))?;
+    for (repo_config, content) in indices {
+        fs::write(index_cache_path(&repo_config.name), content)?;
+    }
+    Ok(())
+}
+
+// Repos that were never updated are skipped
+pub fn load_cached_indices() -> Result<Vec<(RepoConfig, Vec<u8>)>, PieError> {
+    let config = config::load_config()?;
+    let mut indices = Vec::new();
+
+    for repo_config in config.repos {
+        let path = index_cache_path(&repo_config.name);
+        if Path::new(&path).exists() {
+            let content = fs::read(&path)?;
+            indices.push((repo_config, content));
+        }
+    }
+
+    Ok(indices)
+}
+
+fn repo_cache_path() -> String {
+    format!("{PIE_DATA}/cache/index/repo.bin")
+}
+
+// Everything the merged index depends on: the raw indices, the repo order and
+// urls, the device ABI and the layout of this pie build
+pub fn repo_key(
+    indices: &[(RepoConfig, Vec<u8>)],
+    pins: &BTreeMap<String, PackagePin>,
+    arch: Option<&str>,
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    hasher.update([0u8]);
+    hasher.update(arch.unwrap_or_default());
+    for (repo_config, content) in indices {
+        hasher.update([0u8]);
+        hasher.update(&repo_config.name);
+        hasher.update([0u8]);
+        hasher.update(&repo_config.url);
+        hasher.update([0u8]);
+        hasher.update(repo_config.priority.to_le_bytes());
+        hasher.update(Sha256::digest(content));
+    }
+    // Pins change which copy of a package the merged index holds
+    hasher.update(serde_json::to_vec(pins).unwrap_or_default());
+    hex::encode(hasher.finalize())
+}
+
+// The key is stored in front of the encoded index so a stale cache is
+// recognized without decoding it
+pub fn load_repo(key: &str) -> Option<Repo> {
+    let content = fs::read(repo_cache_path()).ok()?;
+    let encoded = content.strip_prefix(key.as_bytes())?;
+    bincode::deserialize(encoded).ok()
+}
+
+pub fn save_repo(key: &str, repo: &Repo) {
+    let Ok(encoded) = bincode::serialize(repo) else {
+        return;
+    };
+    let path = repo_cache_path();
+    let Some(dir) = Path::new(&path).parent() else {
+        return;
+    };
+    // Written aside and renamed, a concurrent reader never sees half a file
+    let written = fs::create_dir_all(dir)
+        .and_then(|_| NamedTempFile::new_in(dir))
+        .and_then(|mut temp| {
+            temp.write_all(key.as_bytes())?;
+            temp.write_all(&encoded)?;
+            temp.persist(&path).map_err(|e| e.error)?;
+            Ok(())
+        });
+    if let Err(e) = written {
+        eprintln!("Warning: could not cache the parsed index: {e}");
+    }
+}
+
+fn archive_dir(name: &str) -> String {
+    format!("{PIE_DATA}/cache/archives/{name}")
+}
+
+fn version_stem(version: &str) -> String {
+    version.replace('/', "_")
+}
+
+pub fn archive_path(name: &str, version: &str) -> String {
+    format!("{}/{}.archive", archive_dir(name), version_stem(version))
+}
+
+// Everything needed to reinstall a version offline: the verified archive is
+// stored next to the record pie wrote when that version was installed
+#[derive(Serialize, Deserialize)]
+pub struct CachedVersion {
+    pub sha256: String,
+    // Hash of the zstd dictionary under cache/dict, if the archive needs one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dictionary: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub boot_scripts: Vec<boot::BootScript>,
+    pub cached_at: u64,
+    pub package: InstalledPackage,
+}
+
+// Copies a download into the cache while it is being extracted. Caching is
+// best effort, a full disk must not fail the install
+struct Tee {
+    inner: Box<dyn Read>,
+    file: Option<fs::File>,
+}
+
+impl Read for Tee {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(file) = &mut self.file {
+            if file.write_all(&buf[..n]).is_err() {
+                self.file = None;
+            }
+        }
+        Ok(n)
+    }
+}
+
+pub struct PendingArchive(NamedTempFile);
+
+fn keep_versions() -> usize {
+    config::load_config()
+        .map(|config| config.cache_versions)
+        .unwrap_or(DEFAULT_KEEP_VERSIONS)
+}
+
+fn max_size() -> u64 {
+    let value = config::load_config()
+        .map(|config| config.cache_max_size)
+        .unwrap_or_else(|_| DEFAULT_CACHE_MAX_SIZE.to_string());
+    parse_size(&value).unwrap_or_else(|| {
+        eprintln!("Warning: invalid cache_max_size '{value}', using {DEFAULT_CACHE_MAX_SIZE}");
+        parse_size(DEFAULT_CACHE_MAX_SIZE).unwrap_or_default()
+    })
+}
+
+pub fn tee_archive(name: &str, reader: Box<dyn Read>) -> (Box<dyn Read>, Option<PendingArchive>) {
+    let dir = archive_dir(name);
+    let temp = (keep_versions() > 0)
+        .then(|| {
+            fs::create_dir_all(&dir)
+                .and_then(|_| NamedTempFile::new_in(&dir))
+                .ok()
+        })
+        .flatten();
+    let file = temp
+        .as_ref()
+        .and_then(|temp| temp.as_file().try_clone().ok());
+    (
+        Box::new(Tee {
+            inner: reader,
+            file,
+        }),
+        temp.map(PendingArchive),
+    )
+}
+
+// Called once the archive was extracted and verified
+pub fn store_archive(pending: PendingArchive, version: CachedVersion) {
+    let name = version.package.name.clone();
+    if let Err(e) = try_store_archive(pending, version) {
+        eprintln!("Warning: could not cache the archive of {name}: {e}");
+    }
+}
+
+fn try_store_archive(pending: PendingArchive, version: CachedVersion) -> Result<(), PieError> {
+    let package = &version.package;
+    // The copy is incomplete if writing to the cache failed halfway
+    if sha256_file(pending.0.path())? != version.sha256 {
+        return Ok(());
+    }
+
+    pending
+        .0
+        .persist(archive_path(&package.name, &package.version))
+        .map_err(|e| e.error)?;
+    fs::write(
+        format!(
+            "{}/{}.json",
+            archive_dir(&package.name),
+            version_stem(&package.version)
+        ),
+        serde_json::to_string_pretty(&version)?,
+    )?;
+
+    // The newest keep_versions() stay, older ones are removed
+    for stale in cached_versions(&package.name)?.iter().skip(keep_versions()) {
+        let _ = fs::remove_file(archive_path(&package.name, &stale.package.version));
+        let _ = fs::remove_file(format!(
+            "{}/{}.json",
+            archive_dir(&package.name),
+            version_stem(&stale.package.version)
+        ));
+    }
+    evict_archives(max_size())
+}
+
+// The modification time doubles as the last use, set when an archive is
+// stored and again whenever rollback reads it
+pub fn touch_archive(name: &str, version: &str) {
+    let _ = fs::File::options()
+        .write(true)
+        .open(archive_path(name, version))
+        .and_then(|file| file.set_modified(SystemTime::now()));
+}
+
+fn evict_archives(max_size: u64) -> Result<(), PieError> {
+    let Ok(dirs) = fs::read_dir(format!("{PIE_DATA}/cache/archives")) else {
+        return Ok(());
+    };
+
+    let mut archives: Vec<(SystemTime, u64, PathBuf)> = Vec::new();
+    for dir in dirs {
+        for entry in fs::read_dir(dir?.path())? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "archive") {
+                let meta = fs::metadata(&path)?;
+                archives.push((meta.modified().unwrap_or(UNIX_EPOCH), meta.len(), path));
+            }
+        }
+    }
+
+    let mut total: u64 = archives.iter().map(|(_, size, _)| size).sum();
+    archives.sort_by_key(|(used, ..)| *used);
+    for (_, size, path) in archives {
+        if total <= max_size {
+            break;
+        }
+        fs::remove_file(&path)?;
+        let _ = fs::remove_file(path.with_extension("json"));
+        total -= size;
+    }
+    Ok(())
+}
+
+// Newest first
+pub fn cached_versions(name: &str) -> Result<Vec<CachedVersion>, PieError> {
+    let Ok(entries) = fs::read_dir(archive_dir(name)) else {
+        return Ok(Vec::new());
+    };
+
+    let mut versions = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let version: CachedVersion = serde_json::from_str(&fs::read_to_string(&path)?)?;
+            if Path::new(&archive_path(name, &version.package.version)).exists() {
+                versions.push(version);
+            }
+        }
+    }
+    versions.sort_by(|a, b| b.cached_at.cmp(&a.cached_at));
+    Ok(versions)
+}
@@ -0,0 +1,88 @@
+use clap::{CommandFactory, ValueEnum};
+use clap_complete::Shell;
+use std::io;
+
+use crate::error::PieError;
+use crate::{cache, get_installed_packages, merge_indices, Cli};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CompletionKind {
+    /// Packages in the cached repo index
+    Available,
+    /// Installed packages
+    Installed,
+}
+
+// Static clap completions, with install/uninstall arguments routed through
+// `pie __complete` so package names come from the cache and database
+const BASH_DYNAMIC: &str = r#"
+_pie_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    if [[ $COMP_CWORD -ge 2 && "$cur" != -* ]]; then
+        case "${COMP_WORDS[1]}" in
+            install|add)
+                COMPREPLY=($(pie __complete available "$cur" 2>/dev/null))
+                return 0
+                ;;
+            uninstall|remove)
+                COMPREPLY=($(pie __complete installed "$cur" 2>/dev/null))
+                return 0
+                ;;
+        esac
+    fi
+    _pie "$@"
+}
+complete -F _pie_dynamic -o bashdefault -o default pie
+"#;
+
+const ZSH_DYNAMIC: &str = r#"
+_pie_dynamic() {
+    if (( CURRENT > 2 )) && [[ ${words[CURRENT]} != -* ]]; then
+        case ${words[2]} in
+            install|add)
+                compadd -- ${(f)"$(pie __complete available ${words[CURRENT]} 2>/dev/null)"}
+                return
+                ;;
+            uninstall|remove)
+                compadd -- ${(f)"$(pie __complete installed ${words[CURRENT]} 2>/dev/null)"}
+                return
+                ;;
+        esac
+    fi
+    _pie "$@"
+}
+compdef _pie_dynamic pie
+"#;
+
+const FISH_DYNAMIC: &str = r#"
+complete -c pie -n "__fish_seen_subcommand_from install add" -f -a "(pie __complete available (commandline -ct) 2>/dev/null)"
+complete -c pie -n "__fish_seen_subcommand_from uninstall remove" -f -a "(pie __complete installed (commandline -ct) 2>/dev/null)"
+"#;
+
+pub fn print_completions(shell: Shell) {
+    clap_complete::generate(shell, &mut Cli::command(), "pie", &mut io::stdout());
+
+    match shell {
+        Shell::Bash => print!("{BASH_DYNAMIC}"),
+        Shell::Zsh => print!("{ZSH_DYNAMIC}"),
+        Shell::Fish => print!("{FISH_DYNAMIC}"),
+        _ => {}
+    }
+}
+
+pub fn complete(kind: CompletionKind, prefix: &str) -> Result<(), PieError> {
+    let mut names: Vec<String> = match kind {
+        CompletionKind::Available => merge_indices(&cache::load_cached_indices()?)?
+            .packages
+            .into_keys()
+            .collect(),
+        CompletionKind::Installed => get_installed_packages()?.packages.into_keys().collect(),
+    };
+    names.retain(|name| name.starts_with(prefix));
+    names.sort();
+
+    for name in names {
+        println!("{name}");
+    }
+    Ok(())
+}
@@ -0,0 +1,424 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::cache::{DEFAULT_CACHE_MAX_SIZE, DEFAULT_KEEP_VERSIONS};
+use crate::error::PieError;
+use crate::exec::DEFAULT_SCRIPT_TIMEOUT;
+use crate::glob;
+use crate::net::{self, Auth};
+use crate::version;
+use crate::{PIE_DATA, REPO_URL};
+
+pub const DEFAULT_IPFS_GATEWAY: &str = "https://ipfs.io";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RepoConfig {
+    pub name: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    // SHA-256 the fetched index must have, so a fleet or CI works from one
+    // published snapshot and an older index can't be replayed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_sha256: Option<String>,
+    // Packages offered by several repos come from the highest priority one,
+    // config order breaks ties
+    #[serde(default, skip_serializing_if = "is_default_priority")]
+    pub priority: i32,
+}
+
+fn is_default_priority(priority: &i32) -> bool {
+    *priority == 0
+}
+
+// Restricts where a package may come from, e.g.
+// "pins": {"curl": {"repo": "main", "version": ">=8.0, <9"}}
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PackagePin {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    // Comma separated comparisons, see version::matches_range
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl PackagePin {
+    pub fn allows(&self, repo: &str, version: &str) -> bool {
+        self.repo.as_deref().is_none_or(|pinned| pinned == repo)
+            && self
+                .version
+                .as_deref()
+                .is_none_or(|range| version::matches_range(version, range))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallMode {
+    #[default]
+    Direct,
+    Overlay,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default = "default_repos")]
+    pub repos: Vec<RepoConfig>,
+    #[serde(default)]
+    pub install_mode: InstallMode,
+    // Same as always passing -y
+    #[serde(default)]
+    pub assume_yes: bool,
+    #[serde(default)]
+    pub licenses: LicensePolicy,
+    // Seconds a hook or maintainer script may run before it is killed, also
+    // how long a stopping service gets before its process group is killed
+    #[serde(default = "default_script_timeout")]
+    pub script_timeout: u64,
+    // Mirror errors, warnings and transaction results to logcat under "pie"
+    #[serde(default)]
+    pub logcat: bool,
+    // Same as always passing --ascii
+    #[serde(default)]
+    pub ascii: bool,
+    // Installed versions of each package kept in the cache for `pie rollback`,
+    // 0 disables the archive cache
+    #[serde(default = "default_cache_versions")]
+    pub cache_versions: usize,
+    // Cached archives beyond this size are evicted least recently used
+    // first, e.g. "200MB"
+    #[serde(default = "default_cache_max_size")]
+    pub cache_max_size: String,
+    #[serde(default)]
+    pub unattended: UnattendedPolicy,
+    // Gateway for packages published by CID, e.g. a local node at
+    // http://127.0.0.1:8080
+    #[serde(default = "default_ipfs_gateway")]
+    pub ipfs_gateway: String,
+    // Opt-in: anonymous install and upgrade counts are posted here when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub telemetry_endpoint: Option<String>,
+    // Safety checks overridden on every run, like the --force-* flags
+    #[serde(default)]
+    pub force: ForceOptions,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pins: BTreeMap<String, PackagePin>,
+    // Names or globs never installed, not even as a dependency, and left out
+    // of search, e.g. packages known to break on this device
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+}
+
+fn default_script_timeout() -> u64 {
+    DEFAULT_SCRIPT_TIMEOUT
+}
+
+fn default_ipfs_gateway() -> String {
+    DEFAULT_IPFS_GATEWAY.to_string()
+}
+
+fn default_cache_versions() -> usize {
+    DEFAULT_KEEP_VERSIONS
+}
+
+fn default_cache_max_size() -> String {
+    DEFAULT_CACHE_MAX_SIZE.to_string()
+}
+
+// Each one lifts a single safety check, there is deliberately no catch-all
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct ForceOptions {
+    // Remove essential packages without the double confirmation
+    #[serde(default)]
+    pub remove: bool,
+    // Install files another installed package owns
+    #[serde(default)]
+    pub overwrite: bool,
+    // Install another ABI's build when there is none for the device
+    #[serde(default)]
+    pub arch: bool,
+    // Let upgrade go back to the repo's version of newer installed packages
+    #[serde(default)]
+    pub downgrade: bool,
+}
+
+static FORCE: OnceLock<ForceOptions> = OnceLock::new();
+static INDEX_PINS: OnceLock<Vec<String>> = OnceLock::new();
+static IGNORE: OnceLock<Vec<String>> = OnceLock::new();
+
+// Set once from the flags and the config before any command runs
+pub fn set_force(force: ForceOptions) {
+    let _ = FORCE.set(force);
+}
+
+pub fn force() -> ForceOptions {
+    FORCE.get().copied().unwrap_or_default()
+}
+
+pub fn set_ignore(ignore: Vec<String>) {
+    let _ = IGNORE.set(ignore);
+}
+
+pub fn ignored(name: &str) -> bool {
+    IGNORE
+        .get()
+        .into_iter()
+        .flatten()
+        .any(|pattern| glob::matches(pattern, name))
+}
+
+// From --index-hash, "NAME=SHA256" or a bare hash when only one repo is set up
+pub fn set_index_pins(pins: Vec<String>) {
+    let _ = INDEX_PINS.set(pins);
+}
+
+// The flag overrides the repo's own index_sha256
+pub fn index_pin(repo: &RepoConfig, repo_count: usize) -> Result<Option<String>, PieError> {
+    for pin in INDEX_PINS.get().into_iter().flatten() {
+        match pin.split_once('=') {
+            Some((name, hash)) if name == repo.name => return Ok(Some(hash.to_lowercase())),
+            Some(_) => {}
+            None if repo_count == 1 => return Ok(Some(pin.to_lowercase())),
+            None => {
+                return Err(PieError::Other(format!(
+                    "--index-hash {pin} is ambiguous with {repo_count} repos, use NAME=SHA256"
+                )))
+            }
+        }
+    }
+    Ok(repo.index_sha256.as_ref().map(|hash| hash.to_lowercase()))
+}
+
+// With an allow list only those licenses pass, the deny list always wins
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct LicensePolicy {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+// What `pie upgrade --unattended` may do when run from cron or service.d
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct UnattendedPolicy {
+    // Repos new versions may come from, every configured repo if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repos: Vec<String>,
+    // Held packages, shell-style patterns allowed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub security_only: bool,
+    #[serde(default)]
+    pub allow_metered: bool,
+    // Local time range like "02:00-05:00", runs outside it do nothing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            repos: default_repos(),
+            install_mode: InstallMode::default(),
+            assume_yes: false,
+            licenses: LicensePolicy::default(),
+            script_timeout: DEFAULT_SCRIPT_TIMEOUT,
+            logcat: false,
+            ascii: false,
+            cache_versions: DEFAULT_KEEP_VERSIONS,
+            cache_max_size: default_cache_max_size(),
+            unattended: UnattendedPolicy::default(),
+            ipfs_gateway: default_ipfs_gateway(),
+            telemetry_endpoint: None,
+            force: ForceOptions::default(),
+            pins: BTreeMap::new(),
+            ignore: Vec::new(),
+        }
+    }
+}
+
+fn default_repos() -> Vec<RepoConfig> {
+    vec![RepoConfig {
+        name: "main".to_string(),
+        url: REPO_URL.to_string(),
+        username: None,
+        password: None,
+        token: None,
+        index_sha256: None,
+        priority: 0,
+    }]
+}
+
+pub fn load_config() -> Result<Config, PieError> {
+    let config_file = format!("{PIE_DATA}/config.json");
+
+    let mut config = if Path::new(&config_file).exists() {
+        let content = fs::read_to_string(&config_file)?;
+        serde_json::from_str::<Config>(&content)
+            .map_err(|e| PieError::Schema(format!("Invalid config file '{config_file}': {e}")))?
+    } else {
+        Config::default()
+    };
+
+    // PIE_REPO points pie at another index, e.g. a local repo on the sdcard
+    if let Ok(url) = std::env::var("PIE_REPO") {
+        config.repos = vec![RepoConfig {
+            name: "main".to_string(),
+            url,
+            username: None,
+            password: None,
+            token: None,
+            index_sha256: None,
+            priority: 0,
+        }];
+    }
+
+    Ok(config)
+}
+
+pub fn overlay_default() -> Result<bool, PieError> {
+    Ok(load_config()?.install_mode == InstallMode::Overlay)
+}
+
+pub fn save_config(config: &Config) -> Result<(), PieError> {
+    fs::create_dir_all(&PIE_DATA)?;
+    let config_file = format!("{PIE_DATA}/config.json");
+    let temp_file = format!("{config_file}.tmp");
+    let content = serde_json::to_string_pretty(config)?;
+
+    // Repo credentials may be in it, so only the owner can read it, like the
+    // secrets file
+    let _ = fs::remove_file(&temp_file);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temp_file)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_file, &config_file)?;
+    Ok(())
+}
+
+impl RepoConfig {
+    // Credentials from the config take precedence over the secrets file, and
+    // a token over a username/password pair
+    pub fn auth(&self) -> Result<Option<Auth>, PieError> {
+        if let Some(token) = &self.token {
+            return Ok(Some(Auth::Bearer(token.clone())));
+        }
+
+        if let Some(username) = &self.username {
+            return Ok(Some(Auth::Basic {
+                username: username.clone(),
+                password: self.password.clone(),
+            }));
+        }
+
+        host_auth(&self.url)
+    }
+}
+
+// Repo credentials only go to the host serving its index. Absolute urls
+// elsewhere in it get what the secrets file has for their own host
+pub fn url_auth(
+    index_url: &str,
+    repo_auth: Option<&Auth>,
+    url: &str,
+) -> Result<Option<Auth>, PieError> {
+    if net::host(url) == net::host(index_url) {
+        return Ok(repo_auth.cloned());
+    }
+    host_auth(url)
+}
+
+// Credentials for a url from the secrets file alone
+pub fn host_auth(url: &str) -> Result<Option<Auth>, PieError> {
+    let Some(host) = net::host(url) else {
+        return Ok(None);
+    };
+
+    match lookup_secret(host)? {
+        Some(Secret {
+            token: Some(token), ..
+        }) => Ok(Some(Auth::Bearer(token))),
+        Some(Secret {
+            login: Some(username),
+            password,
+            ..
+        }) => Ok(Some(Auth::Basic { username, password })),
+        _ => Ok(None),
+    }
+}
+
+#[derive(Default)]
+struct Secret {
+    login: Option<String>,
+    password: Option<String>,
+    token: Option<String>,
+}
+
+// netrc-style: "machine <host> login <user> password <pass>", with an optional
+// trailing "default" entry. "token <value>" is accepted for bearer auth
+fn lookup_secret(host: &str) -> Result<Option<Secret>, PieError> {
+    let secrets_file = format!("{PIE_DATA}/secrets");
+
+    if !Path::new(&secrets_file).exists() {
+        return Ok(None);
+    }
+
+    let mode = fs::metadata(&secrets_file)?.permissions().mode();
+    if mode & 0o077 != 0 {
+        return Err(format!(
+            "Refusing to read '{secrets_file}': permissions {:o} are too open (run chmod 600)",
+            mode & 0o777
+        )
+        .into());
+    }
+
+    let content = fs::read_to_string(&secrets_file)?;
+    let mut tokens = content.split_whitespace();
+    let mut found: Option<Secret> = None;
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => {
+                if found.is_some() {
+                    break;
+                }
+                if tokens.next() == Some(host) {
+                    found = Some(Secret::default());
+                }
+            }
+            "default" => {
+                if found.is_some() {
+                    break;
+                }
+                found = Some(Secret::default());
+            }
+            "login" | "password" | "token" => {
+                let value = tokens.next().map(str::to_string);
+                if let Some(secret) = found.as_mut() {
+                    match token {
+                        "login" => secret.login = value,
+                        "password" => secret.password = value,
+                        _ => secret.token = value,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(found)
+}
@@ -0,0 +1,273 @@
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+
+use crate::config;
+use crate::error::PieError;
+use crate::glyph::CHECK;
+use crate::{
+    explain, fetch_repo, get_installed_packages, install_packages, journal, order_transaction,
+    package_architecture, progress, query, resolve_dependencies, resolve_install_target, Commands,
+    PIE_DATA,
+};
+
+// `pie daemon` serves newline-delimited JSON-RPC 2.0 on a unix socket, so a
+// frontend app gets root once instead of spawning a root shell for every
+// operation. Methods:
+//   query      {"expression"}             the records `pie query --json` prints
+//   resolve    {"packages"}               the transaction installing them would run
+//   install    {"packages", "overlay"}    progress notifications, then the result
+//   subscribe                             progress of every transaction from now on
+pub fn socket_path() -> String {
+    format!("{PIE_DATA}/pie.sock")
+}
+
+// One transaction at a time, like separate pie processes would have to
+static TRANSACTION: Mutex<()> = Mutex::new(());
+
+enum Failure {
+    Parse(String),
+    Method(String),
+    Params(String),
+    Pie(PieError),
+}
+
+impl From<PieError> for Failure {
+    fn from(e: PieError) -> Self {
+        Failure::Pie(e)
+    }
+}
+
+impl Failure {
+    fn to_json(&self) -> Value {
+        match self {
+            Failure::Parse(message) => json!({ "code": -32700, "message": message }),
+            Failure::Method(method) => {
+                json!({ "code": -32601, "message": format!("Unknown method '{method}'") })
+            }
+            Failure::Params(message) => json!({ "code": -32602, "message": message }),
+            Failure::Pie(e) => json!({
+                "code": -32000,
+                "message": e.to_string(),
+                "data": { "kind": e.kind(), "exit_code": e.exit_code() },
+            }),
+        }
+    }
+}
+
+pub fn serve(socket: &str, allow_uid: Option<u32>) -> Result<(), PieError> {
+    if Path::new(socket).exists() {
+        if UnixStream::connect(socket).is_ok() {
+            return Err(format!("A pie daemon is already listening on {socket}").into());
+        }
+        // Left behind by a daemon that was killed
+        fs::remove_file(socket)?;
+    }
+    let listener =
+        UnixListener::bind(socket).map_err(|e| format!("Failed to listen on {socket}: {e}"))?;
+    // Installs run as whoever started the daemon, so only root and the
+    // frontend's uid may connect
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+    if let Some(uid) = allow_uid {
+        std::os::unix::fs::chown(socket, Some(uid), None)?;
+    }
+    println!("{CHECK} Listening on {socket}");
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Warning: failed to accept a connection: {e}");
+                continue;
+            }
+        };
+        thread::spawn(move || {
+            if let Err(e) = handle(stream) {
+                eprintln!("Warning: connection ended with an error: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(stream: UnixStream) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream.try_clone()?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, result) = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => {
+                let id = request.get("id").cloned().unwrap_or(Value::Null);
+                let method = request["method"].as_str().unwrap_or_default();
+                (id, call(method, &request["params"], &stream))
+            }
+            Err(e) => (Value::Null, Err(Failure::Parse(e.to_string()))),
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(failure) => json!({ "jsonrpc": "2.0", "id": id, "error": failure.to_json() }),
+        };
+        writeln!(writer, "{response}")?;
+    }
+    Ok(())
+}
+
+fn call(method: &str, params: &Value, stream: &UnixStream) -> Result<Value, Failure> {
+    match method {
+        "query" => {
+            let expression = params["expression"]
+                .as_str()
+                .ok_or_else(|| Failure::Params("expression must be a string".to_string()))?;
+            Ok(json!(query::query(expression)?))
+        }
+        "resolve" => Ok(resolve(&package_names(params)?)?),
+        "install" => {
+            let names = package_names(params)?;
+            let overlay =
+                params["overlay"].as_bool().unwrap_or(false) || config::overlay_default()?;
+            let _transaction = TRANSACTION.lock().unwrap_or_else(|e| e.into_inner());
+            journal::recover()?;
+            let subscription = progress::subscribe(stream.try_clone().map_err(PieError::from)?);
+            let result = install_packages(&names, overlay);
+            progress::unsubscribe(subscription);
+            result?;
+            Ok(json!(true))
+        }
+        "subscribe" => {
+            progress::subscribe(stream.try_clone().map_err(PieError::from)?);
+            Ok(json!(true))
+        }
+        _ => Err(Failure::Method(method.to_string())),
+    }
+}
+
+fn package_names(params: &Value) -> Result<Vec<String>, Failure> {
+    let names: Option<Vec<String>> = params["packages"].as_array().map(|packages| {
+        packages
+            .iter()
+            .filter_map(|name| name.as_str().map(str::to_string))
+            .collect()
+    });
+    match names {
+        Some(names) if !names.is_empty() => Ok(names),
+        _ => Err(Failure::Params(
+            "packages must be a list of package names".to_string(),
+        )),
+    }
+}
+
+// What `install` would do, in transaction order, without changing anything
+fn resolve(names: &[String]) -> Result<Value, PieError> {
+    let repo = fetch_repo()?;
+    let installed = get_installed_packages()?;
+
+    let mut targets: Vec<String> = Vec::new();
+    for name in names {
+        if let Some(target) = resolve_install_target(&repo, name)? {
+            if !installed.packages.contains_key(&target) && !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    explain::check(&repo, &targets, &installed, &[])?;
+
+    let mut transaction: Vec<String> = Vec::new();
+    for target in &targets {
+        for dep in resolve_dependencies(&repo, target, &installed)? {
+            if !transaction.contains(&dep) && !targets.contains(&dep) {
+                transaction.push(dep);
+            }
+        }
+    }
+    transaction.extend(targets.iter().cloned());
+
+    let mut packages = Vec::new();
+    for name in order_transaction(&repo, transaction)? {
+        let (package, architecture) = package_architecture(&repo, &name)?;
+        packages.push(json!({
+            "name": name,
+            "version": package.version,
+            "size": architecture.size,
+            "installed_size": architecture.uncompressed_size,
+            "explicit": targets.contains(&name),
+        }));
+    }
+    Ok(json!(packages))
+}
+
+// Commands the CLI can hand to a daemon instead of running them itself
+pub fn client(socket: &str, command: Commands) -> Result<(), PieError> {
+    match command {
+        Commands::Install {
+            packages,
+            overlay,
+            url: None,
+            file: None,
+            archives: None,
+            ..
+        } => {
+            request(
+                socket,
+                "install",
+                json!({ "packages": packages, "overlay": overlay }),
+            )?;
+            Ok(())
+        }
+        Commands::Query { expression, json } => {
+            let result = request(socket, "query", json!({ "expression": expression }))?;
+            let matched = result.as_array().cloned().unwrap_or_default();
+            query::print_matches(&matched, json)
+        }
+        _ => Err("Only install and query can go through the daemon".into()),
+    }
+}
+
+// The CLI side: sends one request and prints the progress notifications that
+// arrive before the response
+pub fn request(socket: &str, method: &str, params: Value) -> Result<Value, PieError> {
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| format!("Failed to connect to the pie daemon at {socket}: {e}"))?;
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    writeln!(stream, "{request}")?;
+
+    for line in BufReader::new(stream).lines() {
+        let message: Value = serde_json::from_str(&line?)?;
+        if message["method"] == "progress" {
+            print_event(&message["params"]);
+            continue;
+        }
+        if let Some(error) = message.get("error") {
+            let message = error["message"].as_str().unwrap_or_default().to_string();
+            return Err(match error["data"]["kind"].as_str() {
+                Some(kind) => PieError::from_kind(kind, message),
+                None => PieError::Other(message),
+            });
+        }
+        return Ok(message["result"].clone());
+    }
+    Err(PieError::Network(
+        "The pie daemon closed the connection".to_string(),
+    ))
+}
+
+fn print_event(event: &Value) {
+    let text = |field: &str| event[field].as_str().unwrap_or_default().to_string();
+    match event["event"].as_str() {
+        Some("package") => println!(
+            "[{}/{}] {} {}",
+            event["index"],
+            event["total"],
+            text("action"),
+            text("package")
+        ),
+        Some("done") => println!("{CHECK} {} finished", text("action")),
+        _ => {}
+    }
+}
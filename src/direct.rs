@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::config;
+use crate::error::PieError;
+use crate::glyph::{CHECK, CROSS};
+use crate::{
+    cache, extract, get_installed_packages, get_separator, hash_contents, hooks, logcat, net,
+    overlay, package_root, prompt, save_installed_packages, signal, unix_now, InstalledPackage,
+};
+
+// Installs an artifact that isn't published in any repo yet. The pinned hash
+// stands in for the index entry and the file list is read from the archive,
+// so the result is tracked, verified and removed like any other package
+pub fn install_url(
+    url: &str,
+    sha256: &str,
+    name: &str,
+    version: &str,
+    overlay: bool,
+) -> Result<(), PieError> {
+    let sha256 = sha256.to_lowercase();
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid SHA-256 '{sha256}'").into());
+    }
+
+    if config::ignored(name) {
+        return Err(PieError::Resolution(format!(
+            "'{name}' is blocked by the ignore list in the config"
+        )));
+    }
+
+    let mut installed = get_installed_packages()?;
+
+    println!("\n{}", get_separator());
+    println!("DIRECT INSTALL");
+    println!("{}", get_separator());
+    println!("Package: {name} v{version}");
+    println!("Source: {url}");
+    println!("SHA-256: {sha256}");
+    if let Some(previous) = installed.packages.get(name) {
+        println!("Replaces installed {name} v{}", previous.version);
+    }
+    println!("\nDependencies of artifacts outside a repo are not resolved.");
+
+    if !prompt::confirm("\nProceed with installation?", true)? {
+        println!("Installation cancelled");
+        return Ok(());
+    }
+
+    let hook_packages = vec![hooks::HookPackage {
+        name: name.to_string(),
+        version: version.to_string(),
+    }];
+    hooks::run_hooks("pre-install", &hook_packages)?;
+
+    let root = package_root(overlay);
+    if overlay {
+        overlay::ensure_module()?;
+    }
+    fs::create_dir_all(&root)?;
+
+    let _critical = signal::critical();
+    print!("Downloading and extracting {name}... ");
+    io::stdout().flush()?;
+    let (reader, pending) = cache::tee_archive(name, net::open_unsized(url)?);
+    let extracted = match extract::extract_verified(name, reader, None, Path::new(&root), &sha256) {
+        Ok(extracted) => extracted,
+        Err(e) => {
+            println!("{CROSS}");
+            return Err(e);
+        }
+    };
+    println!("{CHECK}");
+    let contents = extracted.contents;
+
+    // Files the previously installed build shipped and this one doesn't
+    if let Some(previous) = installed.packages.remove(name) {
+        let previous_root = package_root(previous.overlay);
+        for file in &previous.contents {
+            if previous.overlay == overlay && contents.contains(file) {
+                continue;
+            }
+            let path = format!("{previous_root}/{file}");
+            if Path::new(&path).exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+
+    let size = contents
+        .iter()
+        .filter_map(|file| fs::symlink_metadata(Path::new(&root).join(file)).ok())
+        .map(|meta| meta.len())
+        .sum();
+    let installed_package = InstalledPackage {
+        name: name.to_string(),
+        version: version.to_string(),
+        description: None,
+        hashes: hash_contents(&root, &contents),
+        contents,
+        services: HashMap::new(),
+        overlay,
+        explicit: true,
+        repo: url.to_string(),
+        size,
+        dependencies: Vec::new(),
+        conflicts: Vec::new(),
+        alternatives: Vec::new(),
+        scripts: HashMap::new(),
+        boot_scripts: Vec::new(),
+        xattrs: extracted.xattrs,
+        hardlinks: extracted.hardlinks,
+        opt: false,
+        abi: None,
+    };
+    if let Some(pending) = pending {
+        cache::store_archive(
+            pending,
+            cache::CachedVersion {
+                sha256: sha256.clone(),
+                dictionary: None,
+                boot_scripts: Vec::new(),
+                cached_at: unix_now(),
+                package: installed_package.clone(),
+            },
+        );
+    }
+    installed
+        .packages
+        .insert(name.to_string(), installed_package);
+    save_installed_packages(&installed)?;
+    println!("Successfully installed {name} v{version}");
+    logcat::info(&format!("Installed {name} v{version} from {url}"));
+
+    hooks::run_hooks("post-install", &hook_packages)?;
+    Ok(())
+}
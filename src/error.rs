@@ -0,0 +1,149 @@
+use clap::ValueEnum;
+use serde_json::json;
+use std::io;
+use thiserror::Error;
+
+use crate::{logcat, signal};
+
+#[derive(Debug, Error)]
+pub enum PieError {
+    #[error("{0}")]
+    Network(String),
+    #[error("Checksum verification failed for {0}")]
+    Checksum(String),
+    #[error("{0}")]
+    Resolution(String),
+    #[error(transparent)]
+    Io(io::Error),
+    #[error("{0}")]
+    Schema(String),
+    #[error("{0}")]
+    Cancelled(String),
+    #[error("Interrupted")]
+    Interrupted,
+    #[error("{0}")]
+    Other(String),
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ErrorFormat {
+    Text,
+    Json,
+}
+
+impl PieError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PieError::Network(_) => "network",
+            PieError::Checksum(_) => "checksum",
+            PieError::Resolution(_) => "resolution",
+            PieError::Io(_) => "io",
+            PieError::Schema(_) => "schema",
+            PieError::Cancelled(_) => "cancelled",
+            PieError::Interrupted => "interrupted",
+            PieError::Other(_) => "other",
+        }
+    }
+
+    // Rebuilds an error `pie daemon` reported, so a client exits with the
+    // code the command would have had when run directly
+    pub fn from_kind(kind: &str, message: String) -> PieError {
+        match kind {
+            "network" => PieError::Network(message),
+            "checksum" => match message.strip_prefix("Checksum verification failed for ") {
+                Some(what) => PieError::Checksum(what.to_string()),
+                None => PieError::Other(message),
+            },
+            "resolution" => PieError::Resolution(message),
+            "io" => PieError::Io(io::Error::other(message)),
+            "schema" => PieError::Schema(message),
+            "cancelled" => PieError::Cancelled(message),
+            "interrupted" => PieError::Interrupted,
+            _ => PieError::Other(message),
+        }
+    }
+
+    // 2 is left to clap for usage errors
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PieError::Other(_) => 1,
+            PieError::Network(_) => 3,
+            PieError::Checksum(_) => 4,
+            PieError::Resolution(_) => 5,
+            PieError::Io(_) => 6,
+            PieError::Schema(_) => 7,
+            PieError::Cancelled(_) => 8,
+            PieError::Interrupted => signal::EXIT_INTERRUPTED,
+        }
+    }
+
+    pub fn report(&self, format: ErrorFormat) {
+        logcat::error(&self.to_string());
+        match format {
+            ErrorFormat::Text => eprintln!("Error: {self}"),
+            ErrorFormat::Json => eprintln!(
+                "{}",
+                json!({
+                    "error": {
+                        "kind": self.kind(),
+                        "code": self.exit_code(),
+                        "message": self.to_string(),
+                    }
+                })
+            ),
+        }
+    }
+}
+
+// Reads abort with ErrorKind::Interrupted once a signal arrived
+impl From<io::Error> for PieError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::Interrupted && signal::interrupted() {
+            PieError::Interrupted
+        } else {
+            PieError::Io(e)
+        }
+    }
+}
+
+impl From<reqwest::Error> for PieError {
+    fn from(e: reqwest::Error) -> Self {
+        PieError::Network(e.to_string())
+    }
+}
+
+impl From<zip::result::ZipError> for PieError {
+    fn from(e: zip::result::ZipError) -> Self {
+        PieError::Other(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for PieError {
+    fn from(e: serde_json::Error) -> Self {
+        PieError::Schema(e.to_string())
+    }
+}
+
+impl From<std::string::FromUtf8Error> for PieError {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        PieError::Other(e.to_string())
+    }
+}
+
+impl From<std::num::ParseIntError> for PieError {
+    fn from(e: std::num::ParseIntError) -> Self {
+        PieError::Other(e.to_string())
+    }
+}
+
+impl From<String> for PieError {
+    fn from(message: String) -> Self {
+        PieError::Other(message)
+    }
+}
+
+impl From<&str> for PieError {
+    fn from(message: &str) -> Self {
+        PieError::Other(message.to_string())
+    }
+}
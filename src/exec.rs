@@ -0,0 +1,117 @@
+use std::fs;
+use std::io::{Read, Seek, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::error::PieError;
+use crate::{format_timestamp, logcat, unix_now, PIE_DATA};
+
+pub const DEFAULT_SCRIPT_TIMEOUT: u64 = 300;
+const LOG_FILE: &str = "transaction.log";
+
+// Runs a hook or maintainer script in its own process group with stdout and
+// stderr going to the transaction log instead of the terminal. A script still
+// running after the configured timeout is killed together with its children
+pub fn run_script(
+    mut command: Command,
+    label: &str,
+    stdin: Option<&[u8]>,
+) -> Result<bool, PieError> {
+    let timeout = config::load_config()
+        .map(|config| config.script_timeout)
+        .unwrap_or(DEFAULT_SCRIPT_TIMEOUT);
+
+    let mut output = tempfile::tempfile()?;
+    let mut child = command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(output.try_clone()?)
+        .stderr(output.try_clone()?)
+        .process_group(0)
+        .spawn()?;
+
+    // Written from a thread, a script that never reads a payload larger than
+    // the pipe buffer would otherwise block pie before the timeout applies.
+    // Scripts are free to ignore stdin, so a broken pipe is not an error, and
+    // the pipe is closed once everything is written
+    if let (Some(payload), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        let payload = payload.to_vec();
+        thread::spawn(move || {
+            let _ = pipe.write_all(&payload);
+        });
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if Instant::now() >= deadline {
+            kill_group(child.id(), "KILL");
+            child.wait()?;
+            break None;
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    let mut captured = Vec::new();
+    output.rewind()?;
+    output.read_to_end(&mut captured)?;
+    append_log(label, status, timeout, &captured)?;
+
+    match status {
+        Some(status) if status.success() => Ok(true),
+        Some(status) => {
+            logcat::warn(&format!("{label} failed ({status})"));
+            eprintln!("Output of {label} is in {PIE_DATA}/{LOG_FILE}");
+            Ok(false)
+        }
+        None => {
+            logcat::warn(&format!("{label} timed out after {timeout} seconds"));
+            eprintln!("{label} timed out after {timeout} seconds and was killed");
+            Ok(false)
+        }
+    }
+}
+
+pub fn kill_group(pid: u32, signal: &str) {
+    let _ = Command::new("kill")
+        .args([&format!("-{signal}"), "--", &format!("-{pid}")])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+fn append_log(
+    label: &str,
+    status: Option<ExitStatus>,
+    timeout: u64,
+    output: &[u8],
+) -> Result<(), PieError> {
+    fs::create_dir_all(&PIE_DATA)?;
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(format!("{PIE_DATA}/{LOG_FILE}"))?;
+
+    let result = match status {
+        Some(status) => status.to_string(),
+        None => format!("killed after {timeout}s timeout"),
+    };
+    writeln!(
+        log,
+        "=== {} {label} ({result})",
+        format_timestamp(unix_now())
+    )?;
+    log.write_all(output)?;
+    if !output.is_empty() && !output.ends_with(b"\n") {
+        writeln!(log)?;
+    }
+    Ok(())
+}
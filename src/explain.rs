@@ -0,0 +1,265 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::config;
+use crate::error::PieError;
+use crate::glyph::{ARROW, BACK_ARROW, CROSS};
+use crate::version;
+use crate::{
+    compat_abi, foreign_architecture, get_api_level, get_arch, get_separator, InstalledPackages,
+    Repo,
+};
+
+enum Reason {
+    Missing,
+    Ignored,
+    NoBuild,
+    Api(u32),
+    InvalidApi(String),
+    Pinned(String, String),
+    Conflicts(String),
+}
+
+// Why a package needed by the transaction can't be installed, with the chain
+// of requirements that pulled it in, requested package last
+struct Problem {
+    chain: Vec<String>,
+    reason: Reason,
+}
+
+// Walks the whole dependency graph of the requested packages before anything
+// is resolved, so every unsatisfiable requirement is reported at once along
+// with what asked for it. Installed dependencies aren't followed, like the
+// resolver doesn't
+pub fn check(
+    repo: &Repo,
+    targets: &[String],
+    installed: &InstalledPackages,
+    pins: &[(String, String)],
+) -> Result<(), PieError> {
+    let arch = get_arch()?;
+    let device_api = get_api_level()?;
+
+    // Breadth first, so the chain recorded for each package is the shortest
+    let mut chains: HashMap<String, Vec<String>> = HashMap::new();
+    let mut order = Vec::new();
+    let mut queue: VecDeque<(String, Vec<String>)> = targets
+        .iter()
+        .map(|name| (name.clone(), vec![name.clone()]))
+        .collect();
+    while let Some((name, chain)) = queue.pop_front() {
+        if chains.contains_key(&name) {
+            continue;
+        }
+        if let Some(package) = repo.packages.get(&name) {
+            for dep in package.dependencies.iter().chain(&package.pre_depends) {
+                if !installed.packages.contains_key(dep) && !chains.contains_key(dep) {
+                    let mut dep_chain = vec![dep.clone()];
+                    dep_chain.extend(chain.iter().cloned());
+                    queue.push_back((dep.clone(), dep_chain));
+                }
+            }
+        }
+        order.push(name.clone());
+        chains.insert(name, chain);
+    }
+
+    let mut problems = Vec::new();
+    for name in &order {
+        let chain = &chains[name];
+        if config::ignored(name) {
+            problems.push(Problem {
+                chain: chain.clone(),
+                reason: Reason::Ignored,
+            });
+            continue;
+        }
+        let Some(package) = repo.packages.get(name) else {
+            problems.push(Problem {
+                chain: chain.clone(),
+                reason: Reason::Missing,
+            });
+            continue;
+        };
+
+        if !package.architectures.contains_key(&arch)
+            && !(config::force().arch && foreign_architecture(package, &arch).is_some())
+        {
+            problems.push(Problem {
+                chain: chain.clone(),
+                reason: Reason::NoBuild,
+            });
+        }
+
+        if let Some(reason) = api_problem(package.min_api.as_deref(), device_api) {
+            problems.push(Problem {
+                chain: chain.clone(),
+                reason,
+            });
+        }
+
+        if let Some((_, range)) = pins.iter().find(|(pinned, _)| pinned == name) {
+            if !version::matches_range(&package.version, range) {
+                problems.push(Problem {
+                    chain: chain.clone(),
+                    reason: Reason::Pinned(range.clone(), package.version.clone()),
+                });
+            }
+        }
+
+        for conflict in &package.conflicts {
+            if chains.contains_key(conflict) {
+                problems.push(Problem {
+                    chain: chain.clone(),
+                    reason: Reason::Conflicts(conflict.clone()),
+                });
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    println!("\n{}", get_separator());
+    println!("UNSATISFIABLE REQUIREMENTS");
+    println!("{}", get_separator());
+    for problem in &problems {
+        println!("{}", render_chain(&problem.chain));
+        let reason = match &problem.reason {
+            Reason::Missing => "not found in any configured repo".to_string(),
+            Reason::Ignored => "blocked by the ignore list in the config".to_string(),
+            Reason::NoBuild => format!("no build for {arch}"),
+            Reason::Api(min_api) => {
+                format!("requires API level {min_api}, the device is API level {device_api}")
+            }
+            Reason::InvalidApi(min_api) => format!("invalid min_api format: '{min_api}'"),
+            Reason::Pinned(pinned, available) => {
+                format!("pinned to v{pinned}, but the repo has v{available}")
+            }
+            Reason::Conflicts(other) => {
+                format!("conflicts with {}", render_chain(&chains[other]))
+            }
+        };
+        println!("  {CROSS} {reason}");
+        if let Reason::NoBuild = problem.reason {
+            for suggestion in no_build(repo, &problem.chain[0], &arch) {
+                println!("    {ARROW} {suggestion}");
+            }
+        }
+    }
+    println!("{}", get_separator());
+
+    Err(PieError::Resolution(format!(
+        "{} unsatisfiable requirement{}",
+        problems.len(),
+        if problems.len() == 1 { "" } else { "s" }
+    )))
+}
+
+// A min_api that doesn't parse is reported rather than skipped, the package
+// would otherwise install on any device
+fn api_problem(min_api: Option<&str>, device_api: u32) -> Option<Reason> {
+    let min_api = min_api
+        .map(str::trim)
+        .filter(|min_api| !min_api.is_empty())?;
+    match min_api.parse::<u32>() {
+        Ok(required) if device_api < required => Some(Reason::Api(required)),
+        Ok(_) => None,
+        Err(_) => Some(Reason::InvalidApi(min_api.to_string())),
+    }
+}
+
+// What is left when a package has no build for the device ABI: the ABIs it
+// does have, whether one of them runs here, and packages shipping the same
+// binaries for this ABI
+pub fn no_build(repo: &Repo, name: &str, arch: &str) -> Vec<String> {
+    let Some(package) = repo.packages.get(name) else {
+        return Vec::new();
+    };
+    let mut abis: Vec<&String> = package.architectures.keys().collect();
+    abis.sort();
+    if abis.is_empty() {
+        return vec!["it has no builds for any architecture".to_string()];
+    }
+
+    let mut suggestions = vec![format!(
+        "available for {}",
+        abis.iter()
+            .map(|abi| abi.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )];
+    match compat_abi(arch).filter(|abi| package.architectures.contains_key(*abi)) {
+        Some(abi) => suggestions.push(format!(
+            "the {abi} build runs on this device in compat mode, install it with --force-arch"
+        )),
+        None => suggestions.push(format!(
+            "none of them runs on {arch}, --force-arch installs {} anyway",
+            abis[0]
+        )),
+    }
+
+    let binaries: HashSet<&str> = package
+        .architectures
+        .values()
+        .flat_map(|architecture| &architecture.contents)
+        .filter(|file| file.starts_with("bin/"))
+        .map(String::as_str)
+        .collect();
+    if binaries.is_empty() {
+        return suggestions;
+    }
+    let mut providers: Vec<(&String, Vec<&str>)> = repo
+        .packages
+        .iter()
+        .filter(|(other, _)| *other != name)
+        .filter_map(|(other, candidate)| {
+            let architecture = candidate.architectures.get(arch)?;
+            let mut shared: Vec<&str> = architecture
+                .contents
+                .iter()
+                .map(String::as_str)
+                .filter(|file| binaries.contains(file))
+                .collect();
+            shared.sort();
+            (!shared.is_empty()).then_some((other, shared))
+        })
+        .collect();
+    providers.sort();
+    for (other, shared) in providers {
+        suggestions.push(format!("{other} provides {} for {arch}", shared.join(", ")));
+    }
+    suggestions
+}
+
+fn render_chain(chain: &[String]) -> String {
+    let mut rendered = chain[0].clone();
+    for pkg in &chain[1..] {
+        rendered.push_str(&format!(" {BACK_ARROW} required by {pkg}"));
+    }
+    rendered.push_str(&format!(" {BACK_ARROW} requested"));
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_min_api_is_rejected() {
+        for min_api in ["21a", "Q", "-1", "21.0"] {
+            match api_problem(Some(min_api), 34) {
+                Some(Reason::InvalidApi(value)) => assert_eq!(value, min_api),
+                _ => panic!("min_api '{min_api}' was accepted"),
+            }
+        }
+    }
+
+    #[test]
+    fn min_api_against_the_device() {
+        assert!(api_problem(None, 21).is_none());
+        assert!(api_problem(Some(""), 21).is_none());
+        assert!(api_problem(Some(" 24 "), 24).is_none());
+        assert!(matches!(api_problem(Some("26"), 24), Some(Reason::Api(26))));
+    }
+}
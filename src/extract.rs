@@ -0,0 +1,252 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+use tar::{Archive, Entry};
+use zstd::stream::read::Decoder;
+
+use crate::error::PieError;
+use crate::signal;
+
+// Hashes everything read through it, so the artifact is verified in the same
+// pass that extracts it
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        signal::check()?;
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+// Decompressed data handed to the unpacking thread, in chunks of this size
+// with at most PIPELINE_DEPTH of them in flight
+const CHUNK_SIZE: usize = 256 * 1024;
+const PIPELINE_DEPTH: usize = 16;
+
+struct ChannelReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+pub struct Extracted {
+    pub contents: Vec<String>,
+    // Extended attributes by file, e.g. security.capability on ping
+    pub xattrs: HashMap<String, HashMap<String, String>>,
+    // Hardlink entries and the earlier name whose data they share
+    pub hardlinks: HashMap<String, String>,
+}
+
+// Extracts into a staging directory inside `root` and only moves the files
+// into place once the hash matches, so a corrupt download leaves no trace
+pub const STAGING_PREFIX: &str = ".pie-staging-";
+
+pub fn extract_verified(
+    name: &str,
+    reader: Box<dyn Read>,
+    dictionary: Option<&[u8]>,
+    root: &Path,
+    sha256: &str,
+) -> Result<Extracted, PieError> {
+    let staging = tempfile::Builder::new()
+        .prefix(STAGING_PREFIX)
+        .tempdir_in(root)?;
+
+    let reader = BufReader::new(HashingReader {
+        inner: reader,
+        hasher: Sha256::new(),
+    });
+    let mut decoder = match dictionary {
+        Some(dictionary) => Decoder::with_dictionary(reader, dictionary)?,
+        None => Decoder::with_buffer(reader)?,
+    };
+    // Archives built with --long use windows beyond the default decoder limit
+    decoder.window_log_max(31)?;
+
+    // The download and decompression run on this thread while another one
+    // writes the files, so neither waits for the other
+    let (sender, receiver) = mpsc::sync_channel(PIPELINE_DEPTH);
+    let staging_path = staging.path().to_path_buf();
+    let unpacker = thread::spawn(move || {
+        unpack(
+            ChannelReader {
+                receiver,
+                chunk: Vec::new(),
+                pos: 0,
+            },
+            &staging_path,
+        )
+    });
+
+    let decompressed = feed(&mut decoder, &sender);
+    drop(sender);
+    // Joined before anything is returned, the staging directory must
+    // outlive the thread writing into it
+    let unpacked = unpacker
+        .join()
+        .map_err(|_| PieError::Other(format!("Extracting {name} panicked")))?;
+    decompressed?;
+    let mut extracted = unpacked?;
+
+    // The tar end marker can come before the end of the stream, the rest
+    // still has to go through the hasher
+    let mut reader = decoder.finish();
+    io::copy(&mut reader, &mut io::sink())?;
+    let hash = hex::encode(reader.into_inner().hasher.finalize());
+
+    if hash != sha256 {
+        return Err(PieError::Checksum(name.to_string()));
+    }
+
+    list_tree(staging.path(), "", &mut extracted.contents)?;
+    extracted.contents.sort();
+
+    move_tree(staging.path(), root)?;
+    Ok(extracted)
+}
+
+// Stops early without an error when the unpacker is done, e.g. at the tar
+// end marker or after it failed
+fn feed<R: Read>(decoder: &mut R, sender: &SyncSender<Vec<u8>>) -> io::Result<()> {
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = decoder.read(&mut buf)?;
+        if n == 0 || sender.send(buf[..n].to_vec()).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+// Hardlinks are created inside the staging directory and survive the move
+// into place, so both names keep sharing their data
+fn unpack(reader: ChannelReader, staging: &Path) -> Result<Extracted, PieError> {
+    let mut archive = Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_unpack_xattrs(true);
+
+    let mut extracted = Extracted {
+        contents: Vec::new(),
+        xattrs: HashMap::new(),
+        hardlinks: HashMap::new(),
+    };
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry
+            .path()?
+            .to_string_lossy()
+            .trim_start_matches("./")
+            .to_string();
+        let attrs = entry_xattrs(&mut entry)?;
+        if !attrs.is_empty() {
+            extracted.xattrs.insert(path.clone(), attrs);
+        }
+        if entry.header().entry_type().is_hard_link() {
+            if let Some(target) = entry.link_name()? {
+                let target = target
+                    .to_string_lossy()
+                    .trim_start_matches("./")
+                    .to_string();
+                extracted.hardlinks.insert(path, target);
+            }
+        }
+        entry.unpack_in(staging)?;
+    }
+    Ok(extracted)
+}
+
+// Xattrs travel as SCHILY.xattr.* PAX records, values are kept hex encoded
+fn entry_xattrs<R: Read>(entry: &mut Entry<R>) -> io::Result<HashMap<String, String>> {
+    let mut attrs = HashMap::new();
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            if let Some(name) = extension
+                .key()
+                .ok()
+                .and_then(|key| key.strip_prefix("SCHILY.xattr."))
+            {
+                attrs.insert(name.to_string(), hex::encode(extension.value_bytes()));
+            }
+        }
+    }
+    Ok(attrs)
+}
+
+// Relative paths of everything but directories, in the same form as the
+// contents lists of the index
+fn list_tree(dir: &Path, prefix: &str, contents: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            list_tree(&entry.path(), &format!("{path}/"), contents)?;
+        } else {
+            contents.push(path);
+        }
+    }
+    Ok(())
+}
+
+pub fn move_tree(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            if fs::symlink_metadata(&target).is_ok_and(|meta| meta.is_dir()) {
+                move_tree(&entry.path(), &target)?;
+            } else {
+                fs::rename(entry.path(), &target)?;
+            }
+        } else {
+            fs::rename(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+// Staging directories are normally removed on drop, only a killed process
+// leaves them behind
+pub fn remove_staging(root: &Path) -> io::Result<()> {
+    if !root.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(STAGING_PREFIX)
+        {
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+// Fills {field} placeholders in a user supplied --format template. Unknown
+// fields are kept verbatim so typos are easy to spot; \t and \n are unescaped
+// since shells pass them through literally
+pub fn render(template: &str, fields: &[(&str, String)]) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'t') => {
+                chars.next();
+                out.push('\t');
+            }
+            '\\' if chars.peek() == Some(&'n') => {
+                chars.next();
+                out.push('\n');
+            }
+            '{' => {
+                let mut key = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    key.push(c);
+                }
+
+                match fields.iter().find(|(name, _)| *name == key) {
+                    Some((_, value)) if closed => out.push_str(value),
+                    _ => {
+                        out.push('{');
+                        out.push_str(&key);
+                        if closed {
+                            out.push('}');
+                        }
+                    }
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
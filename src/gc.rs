@@ -0,0 +1,200 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::error::PieError;
+use crate::glyph::{BULLET, CHECK};
+use crate::{extract, format_size, get_separator, journal, package_root, PIE_DATA};
+
+// Younger leftovers may belong to a pie that is still running, there is no
+// lock to ask
+const MIN_AGE: Duration = Duration::from_secs(60 * 60);
+// Transactions look for leftovers at most this often
+const AUTO_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+struct Leftover {
+    path: PathBuf,
+    kind: &'static str,
+    size: u64,
+}
+
+fn abandoned(meta: &fs::Metadata) -> bool {
+    meta.modified()
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age >= MIN_AGE)
+}
+
+fn tree_size(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| tree_size(&entry.path()))
+                .sum()
+        })
+        .unwrap_or_default()
+}
+
+// NamedTempFile names, left by downloads into the cache and index writes
+// that never got renamed into place
+fn temp_files(dir: &Path, found: &mut Vec<Leftover>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if meta.is_dir() {
+            temp_files(&entry.path(), found);
+        } else if entry.file_name().to_string_lossy().starts_with(".tmp") && abandoned(&meta) {
+            found.push(Leftover {
+                path: entry.path(),
+                kind: "partial download",
+                size: meta.len(),
+            });
+        }
+    }
+}
+
+fn find() -> Result<Vec<Leftover>, PieError> {
+    let mut found = Vec::new();
+
+    // Extraction and maintainer scripts work in staging directories inside
+    // the install roots
+    for overlay in [false, true] {
+        let Ok(entries) = fs::read_dir(package_root(overlay)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let is_staging = entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(extract::STAGING_PREFIX);
+            if is_staging && entry.metadata().is_ok_and(|meta| abandoned(&meta)) {
+                found.push(Leftover {
+                    size: tree_size(&entry.path()),
+                    path: entry.path(),
+                    kind: "staging directory",
+                });
+            }
+        }
+    }
+
+    temp_files(Path::new(&format!("{PIE_DATA}/cache")), &mut found);
+
+    // The database, journal and config are written to .tmp and renamed
+    for name in [
+        "installed.json.tmp",
+        "installed.json.bak.tmp",
+        "journal.json.tmp",
+        "config.json.tmp",
+    ] {
+        let path = Path::new(&*PIE_DATA).join(name);
+        if let Ok(meta) = fs::metadata(&path) {
+            if abandoned(&meta) {
+                found.push(Leftover {
+                    path,
+                    kind: "unfinished write",
+                    size: meta.len(),
+                });
+            }
+        }
+    }
+    if journal::completed()? {
+        found.push(Leftover {
+            path: Path::new(&*PIE_DATA).join("journal.json"),
+            kind: "journal of a completed transaction",
+            size: 0,
+        });
+    }
+
+    Ok(found)
+}
+
+fn remove(leftover: &Leftover) -> io::Result<()> {
+    if fs::symlink_metadata(&leftover.path)?.is_dir() {
+        fs::remove_dir_all(&leftover.path)
+    } else {
+        fs::remove_file(&leftover.path)
+    }
+}
+
+// Removes what killed or crashed runs left behind. The journal of an
+// unfinished transaction stays, `pie install` offers to resume it
+pub fn gc(dry_run: bool) -> Result<(), PieError> {
+    print!("Looking for leftovers of interrupted runs... ");
+    io::stdout().flush()?;
+    let found = find()?;
+    println!("{CHECK}");
+    if found.is_empty() {
+        println!("{CHECK} Nothing to clean up");
+        return Ok(());
+    }
+
+    println!("\n{}", get_separator());
+    println!("LEFTOVERS ({})", found.len());
+    println!("{}", get_separator());
+    for leftover in &found {
+        println!(
+            "  {BULLET} {} {} ({})",
+            leftover.kind,
+            leftover.path.display(),
+            format_size(leftover.size)
+        );
+    }
+    println!("{}", get_separator());
+
+    let total: u64 = found.iter().map(|leftover| leftover.size).sum();
+    if dry_run {
+        println!("Would free {}", format_size(total));
+        return Ok(());
+    }
+    for leftover in &found {
+        remove(leftover)?;
+    }
+    println!(
+        "Removed {} leftovers, freed {}",
+        found.len(),
+        format_size(total)
+    );
+    Ok(())
+}
+
+// Run before transactions. Best effort, a failure only warns
+pub fn auto() {
+    let stamp = format!("{PIE_DATA}/gc.stamp");
+    let due = fs::metadata(&stamp)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_none_or(|age| age >= AUTO_INTERVAL);
+    if !due {
+        return;
+    }
+
+    let cleaned = find().and_then(|found| {
+        let total: u64 = found.iter().map(|leftover| leftover.size).sum();
+        for leftover in &found {
+            remove(leftover)?;
+        }
+        fs::write(&stamp, "")?;
+        Ok((found.len(), total))
+    });
+    match cleaned {
+        Ok((0, _)) => {}
+        Ok((count, total)) => println!(
+            "Cleaned up {count} leftovers of interrupted runs ({})",
+            format_size(total)
+        ),
+        Err(e) => eprintln!("Warning: could not clean up after interrupted runs: {e}"),
+    }
+}
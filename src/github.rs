@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::error::PieError;
+use crate::net::{self, Auth};
+
+const API_URL: &str = "https://api.github.com";
+
+// An artifact attached to a GitHub release, used instead of `url` so
+// maintainers don't have to re-host release binaries
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReleaseAsset {
+    pub owner: String,
+    pub repo: String,
+    pub tag: String,
+    pub asset: String,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    url: String,
+    browser_download_url: String,
+}
+
+// GITHUB_TOKEN wins, then an api.github.com entry in the secrets file. Without
+// a token only public releases resolve, under a much lower rate limit
+pub fn auth() -> Result<Option<Auth>, PieError> {
+    if let Ok(token) = std::env::var("GITHUB_TOKEN") {
+        if !token.is_empty() {
+            return Ok(Some(Auth::Bearer(token)));
+        }
+    }
+
+    config::host_auth(API_URL)
+}
+
+// With a token the API asset url is used, it is the only one that works for
+// private repos. Public downloads go through the plain release url
+pub fn download_url(asset: &ReleaseAsset, auth: Option<&Auth>) -> Result<String, PieError> {
+    let release_url = format!(
+        "{API_URL}/repos/{}/{}/releases/tags/{}",
+        asset.owner, asset.repo, asset.tag
+    );
+    let content = net::fetch_bytes(&release_url, auth).map_err(|e| {
+        PieError::Network(format!(
+            "Failed to look up release {}/{}@{}: {e}",
+            asset.owner, asset.repo, asset.tag
+        ))
+    })?;
+    let release: Release = serde_json::from_slice(&content)
+        .map_err(|e| PieError::Schema(format!("Invalid release from {release_url}: {e}")))?;
+
+    let found = release
+        .assets
+        .into_iter()
+        .find(|a| a.name == asset.asset)
+        .ok_or_else(|| {
+            PieError::Resolution(format!(
+                "Release {}/{}@{} has no asset '{}'",
+                asset.owner, asset.repo, asset.tag, asset.asset
+            ))
+        })?;
+
+    Ok(match auth {
+        Some(_) => found.url,
+        None => found.browser_download_url,
+    })
+}
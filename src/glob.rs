@@ -0,0 +1,49 @@
+// Shell-style patterns for package names: * matches any run of characters,
+// ? a single character and [abc] or [a-z] one character from a set
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_at(&pattern, &name)
+}
+
+fn matches_at(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|i| matches_at(&pattern[1..], &name[i..])),
+        Some('?') => !name.is_empty() && matches_at(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let Some(end) = pattern.iter().position(|&c| c == ']') else {
+                return name.first() == Some(&'[') && matches_at(&pattern[1..], &name[1..]);
+            };
+            let Some(&c) = name.first() else {
+                return false;
+            };
+            class_matches(&pattern[1..end], c) && matches_at(&pattern[end + 1..], &name[1..])
+        }
+        Some(&p) => name.first() == Some(&p) && matches_at(&pattern[1..], &name[1..]),
+    }
+}
+
+fn class_matches(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!') | Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= (class[i]..=class[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
@@ -0,0 +1,50 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+// Set once from --ascii, the config or the locale before any command runs
+static ASCII: OnceLock<bool> = OnceLock::new();
+
+pub fn set_ascii(ascii: bool) {
+    let _ = ASCII.set(ascii || !utf8_terminal());
+}
+
+// Android itself sets no locale and its terminals are UTF-8, so only an
+// explicit non-UTF-8 locale or a dumb terminal falls back to ASCII
+fn utf8_terminal() -> bool {
+    if std::env::var("TERM").is_ok_and(|term| term == "dumb") {
+        return false;
+    }
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty());
+    match locale {
+        Some(locale) => {
+            let locale = locale.to_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        }
+        None => true,
+    }
+}
+
+// Formats as the Unicode symbol, or its ASCII stand-in in ASCII mode
+pub struct Glyph(&'static str, &'static str);
+
+pub static CHECK: Glyph = Glyph("✓", "[ok]");
+pub static CROSS: Glyph = Glyph("✗", "[x]");
+pub static BULLET: Glyph = Glyph("●", "*");
+pub static CIRCLE: Glyph = Glyph("○", "o");
+pub static BRANCH: Glyph = Glyph("├─", "|-");
+pub static LAST_BRANCH: Glyph = Glyph("└─", "`-");
+pub static ARROW: Glyph = Glyph("→", "->");
+pub static BACK_ARROW: Glyph = Glyph("←", "<-");
+
+impl fmt::Display for Glyph {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if ASCII.get().copied().unwrap_or(false) {
+            f.write_str(self.1)
+        } else {
+            f.write_str(self.0)
+        }
+    }
+}
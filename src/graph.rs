@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+
+use crate::error::PieError;
+use crate::glyph::{ARROW, CROSS};
+use crate::{fetch_repo, get_installed_packages, why};
+
+struct Node {
+    dependencies: Vec<String>,
+    conflicts: Vec<String>,
+    explicit: bool,
+}
+
+pub fn print_graph(repo: bool, dot: bool) -> Result<(), PieError> {
+    let nodes = if repo {
+        repo_nodes()?
+    } else {
+        installed_nodes()?
+    };
+
+    if dot {
+        print_dot(&nodes);
+    } else {
+        for (name, node) in &nodes {
+            println!("{name}");
+            for dep in &node.dependencies {
+                println!("  {ARROW} {dep}");
+            }
+            for conflict in &node.conflicts {
+                println!("  {CROSS} {conflict}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn repo_nodes() -> Result<BTreeMap<String, Node>, PieError> {
+    let repo = fetch_repo()?;
+    Ok(repo
+        .packages
+        .iter()
+        .map(|(name, package)| {
+            let node = Node {
+                dependencies: package.dependencies.clone(),
+                conflicts: package.conflicts.clone(),
+                explicit: false,
+            };
+            (name.clone(), node)
+        })
+        .collect())
+}
+
+// Conflicts aren't part of the database, installed packages can't conflict
+// with each other anyway
+fn installed_nodes() -> Result<BTreeMap<String, Node>, PieError> {
+    let installed = get_installed_packages()?;
+    let mut dependencies = why::installed_dependencies(&installed);
+
+    Ok(installed
+        .packages
+        .values()
+        .map(|pkg| {
+            let node = Node {
+                dependencies: dependencies.remove(&pkg.name).unwrap_or_default(),
+                conflicts: Vec::new(),
+                explicit: pkg.explicit,
+            };
+            (pkg.name.clone(), node)
+        })
+        .collect())
+}
+
+// Requested packages are drawn bold, conflicts as dashed red edges
+fn print_dot(nodes: &BTreeMap<String, Node>) {
+    println!("digraph pie {{");
+    println!("    rankdir=LR;");
+    for (name, node) in nodes {
+        if node.explicit {
+            println!("    \"{name}\" [style=bold];");
+        } else {
+            println!("    \"{name}\";");
+        }
+    }
+    for (name, node) in nodes {
+        for dep in &node.dependencies {
+            println!("    \"{name}\" -> \"{dep}\";");
+        }
+        for conflict in &node.conflicts {
+            println!("    \"{name}\" -> \"{conflict}\" [style=dashed, color=red];");
+        }
+    }
+    println!("}}");
+}
@@ -0,0 +1,88 @@
+use serde::Serialize;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::PieError;
+use crate::{exec, ANDSTORE_ROOT, PIE_DATA};
+
+#[derive(Serialize)]
+pub struct HookPackage {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Serialize)]
+struct HookPayload<'a> {
+    hook: &'a str,
+    packages: &'a [HookPackage],
+}
+
+// Hooks live at hooks.d/<hook>, either a single executable or a directory of
+// executables run in name order. A failing pre-* hook aborts the transaction,
+// a failing post-* hook only warns since the change is already done
+pub fn run_hooks(hook: &str, packages: &[HookPackage]) -> Result<(), PieError> {
+    let scripts = hook_scripts(hook)?;
+    if scripts.is_empty() || packages.is_empty() {
+        return Ok(());
+    }
+
+    let payload = serde_json::to_vec(&HookPayload { hook, packages })?;
+    let names: Vec<&str> = packages.iter().map(|pkg| pkg.name.as_str()).collect();
+
+    for script in scripts {
+        println!("Running hook {}...", script.display());
+        let result = run_script(&script, hook, &names.join(" "), &payload);
+
+        match result {
+            Ok(true) => {}
+            Ok(false) | Err(_) if hook.starts_with("pre-") => {
+                return Err(format!("Hook '{}' failed, aborting", script.display()).into());
+            }
+            Ok(false) => eprintln!("Warning: hook '{}' failed", script.display()),
+            Err(e) => eprintln!("Warning: hook '{}' failed: {e}", script.display()),
+        }
+    }
+
+    Ok(())
+}
+
+fn hook_scripts(hook: &str) -> Result<Vec<PathBuf>, PieError> {
+    let path = PathBuf::from(format!("{PIE_DATA}/hooks.d/{hook}"));
+
+    let mut scripts: Vec<PathBuf> = if path.is_dir() {
+        fs::read_dir(&path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect()
+    } else if path.is_file() {
+        vec![path]
+    } else {
+        Vec::new()
+    };
+    scripts.retain(|script| is_executable(script));
+    scripts.sort();
+
+    Ok(scripts)
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+fn run_script(script: &Path, hook: &str, packages: &str, payload: &[u8]) -> Result<bool, PieError> {
+    let mut command = Command::new(script);
+    command
+        .env("PIE_HOOK", hook)
+        .env("PIE_PACKAGES", packages)
+        .env("ANDSTORE_ROOT", &ANDSTORE_ROOT)
+        .env("PIE_DATA", &PIE_DATA);
+
+    exec::run_script(
+        command,
+        &format!("hook {}", script.display()),
+        Some(payload),
+    )
+}
@@ -0,0 +1,90 @@
+use std::fs;
+
+use crate::error::PieError;
+use crate::glyph::BULLET;
+use crate::{config, fetch_repo, get_installed_packages, get_separator, install_packages};
+
+// Readable from root even when pie itself doesn't run inside Termux
+const TERMUX_DPKG_STATUS: &str = "/data/data/com.termux/files/usr/var/lib/dpkg/status";
+
+// Packages installed with Termux's apt/pkg, mapped to andstore names through
+// the repo's termux table or, failing that, an identical package name
+pub fn import_termux() -> Result<(), PieError> {
+    let status_file = std::env::var("PREFIX")
+        .ok()
+        .map(|prefix| format!("{prefix}/var/lib/dpkg/status"))
+        .filter(|path| fs::metadata(path).is_ok())
+        .unwrap_or_else(|| TERMUX_DPKG_STATUS.to_string());
+    let status = fs::read_to_string(&status_file)
+        .map_err(|e| format!("Failed to read Termux package list '{status_file}': {e}"))?;
+    let termux_packages = installed_dpkg_packages(&status);
+
+    let repo = fetch_repo()?;
+    let installed = get_installed_packages()?;
+
+    let mut matches: Vec<(String, String)> = Vec::new();
+    let mut unmatched: Vec<String> = Vec::new();
+    for termux_name in termux_packages {
+        let mapped = repo.termux.get(&termux_name).cloned().or_else(|| {
+            repo.packages
+                .contains_key(&termux_name)
+                .then(|| termux_name.clone())
+        });
+        match mapped {
+            // An empty mapping marks Termux-only packages with no equivalent
+            Some(name) if name.is_empty() => unmatched.push(termux_name),
+            Some(name) => {
+                if !installed.packages.contains_key(&name)
+                    && !matches.iter().any(|(_, existing)| *existing == name)
+                {
+                    matches.push((termux_name, name));
+                }
+            }
+            None => unmatched.push(termux_name),
+        }
+    }
+
+    println!("\n{}", get_separator());
+    println!("TERMUX IMPORT");
+    println!("{}", get_separator());
+    if !unmatched.is_empty() {
+        println!("No andstore equivalent ({}):", unmatched.len());
+        println!("  {}", unmatched.join(", "));
+    }
+    if matches.is_empty() {
+        println!("Nothing to install, every match is already installed");
+        println!("{}", get_separator());
+        return Ok(());
+    }
+    println!("Equivalents to install ({}):", matches.len());
+    for (termux_name, name) in &matches {
+        if termux_name == name {
+            println!("  {BULLET} {name}");
+        } else {
+            println!("  {BULLET} {name} (from {termux_name})");
+        }
+    }
+
+    let names: Vec<String> = matches.into_iter().map(|(_, name)| name).collect();
+    install_packages(&names, config::overlay_default()?)
+}
+
+fn installed_dpkg_packages(status: &str) -> Vec<String> {
+    let mut packages: Vec<String> = status
+        .split("\n\n")
+        .filter(|stanza| {
+            stanza
+                .lines()
+                .any(|line| line.trim() == "Status: install ok installed")
+        })
+        .filter_map(|stanza| {
+            stanza
+                .lines()
+                .find_map(|line| line.strip_prefix("Package:"))
+                .map(|name| name.trim().to_string())
+        })
+        .collect();
+    packages.sort();
+    packages.dedup();
+    packages
+}
@@ -0,0 +1,161 @@
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::{self, Config};
+use crate::error::PieError;
+use crate::glyph::{CHECK, CROSS};
+use crate::{cache, fetch_indices, get_separator, merge_indices, paths, ANDSTORE_ROOT, PIE_DATA};
+
+const ROOT_CONTEXT: &str = "u:object_r:shell_data_file:s0";
+const DATA_CONTEXT: &str = "u:object_r:adb_data_file:s0";
+const MODULE_MKSHRC: &str = "/data/adb/modules/pie/system/etc/mkshrc";
+
+pub fn init(profile: bool) -> Result<(), PieError> {
+    println!("{}", get_separator());
+    println!("INITIALIZING PIE");
+    println!("{}", get_separator());
+    if paths::termux() {
+        println!("Termux detected, installing into {ANDSTORE_ROOT}");
+    } else if paths::host() {
+        println!("Host mode, preparing {ANDSTORE_ROOT} for the target");
+    }
+
+    print!("Creating {ANDSTORE_ROOT}... ");
+    io::stdout().flush()?;
+    for dir in ["", "/bin", "/lib", "/etc"] {
+        create_dir(&format!("{ANDSTORE_ROOT}{dir}"), 0o755)?;
+    }
+    if std::mem::size_of::<usize>() == 8 {
+        create_dir(&format!("{ANDSTORE_ROOT}/lib64"), 0o755)?;
+    }
+    println!("{CHECK}");
+
+    print!("Creating {PIE_DATA}... ");
+    io::stdout().flush()?;
+    create_dir(&PIE_DATA, 0o700)?;
+    println!("{CHECK}");
+
+    print!("Setting SELinux contexts... ");
+    io::stdout().flush()?;
+    if paths::termux() {
+        println!("skipped (running in Termux)");
+    } else if paths::host() {
+        println!("skipped (host mode, label the image when building it)");
+    } else if relabel() {
+        println!("{CHECK}");
+    } else {
+        println!("skipped (chcon unavailable or SELinux disabled)");
+    }
+
+    let config_file = format!("{PIE_DATA}/config.json");
+    if Path::new(&config_file).exists() {
+        println!("Config already exists at {config_file}");
+    } else {
+        print!("Writing default config... ");
+        io::stdout().flush()?;
+        config::save_config(&Config::default())?;
+        println!("{CHECK}");
+    }
+
+    write_profile()?;
+    if profile {
+        print!("Installing PATH profile hook... ");
+        io::stdout().flush()?;
+        if install_profile_hook()? {
+            println!("{CHECK}");
+        } else {
+            println!("skipped");
+            println!("The pie module is not installed, add this to your shell rc instead:");
+            println!("  . {ANDSTORE_ROOT}/etc/profile");
+        }
+    }
+
+    print!("Checking repository... ");
+    io::stdout().flush()?;
+    match fetch_indices().and_then(|indices| {
+        let repo = merge_indices(&indices)?;
+        cache::save_indices(&indices)?;
+        Ok(repo)
+    }) {
+        Ok(repo) => println!("{CHECK} ({} packages)", repo.packages.len()),
+        Err(e) => {
+            println!("{CROSS}");
+            return Err(PieError::Network(format!(
+                "Repository is not reachable: {e}"
+            )));
+        }
+    }
+
+    println!("{}", get_separator());
+    println!("pie is ready to use!");
+    println!("{}", get_separator());
+
+    Ok(())
+}
+
+fn create_dir(path: &str, mode: u32) -> Result<(), PieError> {
+    fs::create_dir_all(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+// OTAs can relabel /data/local, which hides installed binaries from shells
+pub fn relabel() -> bool {
+    !paths::termux()
+        && !paths::host()
+        && set_context(&ANDSTORE_ROOT, ROOT_CONTEXT)
+        && set_context(&PIE_DATA, DATA_CONTEXT)
+}
+
+fn set_context(path: &str, context: &str) -> bool {
+    Command::new("chcon")
+        .args(["-R", context, path])
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+// Sourced by shells to pick up installed binaries and libraries
+pub fn write_profile() -> Result<(), PieError> {
+    let root = paths::device_path(&ANDSTORE_ROOT);
+    let profile = format!(
+        "export PATH=\"$PATH:{root}/bin\"\n\
+         export LD_LIBRARY_PATH=\"$LD_LIBRARY_PATH:{root}/lib:{root}/lib64:{root}/lib32\"\n"
+    );
+    fs::create_dir_all(format!("{ANDSTORE_ROOT}/etc"))?;
+    fs::write(format!("{ANDSTORE_ROOT}/etc/profile"), profile)?;
+    Ok(())
+}
+
+// The pie module overlays mkshrc, which is the only rc file every shell reads.
+// Termux shells source everything in $PREFIX/etc/profile.d instead
+pub fn install_profile_hook() -> Result<bool, PieError> {
+    if paths::termux() {
+        let Ok(prefix) = std::env::var("PREFIX") else {
+            return Ok(false);
+        };
+        fs::create_dir_all(format!("{prefix}/etc/profile.d"))?;
+        fs::write(
+            format!("{prefix}/etc/profile.d/andstore.sh"),
+            format!(". {ANDSTORE_ROOT}/etc/profile\n"),
+        )?;
+        return Ok(true);
+    }
+
+    let mkshrc_path = paths::on_device(MODULE_MKSHRC);
+    if !Path::new(&mkshrc_path).exists() {
+        return Ok(false);
+    }
+
+    let hook = format!(". {}/etc/profile", paths::device_path(&ANDSTORE_ROOT));
+    let mkshrc = fs::read_to_string(&mkshrc_path)?;
+    if !mkshrc.contains(&hook) {
+        let mut file = fs::OpenOptions::new().append(true).open(&mkshrc_path)?;
+        writeln!(file, "{hook}")?;
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,298 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::PieError;
+use crate::glyph::CHECK;
+use crate::opt;
+use crate::{
+    cache, extract, format_timestamp, get_installed_packages, get_separator, install_packages,
+    package_root, prompt, rollback, save_installed_packages, unix_now, upgrade, PIE_DATA,
+};
+
+// Write-ahead record of an install transaction. It is written before the
+// first package is touched and removed once the transaction completes, so a
+// journal found at startup means pie was killed or the device rebooted
+#[derive(Serialize, Deserialize)]
+struct Journal {
+    targets: Vec<String>,
+    overlay: bool,
+    started: u64,
+    packages: Vec<JournalPackage>,
+    #[serde(default)]
+    upgrade: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JournalPackage {
+    name: String,
+    contents: Vec<String>,
+    #[serde(default)]
+    done: bool,
+    // The version an upgrade replaces, rolled back to instead of removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous: Option<String>,
+}
+
+fn journal_path() -> String {
+    format!("{PIE_DATA}/journal.json")
+}
+
+// Written to a temp file and renamed, a torn journal would be worse than none
+fn write_journal(journal: &Journal) -> Result<(), PieError> {
+    let path = journal_path();
+    let temp_path = format!("{path}.tmp");
+
+    fs::create_dir_all(&PIE_DATA)?;
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(serde_json::to_string_pretty(journal)?.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, &path)?;
+    Ok(())
+}
+
+fn read_journal() -> Result<Option<Journal>, PieError> {
+    let path = journal_path();
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path)?;
+    let journal = serde_json::from_str(&content)
+        .map_err(|e| PieError::Schema(format!("Invalid journal '{path}': {e}")))?;
+    Ok(Some(journal))
+}
+
+pub fn begin(
+    targets: &[String],
+    overlay: bool,
+    packages: Vec<(String, Vec<String>)>,
+) -> Result<(), PieError> {
+    write_journal(&Journal {
+        targets: targets.to_vec(),
+        overlay,
+        started: unix_now(),
+        packages: packages
+            .into_iter()
+            .map(|(name, contents)| JournalPackage {
+                name,
+                contents,
+                done: false,
+                previous: None,
+            })
+            .collect(),
+        upgrade: false,
+    })
+}
+
+// Packages come with the version they are upgraded from, new dependencies
+// with none. The overlay is where new dependencies go
+pub fn begin_upgrade(
+    targets: &[String],
+    overlay: bool,
+    packages: Vec<(String, Vec<String>, Option<String>)>,
+) -> Result<(), PieError> {
+    write_journal(&Journal {
+        targets: targets.to_vec(),
+        overlay,
+        started: unix_now(),
+        packages: packages
+            .into_iter()
+            .map(|(name, contents, previous)| JournalPackage {
+                name,
+                contents,
+                done: false,
+                previous,
+            })
+            .collect(),
+        upgrade: true,
+    })
+}
+
+pub fn mark_done(name: &str) -> Result<(), PieError> {
+    let Some(mut journal) = read_journal()? else {
+        return Ok(());
+    };
+    for package in &mut journal.packages {
+        if package.name == name {
+            package.done = true;
+        }
+    }
+    write_journal(&journal)
+}
+
+pub fn finish() -> Result<(), PieError> {
+    let path = journal_path();
+    if Path::new(&path).exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+// Killed after its last package but before the journal was removed, there
+// is nothing left to resume
+pub fn completed() -> Result<bool, PieError> {
+    let Some(journal) = read_journal()? else {
+        return Ok(false);
+    };
+    let installed = get_installed_packages()?;
+    Ok(journal
+        .packages
+        .iter()
+        .all(|package| package.done && installed.packages.contains_key(&package.name)))
+}
+
+// Called before commands that change the install root
+pub fn recover() -> Result<(), PieError> {
+    let Some(journal) = read_journal()? else {
+        return Ok(());
+    };
+
+    let done = journal.packages.iter().filter(|p| p.done).count();
+    let (kind, verb) = if journal.upgrade {
+        ("An upgrade", "upgraded")
+    } else {
+        ("An install", "installed")
+    };
+    println!("{}", get_separator());
+    println!("UNFINISHED TRANSACTION");
+    println!("{}", get_separator());
+    println!(
+        "{kind} of {} started at {} UTC was interrupted",
+        journal.targets.join(", "),
+        format_timestamp(journal.started)
+    );
+    println!("{done} of {} packages were {verb}", journal.packages.len());
+
+    // A killed extraction leaves its staging directory behind either way
+    remove_staging(&journal)?;
+
+    if prompt::confirm("\nResume it?", true)? {
+        if journal.upgrade {
+            upgrade::upgrade(&journal.targets, false, false)?;
+        } else {
+            install_packages(&journal.targets, journal.overlay)?;
+        }
+        // The transaction itself clears the journal, unless there was
+        // nothing left to do or it was cancelled
+        let installed = get_installed_packages()?;
+        if journal.packages.iter().all(|package| {
+            installed
+                .packages
+                .get(&package.name)
+                .is_some_and(|current| package.previous.as_ref() != Some(&current.version))
+        }) {
+            finish()?;
+        }
+    } else if prompt::confirm("Roll it back?", false)? {
+        rollback(&journal)?;
+    } else {
+        println!("Leaving it for now, pie will ask again next time");
+        return Ok(());
+    }
+    println!();
+    Ok(())
+}
+
+// Undoes the transaction in progress after SIGINT/SIGTERM
+pub fn abort() -> Result<(), PieError> {
+    let Some(journal) = read_journal()? else {
+        return Ok(());
+    };
+    remove_staging(&journal)?;
+    rollback(&journal)
+}
+
+// Upgraded packages stay where they were installed, which may not be the
+// overlay the journal was started with
+fn remove_staging(journal: &Journal) -> Result<(), PieError> {
+    extract::remove_staging(Path::new(&package_root(journal.overlay)))?;
+    if journal.upgrade {
+        extract::remove_staging(Path::new(&package_root(!journal.overlay)))?;
+    }
+    Ok(())
+}
+
+// Packages new to the transaction go with everything the journal lists for
+// them, including files of the one being extracted. Upgraded packages are
+// restored from the cache instead
+fn rollback(journal: &Journal) -> Result<(), PieError> {
+    let mut installed = get_installed_packages()?;
+    let root = package_root(journal.overlay);
+
+    // Packages after the one that was interrupted were never touched
+    let touched = journal
+        .packages
+        .iter()
+        .position(|package| !package.done)
+        .map_or(journal.packages.len(), |i| i + 1);
+    let mut upgraded = Vec::new();
+
+    for (i, package) in journal.packages.iter().enumerate() {
+        if let Some(previous) = &package.previous {
+            if i < touched {
+                upgraded.push((package, previous));
+            }
+            continue;
+        }
+        print!("Removing {}... ", package.name);
+        std::io::stdout().flush()?;
+        for file in &package.contents {
+            let path = format!("{root}/{file}");
+            if fs::symlink_metadata(&path).is_ok() {
+                fs::remove_file(&path)?;
+            }
+        }
+        // Opt packages are journaled under their prefix, which goes with them
+        let prefix = opt::prefix(&package.name);
+        let prefix_dir = format!("{root}/{prefix}");
+        if package
+            .contents
+            .iter()
+            .any(|file| file.starts_with(&format!("{prefix}/")))
+            && Path::new(&prefix_dir).is_dir()
+        {
+            fs::remove_dir_all(&prefix_dir)?;
+        }
+        installed.packages.remove(&package.name);
+        println!("{CHECK}");
+    }
+
+    save_installed_packages(&installed)?;
+    for (package, previous) in upgraded {
+        restore(package, previous)?;
+    }
+    finish()?;
+    println!("Rolled back the interrupted transaction");
+    Ok(())
+}
+
+// Files of the new version the old one doesn't ship are left over when the
+// extraction was cut short, the installed record never listed them
+fn restore(package: &JournalPackage, previous: &str) -> Result<(), PieError> {
+    let name = &package.name;
+    let Some(cached) = cache::cached_versions(name)?
+        .into_iter()
+        .find(|cached| cached.package.version == previous)
+    else {
+        println!("{name} v{previous} is not cached, reinstall it with 'pie install {name}'");
+        return Ok(());
+    };
+    rollback::restore(name, cached)?;
+
+    let installed = get_installed_packages()?;
+    if let Some(restored) = installed.packages.get(name) {
+        let root = package_root(restored.overlay);
+        for file in package
+            .contents
+            .iter()
+            .filter(|file| !restored.contents.contains(file))
+        {
+            let path = format!("{root}/{file}");
+            if fs::symlink_metadata(&path).is_ok() {
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+    Ok(())
+}
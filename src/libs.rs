@@ -0,0 +1,294 @@
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use crate::error::PieError;
+use crate::glyph::{ARROW, CHECK, CROSS};
+use crate::{
+    cache, compat_abi, get_arch, get_installed_packages, get_separator, merge_indices,
+    package_root, paths, ANDSTORE_ROOT,
+};
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+// What the dynamic linker needs to load an ELF file. Android is little
+// endian on every ABI, so nothing else is parsed
+pub struct Elf {
+    pub is_64: bool,
+    pub needed: Vec<String>,
+    pub runpath: Vec<String>,
+}
+
+fn read_at(file: &fs::File, offset: u64, len: usize) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    file.read_exact_at(&mut buf, offset).ok()?;
+    Some(buf)
+}
+
+// Addresses and offsets are 4 bytes in 32-bit files and 8 in 64-bit ones
+fn word(buf: &[u8], offset: usize, is_64: bool) -> u64 {
+    if is_64 {
+        u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap_or_default())
+    } else {
+        u64::from(u32::from_le_bytes(
+            buf[offset..offset + 4].try_into().unwrap_or_default(),
+        ))
+    }
+}
+
+fn half(buf: &[u8], offset: usize) -> u64 {
+    u64::from(u16::from_le_bytes([buf[offset], buf[offset + 1]]))
+}
+
+pub fn read_elf(path: &Path) -> Option<Elf> {
+    let file = fs::File::open(path).ok()?;
+    let header = read_at(&file, 0, 64)?;
+    if header[..4] != *b"\x7fELF" || header[5] != 1 {
+        return None;
+    }
+    let is_64 = header[4] == 2;
+    let (phoff, phentsize, phnum) = if is_64 {
+        (
+            word(&header, 32, true),
+            half(&header, 54),
+            half(&header, 56),
+        )
+    } else {
+        (
+            word(&header, 28, false),
+            half(&header, 42),
+            half(&header, 44),
+        )
+    };
+
+    if phentsize < if is_64 { 56 } else { 32 } {
+        return None;
+    }
+    // (type, offset, vaddr, filesz) of each program header
+    let table = read_at(&file, phoff, (phentsize * phnum) as usize)?;
+    let segments: Vec<(u32, u64, u64, u64)> = table
+        .chunks_exact(phentsize as usize)
+        .map(|ph| {
+            let kind = u32::from_le_bytes([ph[0], ph[1], ph[2], ph[3]]);
+            if is_64 {
+                (
+                    kind,
+                    word(ph, 8, true),
+                    word(ph, 16, true),
+                    word(ph, 32, true),
+                )
+            } else {
+                (
+                    kind,
+                    word(ph, 4, false),
+                    word(ph, 8, false),
+                    word(ph, 16, false),
+                )
+            }
+        })
+        .collect();
+    // Static executables have no dynamic segment and need nothing
+    let &(_, dyn_offset, _, dyn_size) = segments.iter().find(|s| s.0 == PT_DYNAMIC)?;
+
+    // Real dynamic sections are a few hundred bytes
+    if dyn_size > 1 << 20 {
+        return None;
+    }
+    let entry_size = if is_64 { 16 } else { 8 };
+    let dynamic = read_at(&file, dyn_offset, dyn_size as usize)?;
+    let mut strtab = None;
+    let mut needed = Vec::new();
+    let mut runpath = Vec::new();
+    for entry in dynamic.chunks_exact(entry_size) {
+        let tag = word(entry, 0, is_64);
+        let value = word(entry, entry_size / 2, is_64);
+        match tag {
+            0 => break,
+            DT_STRTAB => strtab = Some(value),
+            DT_NEEDED => needed.push(value),
+            DT_RPATH | DT_RUNPATH => runpath.push(value),
+            _ => {}
+        }
+    }
+
+    // The string table is given as an address, mapped back through the
+    // loadable segment containing it
+    let strtab = strtab?;
+    let &(_, offset, vaddr, _) = segments.iter().find(|&&(kind, _, vaddr, size)| {
+        kind == PT_LOAD && (vaddr..vaddr.saturating_add(size)).contains(&strtab)
+    })?;
+    let strtab = strtab - vaddr + offset;
+    let string = |index: u64| -> Option<String> {
+        let mut bytes = vec![0u8; 256];
+        let n = file.read_at(&mut bytes, strtab + index).ok()?;
+        let end = bytes[..n].iter().position(|&b| b == 0)?;
+        Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    };
+
+    Some(Elf {
+        is_64,
+        needed: needed.into_iter().filter_map(&string).collect(),
+        runpath: runpath
+            .into_iter()
+            .filter_map(&string)
+            .flat_map(|list| list.split(':').map(str::to_string).collect::<Vec<_>>())
+            .collect(),
+    })
+}
+
+// Where the linker finds libraries for each ELF class, system first
+fn search_dirs(is_64: bool, arch: &str) -> Vec<String> {
+    let lib = if is_64 { "lib64" } else { "lib" };
+    let mut dirs: Vec<String> = ["/system", "/system_ext", "/product", "/vendor", "/odm"]
+        .iter()
+        .map(|partition| paths::on_device(&format!("{partition}/{lib}")))
+        .collect();
+    dirs.push(paths::on_device(&format!(
+        "/apex/com.android.runtime/{lib}/bionic"
+    )));
+    if let Ok(apexes) = fs::read_dir(paths::on_device("/apex")) {
+        for apex in apexes.flatten() {
+            dirs.push(format!("{}/{lib}", apex.path().display()));
+        }
+    }
+
+    // 32-bit libraries of a 64-bit device live in lib32, see multilib.rs
+    if !is_64 && compat_abi(arch).is_some() {
+        dirs.push(format!("{ANDSTORE_ROOT}/lib32"));
+    } else {
+        dirs.push(format!("{ANDSTORE_ROOT}/lib"));
+        dirs.push(format!("{ANDSTORE_ROOT}/lib64"));
+    }
+    dirs
+}
+
+fn libraries_in(dirs: &[String]) -> HashSet<String> {
+    dirs.iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect()
+}
+
+// Reports every DT_NEEDED of an installed ELF file that no library
+// directory provides, and which repo packages ship a file of that name
+pub fn check_libraries() -> Result<bool, PieError> {
+    let installed = get_installed_packages()?;
+    let arch = get_arch()?;
+
+    let files: Vec<(&str, &str, String)> = installed
+        .packages
+        .values()
+        .flat_map(|pkg| {
+            let root = package_root(pkg.overlay);
+            pkg.contents
+                .iter()
+                .map(move |file| (pkg.name.as_str(), file.as_str(), format!("{root}/{file}")))
+        })
+        .collect();
+
+    print!(
+        "Scanning {} installed files for ELF binaries... ",
+        files.len()
+    );
+    io::stdout().flush()?;
+    let elves: Vec<(&str, &str, String, Elf)> = files
+        .into_par_iter()
+        .filter_map(|(pkg, file, path)| {
+            let meta = fs::symlink_metadata(&path).ok()?;
+            if !meta.is_file() {
+                return None;
+            }
+            let elf = read_elf(Path::new(&path))?;
+            Some((pkg, file, path, elf))
+        })
+        .collect();
+    println!("{CHECK} ({} found)", elves.len());
+
+    let system = [
+        libraries_in(&search_dirs(true, &arch)),
+        libraries_in(&search_dirs(false, &arch)),
+    ];
+    // Missing sonames and the files needing them
+    let mut missing: BTreeMap<&str, Vec<(&str, &str)>> = BTreeMap::new();
+    for (pkg, file, path, elf) in &elves {
+        let origin = Path::new(path)
+            .parent()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default();
+        let runpath: Vec<String> = elf
+            .runpath
+            .iter()
+            .map(|dir| {
+                if dir.contains("$ORIGIN") {
+                    dir.replace("$ORIGIN", &origin)
+                } else {
+                    paths::on_device(dir)
+                }
+            })
+            .collect();
+        let own = libraries_in(&runpath);
+        let known = &system[usize::from(!elf.is_64)];
+        for soname in &elf.needed {
+            if !known.contains(soname) && !own.contains(soname) {
+                missing
+                    .entry(soname.as_str())
+                    .or_default()
+                    .push((pkg, file));
+            }
+        }
+    }
+
+    if missing.is_empty() {
+        println!("{CHECK} All shared libraries resolve");
+        return Ok(true);
+    }
+
+    // The cached indices are enough to suggest providers, no network needed
+    let repo = cache::load_cached_indices()
+        .ok()
+        .and_then(|indices| merge_indices(&indices).ok());
+
+    println!("\n{}", get_separator());
+    println!("MISSING LIBRARIES ({})", missing.len());
+    println!("{}", get_separator());
+    for (soname, users) in &missing {
+        println!("  {CROSS} {soname}");
+        for (pkg, file) in users {
+            println!("      needed by {file} ({pkg})");
+        }
+        let mut providers: Vec<&String> = repo
+            .iter()
+            .flat_map(|repo| &repo.packages)
+            .filter(|(_, package)| {
+                package
+                    .architectures
+                    .get(&arch)
+                    .is_some_and(|architecture| {
+                        architecture.contents.iter().any(|content| {
+                            Path::new(content).file_name() == Some(OsStr::new(soname))
+                        })
+                    })
+            })
+            .map(|(name, _)| name)
+            .collect();
+        providers.sort();
+        if providers.is_empty() {
+            println!("    {ARROW} no package in the repo provides it");
+        } else {
+            let names: Vec<&str> = providers.iter().map(|name| name.as_str()).collect();
+            println!("    {ARROW} provided by {}", names.join(", "));
+        }
+    }
+
+    Ok(false)
+}
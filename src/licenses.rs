@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+
+use crate::config::{self, LicensePolicy};
+use crate::error::PieError;
+use crate::glyph::{BULLET, CROSS};
+use crate::{fetch_repo, get_installed_packages, get_separator};
+
+enum Verdict {
+    Allowed,
+    Denied,
+    Unknown,
+}
+
+// "MIT OR Apache-2.0" passes if any of the alternatives does
+fn check_license(license: &str, policy: &LicensePolicy) -> Verdict {
+    let allowed = |id: &str| {
+        !policy.deny.iter().any(|d| d == id)
+            && (policy.allow.is_empty() || policy.allow.iter().any(|a| a == id))
+    };
+
+    if license.split(" OR ").map(str::trim).any(allowed) {
+        Verdict::Allowed
+    } else {
+        Verdict::Denied
+    }
+}
+
+// Returns false if any installed package has an unknown or denied license
+pub fn license_report() -> Result<bool, PieError> {
+    let policy = config::load_config()?.licenses;
+    let repo = fetch_repo()?;
+    let installed = get_installed_packages()?;
+
+    let mut by_license: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    let mut problems = Vec::new();
+
+    let mut names: Vec<&String> = installed.packages.keys().collect();
+    names.sort();
+    for name in names {
+        let license = repo
+            .packages
+            .get(name)
+            .and_then(|package| package.license.as_deref());
+
+        let verdict = match license {
+            Some(license) => check_license(license, &policy),
+            None => Verdict::Unknown,
+        };
+        match verdict {
+            Verdict::Allowed => {}
+            Verdict::Denied => problems.push(format!(
+                "{name}: {} is not allowed",
+                license.unwrap_or_default()
+            )),
+            Verdict::Unknown => problems.push(format!("{name}: license unknown")),
+        }
+
+        by_license
+            .entry(license.unwrap_or("unknown").to_string())
+            .or_default()
+            .push(name);
+    }
+
+    println!("Licenses of installed packages:\n");
+    for (license, packages) in &by_license {
+        println!("{BULLET} {license} ({})", packages.len());
+        println!("  {}", packages.join(", "));
+    }
+
+    if !problems.is_empty() {
+        println!("\n{}", get_separator());
+        println!("POLICY VIOLATIONS");
+        println!("{}", get_separator());
+        for problem in &problems {
+            println!("{CROSS} {problem}");
+        }
+    }
+
+    Ok(problems.is_empty())
+}
@@ -0,0 +1,362 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path};
+use tar::{Archive, EntryType};
+use zstd::stream::read::Decoder;
+
+use crate::error::PieError;
+use crate::glyph::{BULLET, CHECK, CROSS};
+use crate::{
+    expand_subpackage, get_separator, parse_index, paths, sha256_file, Architecture, Package,
+};
+
+// Where packages may put files. lib32 belongs to multilib variants, opt to
+// packages installed with "opt": true and etc/profile to pie itself
+const TOP_LEVEL_DIRS: &[&str] = &[
+    "bin", "sbin", "lib", "lib64", "libexec", "include", "share", "etc", "var",
+];
+const RESERVED_PATHS: &[&str] = &["etc/profile"];
+
+// Shebangs have to name something that exists on Android. Packages are built
+// for the default root
+const INTERPRETER_DIRS: &[&str] = &["/system/bin/", "/vendor/bin/", paths::DEFAULT_ROOT];
+
+struct Findings {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+// Checks an index before it is published, and with `archives` the archives
+// it points to as well
+pub fn lint(index: &Path, archives: Option<&Path>) -> Result<bool, PieError> {
+    let content = fs::read(index)?;
+    let repo = parse_index(&content)
+        .map_err(|e| PieError::Schema(format!("Invalid index '{}': {e}", index.display())))?;
+
+    let mut packages = Vec::new();
+    for (name, mut package) in repo.packages {
+        for (sub_name, subpackage) in std::mem::take(&mut package.subpackages) {
+            packages.push((sub_name, expand_subpackage(&name, &package, subpackage)));
+        }
+        packages.push((name, package));
+    }
+    packages.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut clean = true;
+    let mut error_count = 0;
+    for (name, package) in &packages {
+        let mut findings = Findings {
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        };
+        lint_package(package, &mut findings);
+        let mut arches: Vec<&String> = package.architectures.keys().collect();
+        arches.sort();
+        for arch in arches {
+            let architecture = &package.architectures[arch];
+            lint_architecture(package, architecture, arch, &mut findings);
+            if let Some(dir) = archives {
+                lint_archive(architecture, arch, dir, &mut findings);
+            }
+        }
+
+        if findings.errors.is_empty() && findings.warnings.is_empty() {
+            continue;
+        }
+        if clean {
+            println!("\n{}", get_separator());
+            println!("LINT RESULTS");
+            println!("{}", get_separator());
+            clean = false;
+        }
+        println!("{name} v{}:", package.version);
+        for error in &findings.errors {
+            println!("  {CROSS} {error}");
+        }
+        for warning in &findings.warnings {
+            println!("  {BULLET} warning: {warning}");
+        }
+        error_count += findings.errors.len();
+    }
+
+    if clean {
+        println!("{CHECK} {} packages passed", packages.len());
+    } else {
+        println!("{}", get_separator());
+    }
+    Ok(error_count == 0)
+}
+
+fn lint_package(package: &Package, findings: &mut Findings) {
+    if package
+        .min_api
+        .as_deref()
+        .is_none_or(|api| api.trim().is_empty())
+    {
+        findings
+            .warnings
+            .push("no min_api, installs will be attempted on every release".to_string());
+    } else if package
+        .min_api
+        .as_deref()
+        .is_some_and(|api| api.trim().parse::<u32>().is_err())
+    {
+        findings.errors.push(format!(
+            "min_api '{}' is not a number",
+            package.min_api.as_deref().unwrap_or_default()
+        ));
+    }
+    if package.architectures.is_empty() {
+        findings.errors.push("no architectures".to_string());
+    }
+}
+
+fn lint_architecture(
+    package: &Package,
+    architecture: &Architecture,
+    arch: &str,
+    findings: &mut Findings,
+) {
+    if architecture.sha256.len() != 64
+        || !architecture.sha256.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        findings
+            .errors
+            .push(format!("{arch}: sha256 is not a SHA-256 hex digest"));
+    }
+    if architecture.size == 0 || architecture.uncompressed_size == 0 {
+        findings
+            .errors
+            .push(format!("{arch}: size and uncompressed_size must be set"));
+    }
+
+    for file in &architecture.contents {
+        let path = Path::new(file);
+        if !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            findings
+                .errors
+                .push(format!("{arch}: '{file}' is not a plain relative path"));
+            continue;
+        }
+        let top = file.split('/').next().unwrap_or_default();
+        if !TOP_LEVEL_DIRS.contains(&top) {
+            findings.errors.push(format!(
+                "{arch}: '{file}' is outside the allowed directories"
+            ));
+        } else if RESERVED_PATHS
+            .iter()
+            .any(|reserved| file.starts_with(reserved))
+        {
+            findings
+                .errors
+                .push(format!("{arch}: '{file}' is reserved for pie"));
+        }
+    }
+
+    let scripts = package
+        .scripts
+        .values()
+        .chain(package.boot_scripts.iter().map(|script| &script.path));
+    for script in scripts {
+        if !architecture.contents.contains(script) {
+            findings
+                .errors
+                .push(format!("{arch}: script '{script}' is not in the contents"));
+        }
+    }
+}
+
+fn lint_archive(architecture: &Architecture, arch: &str, dir: &Path, findings: &mut Findings) {
+    let Some(file_name) = Path::new(&architecture.url).file_name() else {
+        return;
+    };
+    let path = dir.join(file_name);
+    let Ok(meta) = fs::metadata(&path) else {
+        findings
+            .warnings
+            .push(format!("{arch}: archive {} not found", path.display()));
+        return;
+    };
+
+    if meta.len() != architecture.size {
+        findings.errors.push(format!(
+            "{arch}: size is {} but the archive has {} bytes",
+            architecture.size,
+            meta.len()
+        ));
+    }
+    if sha256_file(&path).is_ok_and(|hash| hash != architecture.sha256) {
+        findings
+            .errors
+            .push(format!("{arch}: sha256 doesn't match the archive"));
+    }
+
+    match read_archive(&path, architecture, dir) {
+        Ok(listing) => compare_listing(architecture, arch, &listing, findings),
+        Err(e) => findings
+            .errors
+            .push(format!("{arch}: can't read the archive: {e}")),
+    }
+}
+
+struct Listing {
+    files: BTreeSet<String>,
+    dirs: BTreeSet<String>,
+    size: u64,
+    // Symlinks and their targets
+    links: HashMap<String, String>,
+    // First line of each file starting with #!
+    shebangs: HashMap<String, String>,
+}
+
+fn read_archive(path: &Path, architecture: &Architecture, dir: &Path) -> Result<Listing, PieError> {
+    let file = fs::File::open(path)?;
+    // Dictionaries are looked for next to the archives
+    let dictionary = match &architecture.dictionary {
+        Some(dictionary) => {
+            let name = Path::new(&dictionary.url)
+                .file_name()
+                .ok_or("dictionary url has no file name")?;
+            Some(fs::read(dir.join(name))?)
+        }
+        None => None,
+    };
+    let mut decoder = match &dictionary {
+        Some(dictionary) => Decoder::with_dictionary(std::io::BufReader::new(file), dictionary)?,
+        None => Decoder::with_buffer(std::io::BufReader::new(file))?,
+    };
+    decoder.window_log_max(31)?;
+
+    let mut listing = Listing {
+        files: BTreeSet::new(),
+        dirs: BTreeSet::new(),
+        size: 0,
+        links: HashMap::new(),
+        shebangs: HashMap::new(),
+    };
+    let mut archive = Archive::new(decoder);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry
+            .path()?
+            .to_string_lossy()
+            .trim_start_matches("./")
+            .trim_end_matches('/')
+            .to_string();
+        match entry.header().entry_type() {
+            EntryType::Directory => {
+                listing.dirs.insert(name);
+                continue;
+            }
+            EntryType::Symlink => {
+                if let Some(target) = entry.link_name()? {
+                    listing
+                        .links
+                        .insert(name.clone(), target.to_string_lossy().into_owned());
+                }
+            }
+            EntryType::Regular => {
+                listing.size += entry.size();
+                let mut head = Vec::new();
+                entry.by_ref().take(256).read_to_end(&mut head)?;
+                if let Some(line) = head.strip_prefix(b"#!") {
+                    let line = line.split(|&b| b == b'\n').next().unwrap_or_default();
+                    listing.shebangs.insert(
+                        name.clone(),
+                        String::from_utf8_lossy(line).trim().to_string(),
+                    );
+                }
+            }
+            _ => {}
+        }
+        listing.files.insert(name);
+    }
+    Ok(listing)
+}
+
+fn compare_listing(
+    architecture: &Architecture,
+    arch: &str,
+    listing: &Listing,
+    findings: &mut Findings,
+) {
+    let declared: BTreeSet<&str> = architecture.contents.iter().map(String::as_str).collect();
+    for file in &listing.files {
+        if !declared.contains(file.as_str()) {
+            findings.errors.push(format!(
+                "{arch}: '{file}' is in the archive but not in contents"
+            ));
+        }
+    }
+    for file in &declared {
+        if !listing.files.contains(*file) {
+            findings.errors.push(format!(
+                "{arch}: '{file}' is in contents but not in the archive"
+            ));
+        }
+    }
+    if listing.size != architecture.uncompressed_size {
+        findings.warnings.push(format!(
+            "{arch}: uncompressed_size is {} but the files add up to {}",
+            architecture.uncompressed_size, listing.size
+        ));
+    }
+
+    for (link, target) in &listing.links {
+        if target.starts_with('/') {
+            if !INTERPRETER_DIRS
+                .iter()
+                .chain(&["/system/", "/vendor/", "/apex/"])
+                .any(|dir| target.starts_with(dir))
+            {
+                findings.warnings.push(format!(
+                    "{arch}: '{link}' points outside Android ({target})"
+                ));
+            }
+            continue;
+        }
+        let parent = Path::new(link).parent().unwrap_or(Path::new(""));
+        let resolved = normalize(&parent.join(target));
+        if resolved.as_deref().is_none_or(|resolved| {
+            !listing.files.contains(resolved) && !listing.dirs.contains(resolved)
+        }) {
+            findings.errors.push(format!(
+                "{arch}: '{link}' is a dangling symlink to {target}"
+            ));
+        }
+    }
+
+    let mut shebangs: Vec<(&String, &String)> = listing.shebangs.iter().collect();
+    shebangs.sort();
+    for (file, line) in shebangs {
+        let interpreter = line.split_whitespace().next().unwrap_or_default();
+        if !INTERPRETER_DIRS
+            .iter()
+            .any(|dir| interpreter.starts_with(dir))
+        {
+            findings.errors.push(format!(
+                "{arch}: '{file}' runs {interpreter}, which doesn't exist on Android"
+            ));
+        }
+    }
+}
+
+// Resolves . and .. without touching the disk, None if it leaves the root
+fn normalize(path: &Path) -> Option<String> {
+    let mut parts: Vec<&str> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::ParentDir => {
+                parts.pop()?;
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(parts.join("/"))
+}
@@ -0,0 +1,36 @@
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+const TAG: &str = "pie";
+
+// Set once from the config before any command runs
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+pub fn set_enabled(enabled: bool) {
+    let _ = ENABLED.set(enabled);
+}
+
+pub fn info(message: &str) {
+    write("i", message);
+}
+
+pub fn warn(message: &str) {
+    write("w", message);
+}
+
+pub fn error(message: &str) {
+    write("e", message);
+}
+
+// The `log` tool ships with every Android build and writes through liblog;
+// logging must never get in the way of the transaction itself
+fn write(priority: &str, message: &str) {
+    if !ENABLED.get().copied().unwrap_or(false) {
+        return;
+    }
+    let _ = Command::new("log")
+        .args(["-p", priority, "-t", TAG, message])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
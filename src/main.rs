@@ -1,16 +1,81 @@
+mod alternatives;
+mod audit;
+mod boot;
+mod cache;
+mod completion;
+mod config;
+mod daemon;
+mod direct;
+mod error;
+mod exec;
+mod explain;
+mod extract;
+mod format;
+mod gc;
+mod github;
+mod glob;
+mod glyph;
+mod graph;
+mod hooks;
+mod import;
+mod init;
+mod journal;
+mod libs;
+mod licenses;
+mod lint;
+mod logcat;
+mod mangen;
+mod mirror;
+mod module;
+mod multilib;
+mod net;
+mod news;
+mod notify;
+mod opt;
+mod orphans;
+mod overlay;
+mod packaging;
+mod paths;
+mod post_ota;
+mod prefetch;
+mod progress;
+mod prompt;
+mod publish;
+mod query;
+mod remote;
+mod repair;
+mod restore;
+mod rollback;
+mod scripts;
+mod service;
+mod signal;
+mod snapshot;
+mod source;
+mod sysprop;
+mod telemetry;
+mod unattended;
+mod upgrade;
+mod verify;
+mod version;
+mod why;
+
 use clap::{Parser, Subcommand};
+use config::RepoConfig;
+use error::{ErrorFormat, PieError};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::fs;
 use std::io::{self, Write};
 use std::path::Path;
-use tar::Archive;
-use zstd::stream::read::Decoder;
-use terminal_size::{Width, terminal_size};
+use std::thread;
+use terminal_size::{terminal_size, Width};
+
+use glyph::{ARROW, BRANCH, BULLET, CHECK, CROSS, LAST_BRANCH};
+use paths::{ANDSTORE_ROOT, PIE_DATA};
 
-const ANDSTORE_ROOT: &str = "/data/local/andstore";
-const PIE_DATA: &str = "/data/adb/pie";
 const REPO_URL: &str =
     "https://raw.githubusercontent.com/andstore-org/andstore-repo/main/repo.json";
 
@@ -19,56 +84,575 @@ const REPO_URL: &str =
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Answer yes to every prompt
+    #[arg(
+        short = 'y',
+        long = "assume-yes",
+        visible_alias = "no-confirm",
+        global = true,
+        conflicts_with = "assume_no"
+    )]
+    assume_yes: bool,
+    /// Answer no to every prompt, e.g. to review what a command would do
+    #[arg(long, global = true)]
+    assume_no: bool,
+    /// Report errors as text or as a JSON object on stderr
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    error_format: ErrorFormat,
+    /// Write progress as newline-delimited JSON events to stdout, or with
+    /// =FILE to a file or named pipe, for frontends
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "-"
+    )]
+    progress_json: Option<String>,
+    /// Hand install and query to a running `pie daemon`, through its default
+    /// socket or =SOCKET
+    #[arg(
+        long,
+        global = true,
+        value_name = "SOCKET",
+        num_args = 0..=1,
+        require_equals = true
+    )]
+    connect: Option<Option<String>>,
+    /// Print plain ASCII instead of symbols like ✓ and ●
+    #[arg(long, global = true)]
+    ascii: bool,
+    /// Run on a workstation against a mounted image or staging directory
+    #[arg(long, global = true, value_name = "DIR")]
+    target_root: Option<String>,
+    /// ABI of the target, instead of its build.prop
+    #[arg(long, global = true, requires = "target_root")]
+    abi: Option<String>,
+    /// API level of the target, instead of its build.prop
+    #[arg(long, global = true, requires = "target_root")]
+    api: Option<u32>,
+    /// build.prop style file describing the target
+    #[arg(long, global = true, value_name = "FILE", requires = "target_root")]
+    props: Option<String>,
+    /// Remove essential packages without the double confirmation
+    #[arg(long, global = true)]
+    force_remove: bool,
+    /// Install files that another installed package owns
+    #[arg(long, global = true)]
+    force_overwrite: bool,
+    /// Install another ABI's build when the repo has none for this device
+    #[arg(long, global = true)]
+    force_arch: bool,
+    /// Let upgrade replace packages newer than the repo with the repo's version
+    #[arg(long, global = true)]
+    force_downgrade: bool,
+    /// Refuse to work with a repo index that doesn't have this SHA-256,
+    /// NAME=SHA256 pins one of several repos
+    #[arg(long, global = true, value_name = "SHA256")]
+    index_hash: Vec<String>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     #[command(alias = "add")]
     Install {
-        package: String,
-        #[arg(short = 'y', long = "no-confirm")]
-        no_confirm: bool,
+        /// Packages to install, @name installs a group, name=version pins a version
+        /// and - reads the list from stdin
+        #[arg(required_unless_present_any = ["url", "file"])]
+        packages: Vec<String>,
+        /// Read packages from a file, one per line, # starts a comment
+        #[arg(long, short = 'f', value_name = "FILE", conflicts_with = "url")]
+        file: Option<String>,
+        /// Install into a systemless Magisk/KernelSU module instead of the andstore root
+        #[arg(long)]
+        overlay: bool,
+        /// Install an artifact that is not in any repo, verified against --sha256
+        #[arg(
+            long,
+            conflicts_with = "packages",
+            requires_all = ["sha256", "name", "version"]
+        )]
+        url: Option<String>,
+        /// Expected SHA-256 of the artifact given with --url
+        #[arg(long, requires = "url")]
+        sha256: Option<String>,
+        /// Package name to record the --url artifact under
+        #[arg(long, requires = "url")]
+        name: Option<String>,
+        /// Version to record the --url artifact under
+        #[arg(long, requires = "url")]
+        version: Option<String>,
+        /// Use archives from this directory, named by SHA-256, instead of downloading
+        #[arg(long, hide = true, value_name = "DIR")]
+        archives: Option<String>,
     },
     #[command(alias = "remove")]
     Uninstall {
-        package: String,
+        /// Packages to remove, @name removes the installed members of a group
+        #[arg(required = true)]
+        packages: Vec<String>,
     },
     Update,
+    /// Upgrade installed packages to the versions in the repo
+    Upgrade {
+        /// Packages to upgrade, all installed packages if omitted
+        packages: Vec<String>,
+        /// Only apply updates that fix a known security advisory
+        #[arg(long)]
+        security_only: bool,
+        /// List the files each upgrade adds (+), removes (-) and replaces (~)
+        #[arg(long)]
+        show_files: bool,
+        /// Upgrade without prompts under the config's unattended policy, for cron or service.d
+        #[arg(long, conflicts_with_all = ["packages", "show_files"])]
+        unattended: bool,
+    },
+    /// Go back to a previously installed version kept in the cache, offline
+    Rollback {
+        package: String,
+        /// Cached version to go back to, the most recent other one if omitted
+        #[arg(long)]
+        version: Option<String>,
+    },
     Search {
         query: Option<String>,
+        /// Treat the query as a regular expression matched against names,
+        /// descriptions and file paths
+        #[arg(long, requires = "query")]
+        regex: bool,
+        /// List every package shipping a file whose path contains this
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["query", "regex"])]
+        contents: Option<String>,
+        /// Only show packages that are already installed
+        #[arg(long, conflicts_with = "not_installed")]
+        installed: bool,
+        /// Only show packages that are not installed yet
+        #[arg(long)]
+        not_installed: bool,
+        /// Print each result using a template, e.g. '{name} {version} {size}'
+        #[arg(long)]
+        format: Option<String>,
     },
     #[command(name = "list")]
-    List,
+    List {
+        /// Only list packages matching a shell-style pattern, e.g. 'python-*'
+        pattern: Option<String>,
+        /// Print each package using a template, e.g. '{name} {version} {size}'
+        #[arg(long)]
+        format: Option<String>,
+    },
+    /// Filter packages with an expression, e.g. 'installed && size > 10MB && repo == main'
+    Query {
+        /// Attributes: name, version, description, license, repo, state, installed,
+        /// upgradable, explicit, available, size, installed_size, arch, depends
+        expression: String,
+        /// Print matching packages with all attributes as JSON instead of names
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove staging directories, partial downloads and other leftovers of
+    /// interrupted runs
+    Gc {
+        /// List what would be removed without removing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check installed files against the recorded checksums
+    Verify {
+        /// Packages to verify, all installed packages if omitted
+        packages: Vec<String>,
+    },
+    /// Look for problems with installed packages that only show up at runtime
+    Check {
+        /// Find shared libraries needed by installed binaries that nothing provides
+        #[arg(long)]
+        libs: bool,
+    },
+    /// Check a repo index, and optionally its archives, against packaging policy
+    Lint {
+        /// Index to check
+        index: std::path::PathBuf,
+        /// Directory with the archives and dictionaries the index points to
+        #[arg(long)]
+        archives: Option<std::path::PathBuf>,
+    },
+    /// Show details about a package
+    Info {
+        package: String,
+        /// Print the package using a template, e.g. '{name} {version} {size}'
+        #[arg(long)]
+        format: Option<String>,
+    },
+    /// List installed packages with a newer version in the repo
+    Outdated {
+        /// Print each package using a template, e.g. '{name} {installed} {available}'
+        #[arg(long)]
+        format: Option<String>,
+    },
+    /// Exit 0 and print the version if a package is installed, exit 1 otherwise
+    Status {
+        package: String,
+        /// Only succeed if the installed version is at least this
+        #[arg(long, value_name = "VERSION")]
+        min_version: Option<String>,
+    },
+    /// Explain why a package is installed
+    Why {
+        package: String,
+    },
+    /// Download and verify the sources of a package
+    Source {
+        package: String,
+        /// Directory to store the sources in
+        #[arg(long, default_value = ".")]
+        output: String,
+    },
+    /// Summarize the licenses of installed packages and check them against the policy
+    Licenses {
+        /// Exit non-zero if any license is unknown or not allowed
+        #[arg(long)]
+        check: bool,
+    },
+    /// Check installed packages against the repo's security advisories
+    Audit,
+    /// Show or switch the provider of commands offered by several packages
+    Alternatives {
+        #[command(subcommand)]
+        action: Option<alternatives::AlternativesAction>,
+    },
+    /// Print the dependency graph of installed or repo packages
+    Graph {
+        /// Graph installed packages (the default)
+        #[arg(long, conflicts_with = "repo")]
+        installed: bool,
+        /// Graph every package in the repo, including conflicts
+        #[arg(long)]
+        repo: bool,
+        /// Emit Graphviz DOT instead of a text listing
+        #[arg(long)]
+        dot: bool,
+    },
+    /// Set up directories, config and shell integration on a fresh device
+    Init {
+        /// Also hook the PATH profile into the pie module's mkshrc
+        #[arg(long)]
+        profile: bool,
+    },
+    /// List files under the install root that no package owns
+    Orphans {
+        /// Delete the orphaned files
+        #[arg(long, conflicts_with = "adopt")]
+        delete: bool,
+        /// Record the orphaned files as part of an installed package
+        #[arg(long, value_name = "PACKAGE")]
+        adopt: Option<String>,
+    },
+    /// Write the list of explicitly installed packages and the config to a file
+    Export {
+        /// Output file, stdout if omitted
+        path: Option<String>,
+    },
+    /// Reinstall everything from an exported manifest, e.g. after a factory reset
+    Restore {
+        /// Manifest to restore, defaults to the automatic backup on internal storage
+        path: Option<String>,
+    },
+    /// Repair the environment after a system update, meant for boot scripts
+    PostOta,
+    /// Export the installed environment as a Magisk/KernelSU module
+    Module {
+        #[command(subcommand)]
+        action: module::ModuleAction,
+    },
+    /// Install andstore equivalents of packages installed elsewhere
+    Import {
+        /// Read the packages installed with Termux's apt/pkg
+        #[arg(long, required = true)]
+        from_termux: bool,
+    },
+    /// Create, list and roll back to snapshots of the whole environment
+    Snapshot {
+        #[command(subcommand)]
+        action: snapshot::SnapshotAction,
+    },
+    /// Print a shell completion script
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// List completion candidates, used by the completion scripts
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        #[arg(value_enum)]
+        kind: completion::CompletionKind,
+        #[arg(default_value = "")]
+        prefix: String,
+    },
+    /// Write roff man pages for pie and its subcommands
+    #[command(hide = true)]
+    Mangen {
+        #[arg(long, default_value = ".")]
+        out_dir: std::path::PathBuf,
+    },
+    /// Build a package archive from a staging directory and print its repo entry
+    Package(packaging::PackageOptions),
+    /// Serve a JSON-RPC API on a unix socket, for frontend apps
+    Daemon {
+        /// Socket to listen on, PIE_DATA/pie.sock by default
+        #[arg(long, value_name = "PATH")]
+        socket: Option<String>,
+        /// Let this uid connect besides root, e.g. the frontend app's
+        #[arg(long, value_name = "UID")]
+        allow_uid: Option<u32>,
+    },
+    /// Build a package and publish it with an updated index to the repo
+    Publish(publish::PublishOptions),
+    /// Copy the configured repos with all their archives into a directory that
+    /// can be served as a repo, re-running it syncs only what changed
+    Mirror {
+        /// Directory to mirror into, one subdirectory per repo
+        dir: std::path::PathBuf,
+        /// Only mirror builds for this ABI, can be repeated
+        #[arg(long)]
+        arch: Vec<String>,
+        /// Only mirror this repo, can be repeated
+        #[arg(long)]
+        repo: Vec<String>,
+    },
+    /// Train a shared compression dictionary from package staging directories
+    Dict {
+        /// Dictionary file to write
+        output: std::path::PathBuf,
+        /// Staging directories to sample
+        #[arg(required = true)]
+        dirs: Vec<std::path::PathBuf>,
+        /// Maximum dictionary size in bytes
+        #[arg(long, default_value_t = 112640)]
+        max_size: usize,
+    },
+    /// Manage long-running services shipped by packages
+    Service {
+        #[command(subcommand)]
+        action: service::ServiceAction,
+    },
+    /// Provision a device over adb from this machine
+    Remote {
+        /// adb serial of the device, needed when several are connected
+        #[arg(long, short = 's')]
+        serial: Option<String>,
+        #[command(subcommand)]
+        action: remote::RemoteAction,
+    },
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 struct Repo {
     packages: HashMap<String, Package>,
+    #[serde(default)]
+    advisories: Vec<audit::Advisory>,
+    #[serde(default)]
+    news: Vec<news::NewsEntry>,
+    // Named sets of packages installed together as @name
+    #[serde(default)]
+    groups: HashMap<String, Vec<String>>,
+    // Termux package name to its andstore equivalent, "" when there is none
+    #[serde(default)]
+    termux: HashMap<String, String>,
+    // Repo name to its index url and credentials
+    #[serde(skip)]
+    auth: HashMap<String, (String, net::Auth)>,
+}
+
+impl Repo {
+    fn auth_for(&self, repo: &str, url: &str) -> Result<Option<net::Auth>, PieError> {
+        match self.auth.get(repo) {
+            Some((index_url, auth)) => config::url_auth(index_url, Some(auth), url),
+            None => config::host_auth(url),
+        }
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 struct Package {
+    // Both are filled in while merging, defaults rather than skipped so they
+    // survive the binary index cache
+    #[serde(default)]
+    repo: String,
     version: String,
     min_api: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    license: Option<String>,
+    dependencies: Vec<String>,
+    // Dependencies that have to be fully installed, scripts included, before
+    // this package is extracted, e.g. tools its post-install script runs
+    #[serde(default)]
+    pre_depends: Vec<String>,
+    conflicts: Vec<String>,
+    // Obsolete or renamed packages this one supersedes
+    #[serde(default)]
+    replaces: Vec<String>,
+    // Shown after installing, e.g. setup steps the user has to do once
+    #[serde(default)]
+    notes: Option<String>,
+    #[serde(default)]
+    alternatives: Vec<alternatives::AlternativeSpec>,
+    #[serde(default)]
+    source: Option<source::SourceSpec>,
+    // Maintainer scripts by hook, paths inside the package
+    #[serde(default)]
+    scripts: HashMap<String, String>,
+    #[serde(default)]
+    boot_scripts: Vec<boot::BootScript>,
+    // Removing it would break the environment, e.g. pie's own runtime deps
+    #[serde(default)]
+    essential: bool,
+    // Installed self-contained under opt/<name>, see opt.rs
+    #[serde(default)]
+    opt: bool,
+    // Public binaries of an opt package, everything in its bin/ if empty
+    #[serde(default)]
+    bin: Vec<String>,
+    // A library that can be installed for the device's 32-bit ABI alongside
+    // the native one, see multilib.rs
+    #[serde(default)]
+    multilib: bool,
+    // Set on the 32-bit variant of a multilib package
+    #[serde(default)]
+    abi: Option<String>,
+    architectures: HashMap<String, Architecture>,
+    #[serde(default)]
+    services: HashMap<String, service::ServiceSpec>,
+    #[serde(default)]
+    subpackages: HashMap<String, Subpackage>,
+    // Set on entries expanded from another package's subpackages
+    #[serde(default)]
+    origin: Option<String>,
+}
+
+// Installable part of a split package, e.g. foo-dev. Version, license and API
+// level are shared with the package that defines it
+#[derive(Serialize, Deserialize)]
+struct Subpackage {
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
     dependencies: Vec<String>,
+    #[serde(default)]
     conflicts: Vec<String>,
+    #[serde(default)]
+    notes: Option<String>,
+    #[serde(default)]
+    alternatives: Vec<alternatives::AlternativeSpec>,
     architectures: HashMap<String, Architecture>,
 }
 
-#[derive(Deserialize)]
+fn expand_subpackage(origin: &str, parent: &Package, subpackage: Subpackage) -> Package {
+    Package {
+        repo: String::new(),
+        version: parent.version.clone(),
+        min_api: parent.min_api.clone(),
+        description: subpackage
+            .description
+            .or_else(|| parent.description.clone()),
+        license: parent.license.clone(),
+        dependencies: subpackage.dependencies,
+        pre_depends: Vec::new(),
+        conflicts: subpackage.conflicts,
+        replaces: Vec::new(),
+        notes: subpackage.notes,
+        alternatives: subpackage.alternatives,
+        source: parent.source.clone(),
+        scripts: HashMap::new(),
+        boot_scripts: Vec::new(),
+        essential: false,
+        opt: false,
+        bin: Vec::new(),
+        multilib: false,
+        abi: None,
+        architectures: subpackage.architectures,
+        services: HashMap::new(),
+        subpackages: HashMap::new(),
+        origin: Some(origin.to_string()),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 struct Architecture {
+    #[serde(default)]
     url: String,
+    // Alternative to url, resolved through the GitHub API at install time
+    #[serde(default)]
+    github: Option<github::ReleaseAsset>,
+    // Content identifier fetched through the configured IPFS gateway first,
+    // url or github then serve as the fallback
+    #[serde(default)]
+    ipfs: Option<String>,
     sha256: String,
     size: u64,
     uncompressed_size: u64,
     contents: Vec<String>,
+    #[serde(default)]
+    dictionary: Option<DictionaryRef>,
+    // Needed only on this ABI, on top of the package-wide dependencies
+    #[serde(default)]
+    dependencies: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize)]
+// Zstd dictionary shared by many small packages, fetched once and cached
+#[derive(Serialize, Deserialize, Clone)]
+struct DictionaryRef {
+    url: String,
+    sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 struct InstalledPackage {
     name: String,
     version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
     contents: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    services: HashMap<String, service::ServiceSpec>,
+    #[serde(default)]
+    overlay: bool,
+    #[serde(default)]
+    opt: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    abi: Option<String>,
+    // Packages installed before this was tracked are treated as requested
+    #[serde(default = "default_explicit")]
+    explicit: bool,
+    #[serde(default)]
+    repo: String,
+    #[serde(default)]
+    size: u64,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    hashes: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dependencies: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    conflicts: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    alternatives: Vec<alternatives::AlternativeSpec>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    scripts: HashMap<String, String>,
+    // Installed copies in the root manager's boot directories
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    boot_scripts: Vec<String>,
+    // Hex encoded extended attributes the archive set, checked by verify
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    xattrs: HashMap<String, HashMap<String, String>>,
+    // Hardlinked names and the name they share their data with
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    hardlinks: HashMap<String, String>,
+}
+
+fn default_explicit() -> bool {
+    true
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -87,33 +671,243 @@ fn get_separator() -> String {
 
 fn main() {
     let cli = Cli::parse();
-
-    if let Err(e) = run(cli) {
-        eprintln!("Error: {e}");
-        std::process::exit(1);
+    signal::install_handler();
+
+    let error_format = cli.error_format;
+    if let Err(mut e) = run(cli) {
+        if signal::interrupted() {
+            eprintln!("\nInterrupted, rolling back...");
+            if let Err(e) = journal::abort() {
+                e.report(error_format);
+            }
+            e = PieError::Interrupted;
+        }
+        e.report(error_format);
+        progress::error(&e);
+        notify::failed(&e.to_string());
+        std::process::exit(e.exit_code());
     }
 }
 
-fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+fn run(cli: Cli) -> Result<(), PieError> {
+    if let Some(destination) = &cli.progress_json {
+        progress::enable(destination)?;
+    }
+    if let Some(root) = &cli.target_root {
+        if !Path::new(root).is_dir() {
+            return Err(PieError::Other(format!(
+                "Target root '{root}' is not a directory"
+            )));
+        }
+        if matches!(cli.command, Commands::Remote { .. }) {
+            return Err("--target-root can't be combined with remote".into());
+        }
+        paths::set_host_root(root);
+        sysprop::use_host(root, cli.props.as_deref(), cli.abi.as_deref(), cli.api)?;
+    } else if matches!(cli.command, Commands::Remote { .. }) {
+        paths::set_host_root(&remote::workspace()?);
+    }
+    let config = config::load_config()?;
+    logcat::set_enabled(config.logcat);
+    glyph::set_ascii(cli.ascii || config.ascii);
+    config::set_force(config::ForceOptions {
+        remove: cli.force_remove || config.force.remove,
+        overwrite: cli.force_overwrite || config.force.overwrite,
+        arch: cli.force_arch || config.force.arch,
+        downgrade: cli.force_downgrade || config.force.downgrade,
+    });
+    config::set_index_pins(cli.index_hash.clone());
+    config::set_ignore(config.ignore.clone());
+    prompt::set_assumed_answer(if cli.assume_no {
+        Some(false)
+    } else if cli.assume_yes
+        || config.assume_yes
+        || matches!(
+            cli.command,
+            Commands::Upgrade {
+                unattended: true,
+                ..
+            } | Commands::Daemon { .. }
+        )
+    {
+        Some(true)
+    } else {
+        None
+    });
+
+    // Transactions then run in the daemon, which recovers its own journal
+    if let Some(socket) = cli.connect {
+        return daemon::client(&socket.unwrap_or_else(daemon::socket_path), cli.command);
+    }
+
+    if matches!(
+        cli.command,
+        Commands::Install { .. }
+            | Commands::Upgrade { .. }
+            | Commands::Uninstall { .. }
+            | Commands::Restore { .. }
+            | Commands::Rollback { .. }
+            | Commands::Import { .. }
+            | Commands::Orphans { .. }
+            | Commands::Snapshot { .. }
+    ) {
+        notify::set_transaction(true);
+        journal::recover()?;
+        gc::auto();
+    }
+
     match cli.command {
         Commands::Install {
-            package,
-            no_confirm,
-        } => install_package(&package, no_confirm)?,
-        Commands::Uninstall { package } => uninstall_package(&package)?,
+            packages,
+            overlay,
+            url,
+            sha256,
+            name,
+            version,
+            archives,
+            file,
+        } => {
+            if let Some(dir) = &archives {
+                prefetch::set_archive_dir(dir);
+            }
+            let overlay = overlay || config::overlay_default()?;
+            match (url, sha256, name, version) {
+                (Some(url), Some(sha256), Some(name), Some(version)) => {
+                    direct::install_url(&url, &sha256, &name, &version, overlay)?
+                }
+                _ => {
+                    let mut names = Vec::new();
+                    if let Some(file) = &file {
+                        names.extend(read_package_list(file)?);
+                    }
+                    for name in packages {
+                        if name == "-" {
+                            names.extend(read_package_list("-")?);
+                        } else {
+                            names.push(name);
+                        }
+                    }
+                    install_packages(&names, overlay)?
+                }
+            }
+        }
+        Commands::Uninstall { packages } => uninstall_packages(&packages)?,
         Commands::Update => update_repo()?,
-        Commands::Search { query } => search_packages(query.as_deref())?,
-        Commands::List => list_installed()?,
+        Commands::Upgrade {
+            unattended: true,
+            security_only,
+            ..
+        } => unattended::run_unattended(security_only)?,
+        Commands::Upgrade {
+            packages,
+            security_only,
+            show_files,
+            ..
+        } => upgrade::upgrade(&packages, security_only, show_files)?,
+        Commands::Rollback { package, version } => {
+            rollback::rollback(&package, version.as_deref())?
+        }
+        Commands::Search {
+            query,
+            regex,
+            contents,
+            installed,
+            not_installed,
+            format,
+        } => {
+            let installed_filter = match (installed, not_installed) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            };
+            match contents {
+                Some(fragment) => search_contents(&fragment, installed_filter, format.as_deref())?,
+                None => {
+                    search_packages(query.as_deref(), regex, installed_filter, format.as_deref())?
+                }
+            }
+        }
+        Commands::List { pattern, format } => {
+            list_installed(pattern.as_deref(), format.as_deref())?
+        }
+        Commands::Query { expression, json } => query::run_query(&expression, json)?,
+        Commands::Info { package, format } => package_info(&package, format.as_deref())?,
+        Commands::Check { libs } => {
+            if !libs {
+                return Err(PieError::Other("Nothing to check, pass --libs".to_string()));
+            }
+            if !libs::check_libraries()? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Lint { index, archives } => {
+            if !lint::lint(&index, archives.as_deref())? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Gc { dry_run } => gc::gc(dry_run)?,
+        Commands::Verify { packages } => {
+            if !verify::verify_packages(&packages)? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Outdated { format } => list_outdated(format.as_deref())?,
+        Commands::Status {
+            package,
+            min_version,
+        } => {
+            if !package_status(&package, min_version.as_deref())? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Why { package } => why::explain(&package)?,
+        Commands::Source { package, output } => source::fetch_source(&package, &output)?,
+        Commands::Licenses { check } => {
+            if !licenses::license_report()? && check {
+                std::process::exit(1);
+            }
+        }
+        Commands::Alternatives { action } => alternatives::run_alternatives(action)?,
+        Commands::Audit => {
+            if !audit::audit()? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Graph { repo, dot, .. } => graph::print_graph(repo, dot)?,
+        Commands::Init { profile } => init::init(profile)?,
+        Commands::Orphans { delete, adopt } => orphans::scan_orphans(delete, adopt.as_deref())?,
+        Commands::Service { action } => service::run_service(action)?,
+        Commands::Remote { serial, action } => remote::run_remote(serial.as_deref(), action)?,
+        Commands::Package(options) => packaging::build_package(&options)?,
+        Commands::Publish(options) => publish::publish(&options)?,
+        Commands::Daemon { socket, allow_uid } => {
+            daemon::serve(&socket.unwrap_or_else(daemon::socket_path), allow_uid)?
+        }
+        Commands::Mirror { dir, arch, repo } => mirror::mirror(&dir, &arch, &repo)?,
+        Commands::Dict {
+            output,
+            dirs,
+            max_size,
+        } => packaging::train_dictionary(&output, &dirs, max_size)?,
+        Commands::Completions { shell } => completion::print_completions(shell),
+        Commands::Mangen { out_dir } => mangen::generate_man_pages(&out_dir)?,
+        Commands::Complete { kind, prefix } => completion::complete(kind, &prefix)?,
+        Commands::Snapshot { action } => snapshot::run_snapshot(action)?,
+        Commands::Export { path } => restore::export_manifest(path.as_deref())?,
+        Commands::Restore { path } => restore::restore(path.as_deref())?,
+        Commands::Import { .. } => import::import_termux()?,
+        Commands::Module { action } => module::run_module(action)?,
+        Commands::PostOta => {
+            if !post_ota::post_ota()? {
+                std::process::exit(1);
+            }
+        }
     }
     Ok(())
 }
 
-fn get_arch() -> Result<String, Box<dyn std::error::Error>> {
-    let output = std::process::Command::new("getprop")
-        .args(["ro.product.cpu.abi"])
-        .output()?;
-
-    let arch = String::from_utf8(output.stdout)?.trim().to_string();
+fn get_arch() -> Result<String, PieError> {
+    let arch = sysprop::get("ro.product.cpu.abi").unwrap_or_default();
 
     match arch.as_str() {
         "arm64-v8a" | "armeabi-v7a" | "x86" | "x86_64" | "riscv64" => Ok(arch),
@@ -121,24 +915,225 @@ fn get_arch() -> Result<String, Box<dyn std::error::Error>> {
     }
 }
 
-fn get_api_level() -> Result<u32, Box<dyn std::error::Error>> {
-    let output = std::process::Command::new("getprop")
-        .args(["ro.build.version.sdk"])
-        .output()?;
-
-    let binding = String::from_utf8(output.stdout)?;
-    let api_str = binding.trim();
+fn get_api_level() -> Result<u32, PieError> {
+    let api_str = sysprop::get("ro.build.version.sdk").unwrap_or_default();
     let api_level: u32 = api_str.parse()?;
     Ok(api_level)
 }
 
-fn fetch_repo() -> Result<Repo, Box<dyn std::error::Error>> {
-    let response = reqwest::blocking::get(REPO_URL)?;
-    let repo: Repo = response.json()?;
+// Raw index documents of all configured repos, in config order
+// One thread per repo, so startup waits for the slowest repo rather than
+// for all of them in turn
+fn fetch_indices() -> Result<Vec<(RepoConfig, Vec<u8>)>, PieError> {
+    let config = config::load_config()?;
+
+    let repo_count = config.repos.len();
+    let contents: Vec<Result<Vec<u8>, PieError>> = thread::scope(|scope| {
+        let handles: Vec<_> = config
+            .repos
+            .iter()
+            .map(|repo_config| scope.spawn(move || fetch_index(repo_config, repo_count)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(PieError::Other("Index fetch panicked".to_string())))
+            })
+            .collect()
+    });
+
+    // The first failing repo in config order is reported
+    let mut indices = Vec::new();
+    for (repo_config, content) in config.repos.into_iter().zip(contents) {
+        indices.push((repo_config, content?));
+    }
+    Ok(indices)
+}
+
+fn fetch_index(repo_config: &RepoConfig, repo_count: usize) -> Result<Vec<u8>, PieError> {
+    let auth = repo_config.auth()?;
+    let content = net::fetch_bytes(&repo_config.url, auth.as_ref())?;
+    if let Some(expected) = config::index_pin(repo_config, repo_count)? {
+        let actual = hex::encode(Sha256::digest(&content));
+        if actual != expected {
+            return Err(PieError::Checksum(format!(
+                "the index of repo '{}' (pinned to {expected}, fetched {actual})",
+                repo_config.name
+            )));
+        }
+    }
+    Ok(content)
+}
+
+// Parsing a large index is slow on phone CPUs, so the merged result is cached
+// in binary form for as long as the indices it came from stay the same
+fn merge_indices(indices: &[(RepoConfig, Vec<u8>)]) -> Result<Repo, PieError> {
+    let pins = config::load_config()?.pins;
+    let key = cache::repo_key(indices, &pins, get_arch().ok().as_deref());
+    if let Some(mut repo) = cache::load_repo(&key) {
+        for (repo_config, _) in indices {
+            if let Some(auth) = repo_config.auth()? {
+                repo.auth
+                    .insert(repo_config.name.clone(), (repo_config.url.clone(), auth));
+            }
+        }
+        return Ok(repo);
+    }
+
+    let repo = parse_indices(indices, &pins)?;
+    cache::save_repo(&key, &repo);
     Ok(repo)
 }
 
-fn get_installed_packages() -> Result<InstalledPackages, Box<dyn std::error::Error>> {
+// Indices are merged by priority, then in config order. The first repo
+// offering a package wins, unless a pin rules its copy out
+fn parse_indices(
+    indices: &[(RepoConfig, Vec<u8>)],
+    pins: &BTreeMap<String, config::PackagePin>,
+) -> Result<Repo, PieError> {
+    let mut merged = Repo {
+        packages: HashMap::new(),
+        advisories: Vec::new(),
+        news: Vec::new(),
+        groups: HashMap::new(),
+        termux: HashMap::new(),
+        auth: HashMap::new(),
+    };
+
+    let device_arch = get_arch().ok();
+
+    // Parsing is the expensive part, merging has to follow priorities
+    let parsed: Vec<Result<Repo, serde_json::Error>> = indices
+        .par_iter()
+        .map(|(_, content)| parse_index(content))
+        .collect();
+    let mut ordered: Vec<(&RepoConfig, Result<Repo, serde_json::Error>)> = indices
+        .iter()
+        .map(|(repo_config, _)| repo_config)
+        .zip(parsed)
+        .collect();
+    ordered.sort_by_key(|(repo_config, _)| std::cmp::Reverse(repo_config.priority));
+    let mut pinned_out: HashSet<String> = HashSet::new();
+
+    for (repo_config, repo) in ordered {
+        let repo = repo.map_err(|e| {
+            PieError::Schema(format!(
+                "Invalid index for repo '{}': {e}",
+                repo_config.name
+            ))
+        })?;
+        merged.advisories.extend(repo.advisories);
+        for (group, members) in repo.groups {
+            merged.groups.entry(group).or_insert(members);
+        }
+        for (termux_name, name) in repo.termux {
+            merged.termux.entry(termux_name).or_insert(name);
+        }
+        merged.news.extend(repo.news.into_iter().map(|mut entry| {
+            entry.repo = repo_config.name.clone();
+            entry
+        }));
+
+        let mut packages = Vec::new();
+        for (name, mut package) in repo.packages {
+            package.origin = None;
+            for (sub_name, subpackage) in std::mem::take(&mut package.subpackages) {
+                packages.push((sub_name, expand_subpackage(&name, &package, subpackage)));
+            }
+            packages.push((name, package));
+        }
+
+        for (name, mut package) in packages {
+            if merged.packages.contains_key(&name) {
+                continue;
+            }
+            if let Some(pin) = pins.get(&name) {
+                if !pin.allows(&repo_config.name, &package.version) {
+                    pinned_out.insert(name);
+                    continue;
+                }
+            }
+            package.repo = repo_config.name.clone();
+            if let Some(url) = package
+                .source
+                .as_mut()
+                .and_then(|source| source.url.as_mut())
+            {
+                *url = net::resolve_url(&repo_config.url, url);
+            }
+            // Fold the device ABI's extra dependencies in so the resolver and
+            // everything else only ever sees the effective list
+            if let Some(architecture) = device_arch
+                .as_ref()
+                .and_then(|arch| package.architectures.get(arch))
+            {
+                for dep in &architecture.dependencies {
+                    if !package.dependencies.contains(dep) {
+                        package.dependencies.push(dep.clone());
+                    }
+                }
+            }
+            for (arch, architecture) in package.architectures.iter_mut() {
+                if architecture.url.is_empty()
+                    && architecture.github.is_none()
+                    && architecture.ipfs.is_none()
+                {
+                    return Err(PieError::Schema(format!(
+                        "Package '{name}' in repo '{}' has no url, github or ipfs source for {arch}",
+                        repo_config.name
+                    )));
+                }
+                if !architecture.url.is_empty() {
+                    architecture.url = net::resolve_url(&repo_config.url, &architecture.url);
+                }
+                if let Some(dictionary) = &mut architecture.dictionary {
+                    dictionary.url = net::resolve_url(&repo_config.url, &dictionary.url);
+                }
+            }
+            merged.packages.insert(name, package);
+        }
+
+        if let Some(auth) = repo_config.auth()? {
+            merged
+                .auth
+                .insert(repo_config.name.clone(), (repo_config.url.clone(), auth));
+        }
+    }
+
+    if let Some(arch) = &device_arch {
+        multilib::expand(&mut merged.packages, arch);
+    }
+
+    let mut unavailable: Vec<&String> = pinned_out
+        .iter()
+        .filter(|name| !merged.packages.contains_key(*name))
+        .collect();
+    unavailable.sort();
+    for name in unavailable {
+        eprintln!("Warning: no repo offers {name} as its pin requires, it is unavailable");
+    }
+
+    Ok(merged)
+}
+
+// simd-json parses in place and is several times faster on indices listing
+// thousands of files. Its errors say little, so a failing index is parsed
+// again with serde_json for a useful message
+fn parse_index(content: &[u8]) -> Result<Repo, serde_json::Error> {
+    let mut buffer = content.to_vec();
+    match simd_json::serde::from_slice(&mut buffer) {
+        Ok(repo) => Ok(repo),
+        Err(_) => serde_json::from_slice(content),
+    }
+}
+
+fn fetch_repo() -> Result<Repo, PieError> {
+    merge_indices(&fetch_indices()?)
+}
+
+fn get_installed_packages() -> Result<InstalledPackages, PieError> {
     let installed_file = format!("{PIE_DATA}/installed.json");
 
     if !Path::new(&installed_file).exists() {
@@ -146,38 +1141,34 @@ fn get_installed_packages() -> Result<InstalledPackages, Box<dyn std::error::Err
     }
 
     let content = fs::read_to_string(&installed_file)?;
-    let installed: InstalledPackages = serde_json::from_str(&content)?;
-    Ok(installed)
-}
-
-fn check_api_compatibility(package: &Package) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(min_api_str) = &package.min_api {
-        if min_api_str.trim().is_empty() {
-            return Ok(());
-        }
-        
-        let device_api = get_api_level()?;
-        let min_api: u32 = min_api_str.parse()
-            .map_err(|_| format!("Invalid min_api format: '{}'", min_api_str))?;
-        
-        if device_api < min_api {
-            return Err(format!(
-                "Package requires API level {} but device is API level {}", 
-                min_api, device_api
-            ).into());
-        }
+    match serde_json::from_str(&content) {
+        Ok(installed) => Ok(installed),
+        Err(e) => repair::recover_database(&installed_file, e),
     }
-    Ok(())
- }
+}
 
-fn save_installed_packages(
-    installed: &InstalledPackages,
-) -> Result<(), Box<dyn std::error::Error>> {
-    fs::create_dir_all(PIE_DATA)?;
+// Written to a temp file, synced and renamed over the old copy, which is kept
+// as installed.json.bak. A power loss leaves either the old or the new state
+fn save_installed_packages(installed: &InstalledPackages) -> Result<(), PieError> {
+    fs::create_dir_all(&PIE_DATA)?;
     let installed_file = format!("{PIE_DATA}/installed.json");
+    let temp_file = format!("{installed_file}.tmp");
     let content = serde_json::to_string_pretty(installed)?;
-    fs::write(&installed_file, content)?;
-    Ok(())
+
+    let mut file = fs::File::create(&temp_file)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    // The backup goes the same way, recovery falls back to it
+    if Path::new(&installed_file).exists() {
+        let backup_temp = format!("{installed_file}.bak.tmp");
+        fs::copy(&installed_file, &backup_temp)?;
+        fs::File::open(&backup_temp)?.sync_all()?;
+        fs::rename(&backup_temp, format!("{installed_file}.bak"))?;
+    }
+    fs::rename(&temp_file, &installed_file)?;
+    fs::File::open(&PIE_DATA)?.sync_all()?;
+    restore::backup_manifest(installed);
+    alternatives::refresh(installed)
 }
 
 fn find_package_by_content(repo: &Repo, query: &str) -> Option<String> {
@@ -198,6 +1189,23 @@ fn find_package_by_content(repo: &Repo, query: &str) -> Option<String> {
     None
 }
 
+// Plain bytes or a number with a unit, in the same 1024 steps format_size uses
+fn parse_size(value: &str) -> Option<u64> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let multiplier = match unit.to_uppercase().as_str() {
+        "" | "B" => 1u64,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return None,
+    };
+    Some((number * multiplier as f64) as u64)
+}
+
 fn format_size(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB"];
     let mut size = bytes as f64;
@@ -215,11 +1223,41 @@ fn format_size(bytes: u64) -> String {
     }
 }
 
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// UTC "YYYY-MM-DD HH:MM:SS", days to civil date conversion from Howard Hinnant
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
 fn resolve_dependencies(
     repo: &Repo,
     package_name: &str,
     installed: &InstalledPackages,
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+) -> Result<Vec<String>, PieError> {
     let mut to_install = Vec::new();
     let mut visited = HashSet::new();
 
@@ -229,7 +1267,7 @@ fn resolve_dependencies(
         installed: &InstalledPackages,
         to_install: &mut Vec<String>,
         visited: &mut HashSet<String>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), PieError> {
         if visited.contains(pkg_name) {
             return Ok(());
         }
@@ -238,9 +1276,9 @@ fn resolve_dependencies(
         let package = repo
             .packages
             .get(pkg_name)
-            .ok_or(format!("Dependency '{pkg_name}' not found"))?;
+            .ok_or_else(|| PieError::Resolution(format!("Dependency '{pkg_name}' not found")))?;
 
-        for dep in &package.dependencies {
+        for dep in package.dependencies.iter().chain(&package.pre_depends) {
             if !installed.packages.contains_key(dep) && !to_install.contains(dep) {
                 resolve_recursive(repo, dep, installed, to_install, visited)?;
                 to_install.push(dep.clone());
@@ -254,210 +1292,832 @@ fn resolve_dependencies(
     Ok(to_install)
 }
 
-fn handle_conflicts(
-    package: &Package,
-    installed: &mut InstalledPackages,
-    no_confirm: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut conflicts_to_remove = Vec::new();
-
-    for conflict in &package.conflicts {
-        if installed.packages.contains_key(conflict) {
-            conflicts_to_remove.push(conflict.clone());
+// Moves pre-dependencies in the transaction ahead of the packages needing
+// them, keeping the resolver's order otherwise
+fn order_transaction(repo: &Repo, transaction: Vec<String>) -> Result<Vec<String>, PieError> {
+    fn visit(
+        repo: &Repo,
+        name: &str,
+        transaction: &[String],
+        ordered: &mut Vec<String>,
+        path: &mut Vec<String>,
+    ) -> Result<(), PieError> {
+        if ordered.iter().any(|done| done == name) {
+            return Ok(());
         }
-    }
-
-    if !conflicts_to_remove.is_empty() {
-        println!("\n{}", get_separator());
-        println!("CONFLICT RESOLUTION");
-        println!("{}", get_separator());
-        println!("The following packages conflict and will be removed:");
-        for conflict in &conflicts_to_remove {
-            if let Some(pkg) = installed.packages.get(conflict) {
-                println!("  - {} v{}", conflict, pkg.version);
+        if path.iter().any(|pending| pending == name) {
+            path.push(name.to_string());
+            return Err(PieError::Resolution(format!(
+                "Pre-dependency cycle: {}",
+                path.join(&format!(" {ARROW} "))
+            )));
+        }
+        path.push(name.to_string());
+        if let Some(package) = repo.packages.get(name) {
+            for pre in &package.pre_depends {
+                if transaction.contains(pre) {
+                    visit(repo, pre, transaction, ordered, path)?;
+                }
             }
         }
+        path.pop();
+        ordered.push(name.to_string());
+        Ok(())
+    }
 
-        if !no_confirm {
-            print!("\nContinue? [Y/n]: ");
-            io::stdout().flush()?;
+    let mut ordered = Vec::new();
+    for name in &transaction {
+        visit(repo, name, &transaction, &mut ordered, &mut Vec::new())?;
+    }
+    Ok(ordered)
+}
 
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
-            let input = input.trim().to_lowercase();
+// Installed packages that conflict with anything entering the transaction,
+// paired with the package that conflicts with them. Packages in the
+// transaction can't be resolved against each other, that is an error
+fn find_conflicts(
+    repo: &Repo,
+    transaction: &[String],
+    installed: &InstalledPackages,
+) -> Result<Vec<(String, String)>, PieError> {
+    let mut conflicts: Vec<(String, String)> = Vec::new();
+
+    for name in transaction {
+        let package = &repo.packages[name];
+        for conflict in &package.conflicts {
+            if transaction.contains(conflict) {
+                return Err(PieError::Resolution(format!(
+                    "'{name}' conflicts with '{conflict}', both are part of this transaction"
+                )));
+            }
+            // Replaced packages are migrated after the install instead
+            if installed.packages.contains_key(conflict)
+                && !package.replaces.contains(conflict)
+                && !conflicts.iter().any(|(c, _)| c == conflict)
+            {
+                conflicts.push((conflict.clone(), name.clone()));
+            }
+        }
 
-            if input == "n" || input == "no" {
-                return Err("Installation cancelled due to conflicts".into());
+        // The reverse direction, installed packages declaring a conflict with
+        // this one. Entries from before conflicts were recorded use the repo
+        for pkg in installed.packages.values() {
+            let declared = if pkg.conflicts.is_empty() {
+                repo.packages
+                    .get(&pkg.name)
+                    .map_or(&pkg.conflicts, |available| &available.conflicts)
+            } else {
+                &pkg.conflicts
+            };
+            if declared.contains(name)
+                && !transaction.contains(&pkg.name)
+                && !package.replaces.contains(&pkg.name)
+                && !conflicts.iter().any(|(c, _)| *c == pkg.name)
+            {
+                conflicts.push((pkg.name.clone(), name.clone()));
             }
         }
+    }
+    conflicts.sort();
+
+    Ok(conflicts)
+}
 
-        for conflict in conflicts_to_remove {
-            println!("Removing conflicting package: {conflict}");
-            remove_package_files(&conflict, installed)?;
-            installed.packages.remove(&conflict);
+fn print_conflicts(conflicts: &[(String, String)], installed: &InstalledPackages) {
+    if conflicts.is_empty() {
+        return;
+    }
+    println!("Conflicting packages to remove ({}):", conflicts.len());
+    for (conflict, by) in conflicts {
+        if let Some(pkg) = installed.packages.get(conflict) {
+            println!(
+                "  {CROSS} {} v{} (conflicts with {})",
+                conflict, pkg.version, by
+            );
         }
-        println!();
     }
+}
+
+fn remove_conflicts(
+    repo: &Repo,
+    conflicts: &[(String, String)],
+    installed: &mut InstalledPackages,
+) -> Result<(), PieError> {
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+
+    let names: Vec<String> = conflicts.iter().map(|(name, _)| name.clone()).collect();
+    if !confirm_essential_removal(repo, &names)? {
+        return Err(PieError::Cancelled(
+            "Cancelled, essential packages would be removed".to_string(),
+        ));
+    }
+    let hook_packages = hook_packages_installed(&names, installed);
+    hooks::run_hooks("pre-remove", &hook_packages)?;
+
+    for conflict in names {
+        println!("Removing conflicting package: {conflict}");
+        remove_package_files(&conflict, installed)?;
+        installed.packages.remove(&conflict);
+    }
+    save_installed_packages(installed)?;
+
+    hooks::run_hooks("post-remove", &hook_packages)?;
+    println!();
 
     Ok(())
 }
 
-fn remove_package_files(
-    name: &str,
+// Asked twice, the second time typed out so -y alone never removes them
+fn confirm_essential_removal(repo: &Repo, names: &[String]) -> Result<bool, PieError> {
+    let essential: Vec<&str> = names
+        .iter()
+        .filter(|name| repo.packages.get(*name).is_some_and(|pkg| pkg.essential))
+        .map(|name| name.as_str())
+        .collect();
+    if essential.is_empty() {
+        return Ok(true);
+    }
+
+    let list = essential.join(" ");
+    if config::force().remove {
+        println!("Removing essential packages {list} (--force-remove)");
+        return Ok(true);
+    }
+    println!("\nEssential packages would be removed: {list}");
+    println!("pie and installed packages may stop working without them.");
+    if prompt::assumed_answer().is_some() || !prompt::interactive() {
+        return Err(PieError::Cancelled(format!(
+            "Refusing to remove essential packages ({list}) without confirmation"
+        )));
+    }
+    Ok(prompt::confirm("Remove them anyway?", false)?
+        && prompt::confirm_typed("Type the package names to confirm", &list)?)
+}
+
+fn hook_packages_installed(
+    names: &[String],
     installed: &InstalledPackages,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Vec<hooks::HookPackage> {
+    names
+        .iter()
+        .filter_map(|name| installed.packages.get(name))
+        .map(|pkg| hooks::HookPackage {
+            name: pkg.name.clone(),
+            version: pkg.version.clone(),
+        })
+        .collect()
+}
+
+fn hook_packages_repo(names: &[String], repo: &Repo) -> Vec<hooks::HookPackage> {
+    names
+        .iter()
+        .filter_map(|name| repo.packages.get(name).map(|pkg| (name, pkg)))
+        .map(|(name, pkg)| hooks::HookPackage {
+            name: name.clone(),
+            version: pkg.version.clone(),
+        })
+        .collect()
+}
+
+// Where the files of a package end up relative to its root
+fn package_contents(
+    name: &str,
+    package: &Package,
+    architecture: &Architecture,
+    overlay: bool,
+) -> Vec<String> {
+    if package.abi.is_some() {
+        multilib::contents(architecture)
+    } else if package.opt && !overlay {
+        opt::contents(name, package, architecture)
+    } else {
+        architecture.contents.clone()
+    }
+}
+
+fn package_root(overlay: bool) -> String {
+    if overlay {
+        overlay::overlay_root()
+    } else {
+        ANDSTORE_ROOT.to_string()
+    }
+}
+
+fn remove_package_files(name: &str, installed: &InstalledPackages) -> Result<(), PieError> {
     if let Some(package) = installed.packages.get(name) {
+        boot::remove_scripts(&package.boot_scripts)?;
+        let root = package_root(package.overlay);
         for file_path in &package.contents {
-            let full_path = format!("{ANDSTORE_ROOT}/{file_path}");
-            if Path::new(&full_path).exists() {
+            let full_path = format!("{root}/{file_path}");
+            // Links into an opt prefix dangle once its files are gone
+            if fs::symlink_metadata(&full_path).is_ok() {
                 fs::remove_file(&full_path)?;
             }
         }
+        let prefix = format!("{root}/{}", opt::prefix(name));
+        if package.opt && Path::new(&prefix).is_dir() {
+            fs::remove_dir_all(&prefix)?;
+        }
     }
     Ok(())
 }
 
-fn install_single_package(
-    repo: &Repo,
+fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn package_architecture<'a>(
+    repo: &'a Repo,
     name: &str,
-    installed: &mut InstalledPackages,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(&'a Package, &'a Architecture), PieError> {
     let package = repo
         .packages
         .get(name)
-        .ok_or(format!("Package '{name}' not found"))?;
+        .ok_or_else(|| PieError::Resolution(format!("Package '{name}' not found")))?;
 
     let arch = get_arch()?;
-    let architecture = package.architectures.get(&arch).ok_or(format!(
-        "Package '{name}' not available for architecture '{arch}'"
-    ))?;
+    let architecture = package
+        .architectures
+        .get(&arch)
+        .or_else(|| {
+            config::force()
+                .arch
+                .then(|| foreign_architecture(package, &arch))
+                .flatten()
+        })
+        .ok_or_else(|| {
+            let mut message = format!("Package '{name}' not available for architecture '{arch}'");
+            for suggestion in explain::no_build(repo, name, &arch) {
+                message.push_str(&format!("\n  {ARROW} {suggestion}"));
+            }
+            PieError::Resolution(message)
+        })?;
 
-    // Show package info before downloading
-    println!("Package: {} v{}", name, package.version);
-    println!(
-        "Download size: {} | Installed size: {}",
-        format_size(architecture.size),
-        format_size(architecture.uncompressed_size)
-    );
+    Ok((package, architecture))
+}
 
-    // Download package
-    print!("Downloading {name}... ");
-    io::stdout().flush()?;
-    let response = reqwest::blocking::get(&architecture.url)?;
-    let content = response.bytes()?;
-    println!("✓");
+// The 32-bit ABI a 64-bit device also runs natively
+fn compat_abi(arch: &str) -> Option<&'static str> {
+    match arch {
+        "arm64-v8a" => Some("armeabi-v7a"),
+        "x86_64" => Some("x86"),
+        _ => None,
+    }
+}
 
-    // Verify checksum
-    print!("Verifying checksum... ");
-    io::stdout().flush()?;
-    let mut hasher = Sha256::new();
-    hasher.update(&content);
-    let hash = hex::encode(hasher.finalize());
+// With --force-arch: a 32-bit build the device can still run comes first,
+// then any other. Whether it works is up to the user
+fn foreign_architecture<'a>(package: &'a Package, arch: &str) -> Option<&'a Architecture> {
+    if let Some(architecture) = compat_abi(arch).and_then(|abi| package.architectures.get(abi)) {
+        return Some(architecture);
+    }
+    let mut abis: Vec<&String> = package.architectures.keys().collect();
+    abis.sort();
+    abis.first().map(|abi| &package.architectures[*abi])
+}
 
-    if hash != architecture.sha256 {
-        println!("✗");
-        return Err(format!("Checksum verification failed for package '{name}'").into());
+// Files of the transaction that another installed package owns. Packages
+// being upgraded, replaced or removed for a conflict give theirs up
+fn find_file_conflicts(
+    repo: &Repo,
+    transaction: &[String],
+    installed: &InstalledPackages,
+    conflicts: &[(String, String)],
+    overlay: bool,
+) -> Result<Vec<(String, String, String)>, PieError> {
+    let leaving: HashSet<&str> = transaction
+        .iter()
+        .chain(conflicts.iter().map(|(name, _)| name))
+        .chain(
+            transaction
+                .iter()
+                .filter_map(|name| repo.packages.get(name))
+                .flat_map(|package| &package.replaces),
+        )
+        .map(String::as_str)
+        .collect();
+
+    let mut owners: HashMap<(bool, &str), &str> = HashMap::new();
+    for (name, package) in &installed.packages {
+        if leaving.contains(name.as_str()) {
+            continue;
+        }
+        for file in &package.contents {
+            owners.insert((package.overlay, file.as_str()), name.as_str());
+        }
     }
-    println!("✓");
 
-    // Create temp file and extract
-    print!("Extracting {name}... ");
-    io::stdout().flush()?;
-    let temp_file = tempfile::NamedTempFile::new()?;
-    fs::write(temp_file.path(), &content)?;
+    let mut found = Vec::new();
+    for name in transaction {
+        let (package, architecture) = package_architecture(repo, name)?;
+        let overlay = installed
+            .packages
+            .get(name)
+            .map_or(overlay, |package| package.overlay);
+        for file in &package_contents(name, package, architecture, overlay) {
+            if let Some(owner) = owners.get(&(overlay, file.as_str())) {
+                found.push((file.clone(), owner.to_string(), name.clone()));
+            }
+        }
+    }
+    Ok(found)
+}
 
-    // Extract package
-    let file = fs::File::open(temp_file.path())?;
-    let decoder = Decoder::new(file)?;
-    let mut archive = Archive::new(decoder);
+fn check_file_conflicts(found: &[(String, String, String)]) -> Result<(), PieError> {
+    if found.is_empty() {
+        return Ok(());
+    }
 
-    fs::create_dir_all(ANDSTORE_ROOT)?;
-    archive.unpack(ANDSTORE_ROOT)?;
-    println!("✓");
+    println!("\nFiles owned by other installed packages:");
+    for (file, owner, name) in found.iter().take(20) {
+        println!("  {BULLET} {file} ({owner}, also in {name})");
+    }
+    if found.len() > 20 {
+        println!("  ... and {} more", found.len() - 20);
+    }
+    if config::force().overwrite {
+        println!("Overwriting them (--force-overwrite)");
+        return Ok(());
+    }
+    Err(PieError::Resolution(format!(
+        "{} files belong to other packages, --force-overwrite installs them anyway",
+        found.len()
+    )))
+}
+
+// Dictionaries are cached by hash, so packages sharing one download it once
+fn load_dictionary(
+    repo: &Repo,
+    package: &Package,
+    dictionary: &DictionaryRef,
+) -> Result<Vec<u8>, PieError> {
+    let path = format!("{PIE_DATA}/cache/dict/{}", dictionary.sha256);
+    if let Ok(content) = fs::read(&path) {
+        if hex::encode(Sha256::digest(&content)) == dictionary.sha256 {
+            return Ok(content);
+        }
+    }
+
+    let auth = repo.auth_for(&package.repo, &dictionary.url)?;
+    let content = net::fetch_bytes(&dictionary.url, auth.as_ref())?;
+    if hex::encode(Sha256::digest(&content)) != dictionary.sha256 {
+        return Err(PieError::Checksum(format!("dictionary {}", dictionary.url)));
+    }
+    fs::create_dir_all(format!("{PIE_DATA}/cache/dict"))?;
+    fs::write(&path, &content)?;
+    Ok(content)
+}
+
+// Hashes are recorded so `pie verify` can later detect modified files
+fn hash_contents(root: &str, contents: &[String]) -> HashMap<String, String> {
+    contents
+        .par_iter()
+        .filter_map(|file| {
+            let path = Path::new(root).join(file);
+            let meta = fs::symlink_metadata(&path).ok()?;
+            if !meta.is_file() {
+                return None;
+            }
+            sha256_file(&path).ok().map(|hash| (file.clone(), hash))
+        })
+        .collect()
+}
+
+fn install_single_package(
+    repo: &Repo,
+    name: &str,
+    installed: &mut InstalledPackages,
+    overlay: bool,
+    mut explicit: bool,
+) -> Result<(), PieError> {
+    let (package, architecture) = package_architecture(repo, name)?;
+
+    // Show package info before extracting
+    println!("Package: {} v{}", name, package.version);
+    let arch = get_arch()?;
+    if !package.architectures.contains_key(&arch) {
+        println!("Warning: no {arch} build of {name}, installing another ABI's (--force-arch)");
+    }
+    println!(
+        "Download size: {} | Installed size: {}",
+        format_size(architecture.size),
+        format_size(architecture.uncompressed_size)
+    );
+
+    let dictionary = match &architecture.dictionary {
+        Some(dictionary) => Some(load_dictionary(repo, package, dictionary)?),
+        None => None,
+    };
+
+    let root = package_root(overlay);
+    if overlay {
+        overlay::ensure_module()?;
+    }
+    let opt = package.opt && !overlay;
+    fs::create_dir_all(&root)?;
+    // Multilib variants are unpacked aside, only their libraries are kept
+    let scratch = match package.abi {
+        Some(_) => Some(
+            tempfile::Builder::new()
+                .prefix(extract::STAGING_PREFIX)
+                .tempdir_in(&root)?,
+        ),
+        None => None,
+    };
+    let package_dir = if opt {
+        format!("{root}/{}", opt::prefix(name))
+    } else if let Some(scratch) = &scratch {
+        scratch.path().to_string_lossy().into_owned()
+    } else {
+        root.clone()
+    };
+    fs::create_dir_all(&package_dir)?;
+
+    // Download, verify and extract in a single pass
+    print!("Downloading and extracting {name}... ");
+    io::stdout().flush()?;
+    progress::open_line();
+    let reader = match prefetch::take(name) {
+        Some(reader) => reader,
+        None => prefetch::Source::new(repo, package, architecture)?.open()?,
+    };
+    let reader = progress::track(name, architecture.size, reader);
+    let (reader, pending) = cache::tee_archive(name, reader);
+    let extracted = match extract::extract_verified(
+        name,
+        reader,
+        dictionary.as_deref(),
+        Path::new(&package_dir),
+        &architecture.sha256,
+    ) {
+        Ok(extracted) => extracted,
+        Err(e) => {
+            println!("{CROSS}");
+            return Err(e);
+        }
+    };
+    println!("{CHECK}");
+
+    let contents = package_contents(name, package, architecture, overlay);
+    let (mut xattrs, mut hardlinks) = (extracted.xattrs, extracted.hardlinks);
+    if opt {
+        let prefix = opt::prefix(name);
+        xattrs = xattrs
+            .into_iter()
+            .map(|(file, attrs)| (format!("{prefix}/{file}"), attrs))
+            .collect();
+        hardlinks = hardlinks
+            .into_iter()
+            .map(|(file, target)| (format!("{prefix}/{file}"), format!("{prefix}/{target}")))
+            .collect();
+        opt::link_binaries(name, &contents, &root)?;
+    }
+    if let Some(scratch) = &scratch {
+        multilib::relocate(scratch.path(), &root, &mut xattrs, &mut hardlinks)?;
+    }
+
+    let replaced: Vec<String> = package
+        .replaces
+        .iter()
+        .filter(|old| old.as_str() != name && installed.packages.contains_key(*old))
+        .cloned()
+        .collect();
+    if !replaced.is_empty() {
+        explicit |= replace_packages(&replaced, &contents, installed)?;
+    }
 
     // Update installed packages
+    let boot_scripts = boot::install_scripts(
+        name,
+        &package.boot_scripts,
+        &architecture.contents,
+        &package_dir,
+    )?;
+    if let Some(old) = installed.packages.get(name) {
+        boot::remove_scripts(
+            old.boot_scripts
+                .iter()
+                .filter(|script| !boot_scripts.contains(script)),
+        )?;
+    }
+
     let installed_package = InstalledPackage {
         name: name.to_string(),
         version: package.version.clone(),
-        contents: architecture.contents.clone(),
+        description: package.description.clone(),
+        hashes: hash_contents(&root, &contents),
+        contents,
+        services: package.services.clone(),
+        overlay,
+        opt,
+        abi: package.abi.clone(),
+        explicit,
+        repo: package.repo.clone(),
+        size: architecture.uncompressed_size,
+        dependencies: package.dependencies.clone(),
+        conflicts: package.conflicts.clone(),
+        alternatives: package.alternatives.clone(),
+        scripts: package.scripts.clone(),
+        boot_scripts,
+        xattrs,
+        hardlinks,
     };
+    if let Some(pending) = pending {
+        cache::store_archive(
+            pending,
+            cache::CachedVersion {
+                sha256: architecture.sha256.clone(),
+                dictionary: architecture.dictionary.as_ref().map(|d| d.sha256.clone()),
+                boot_scripts: package.boot_scripts.clone(),
+                cached_at: unix_now(),
+                package: installed_package.clone(),
+            },
+        );
+    }
 
     installed
         .packages
         .insert(name.to_string(), installed_package);
-    println!("Successfully installed {} v{}\n", name, package.version);
+    scripts::run_maintainer_script(
+        name,
+        &package.version,
+        "post-install",
+        &package.scripts,
+        &architecture.contents,
+        &package_dir,
+    )?;
+    println!("Successfully installed {} v{}", name, package.version);
+    logcat::info(&format!("Installed {} v{}", name, package.version));
+    if let Some(notes) = &package.notes {
+        println!("Note: {notes}");
+    }
+    println!();
 
     Ok(())
 }
 
-fn install_package(name: &str, no_confirm: bool) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Fetching repository information...");
-    let repo = fetch_repo()?;
-    let mut installed = get_installed_packages()?;
+// Removes packages superseded by a freshly installed one. Files the new
+// package now owns are left in place, and if the user had requested any of
+// the old packages the new one counts as requested too
+fn replace_packages(
+    replaced: &[String],
+    new_contents: &[String],
+    installed: &mut InstalledPackages,
+) -> Result<bool, PieError> {
+    let hook_packages = hook_packages_installed(replaced, installed);
+    hooks::run_hooks("pre-remove", &hook_packages)?;
+
+    let mut explicit = false;
+    for name in replaced {
+        let Some(old) = installed.packages.remove(name) else {
+            continue;
+        };
+        println!("Replacing obsolete package {} v{}", old.name, old.version);
+
+        let services: Vec<String> = old.services.keys().cloned().collect();
+        service::remove_services(&services)?;
+
+        boot::remove_scripts(&old.boot_scripts)?;
+        let root = package_root(old.overlay);
+        for file in old.contents.iter().filter(|f| !new_contents.contains(f)) {
+            let path = format!("{root}/{file}");
+            if Path::new(&path).exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+        explicit |= old.explicit;
+    }
 
-    // Check if it's a direct package or content search
-    let target_package = if repo.packages.contains_key(name) {
-        name.to_string()
+    hooks::run_hooks("post-remove", &hook_packages)?;
+    Ok(explicit)
+}
+
+// Maps a name given on the command line to a repo package, falling back to a
+// content search. Returns None if the user declined the suggested provider
+fn resolve_install_target(repo: &Repo, name: &str) -> Result<Option<String>, PieError> {
+    if repo.packages.contains_key(name) {
+        return Ok(Some(name.to_string()));
+    }
+
+    let mut replacements: Vec<&String> = repo
+        .packages
+        .iter()
+        .filter(|(_, package)| package.replaces.iter().any(|old| old == name))
+        .map(|(new, _)| new)
+        .collect();
+    replacements.sort();
+    if let Some(new) = replacements.first() {
+        println!("'{name}' has been replaced by '{new}'");
+        return Ok(Some(new.to_string()));
+    }
+
+    // Search for package containing this content
+    if let Some(pkg_name) = find_package_by_content(repo, name) {
+        println!("'{name}' is provided by package '{pkg_name}'");
+        if !prompt::confirm(&format!("Install '{pkg_name}'?"), true)? {
+            return Ok(None);
+        }
+        Ok(Some(pkg_name))
     } else {
-        // Search for package containing this content
-        if let Some(pkg_name) = find_package_by_content(&repo, name) {
-            if !no_confirm {
-                println!("'{name}' is provided by package '{pkg_name}'");
-                print!("Install '{pkg_name}'? [Y/n]: ");
-                io::stdout().flush()?;
+        Err(PieError::Resolution(format!(
+            "Package or content '{name}' not found"
+        )))
+    }
+}
 
-                let mut input = String::new();
-                io::stdin().read_line(&mut input)?;
-                let input = input.trim().to_lowercase();
+// Replaces @group arguments with the group's members, returning the groups
+// that were used so summaries can show them
+fn expand_groups(
+    repo: &Repo,
+    names: &[String],
+) -> Result<(Vec<String>, Vec<(String, Vec<String>)>), PieError> {
+    let mut expanded = Vec::new();
+    let mut groups = Vec::new();
+
+    for name in names {
+        let Some(group) = name.strip_prefix('@') else {
+            expanded.push(name.clone());
+            continue;
+        };
+        let members = repo
+            .groups
+            .get(group)
+            .ok_or_else(|| PieError::Resolution(format!("Group '@{group}' not found")))?;
+        for member in members {
+            if !expanded.contains(member) {
+                expanded.push(member.clone());
+            }
+        }
+        groups.push((name.clone(), members.clone()));
+    }
 
-                if input == "n" || input == "no" {
-                    println!("Installation cancelled");
-                    return Ok(());
-                }
+    Ok((expanded, groups))
+}
+
+// Replaces glob arguments with the candidates they match, listing the matches
+// for confirmation. Returns None if the user declined
+fn expand_globs(
+    names: Vec<String>,
+    candidates: &[&String],
+) -> Result<Option<Vec<String>>, PieError> {
+    if !names.iter().any(|name| glob::is_glob(name)) {
+        return Ok(Some(names));
+    }
+
+    let mut expanded = Vec::new();
+    for name in names {
+        if !glob::is_glob(&name) {
+            expanded.push(name);
+            continue;
+        }
+        let mut matched: Vec<&String> = candidates
+            .iter()
+            .copied()
+            .filter(|candidate| glob::matches(&name, candidate))
+            .collect();
+        if matched.is_empty() {
+            return Err(PieError::Resolution(format!("No packages match '{name}'")));
+        }
+        matched.sort();
+        let list: Vec<&str> = matched.iter().map(|m| m.as_str()).collect();
+        println!("'{name}' matches: {}", list.join(", "));
+        for m in matched {
+            if !expanded.contains(m) {
+                expanded.push(m.clone());
             }
-            pkg_name
-        } else {
-            return Err(format!("Package or content '{name}' not found").into());
         }
-    };
+    }
 
-    let package = repo.packages.get(&target_package).unwrap();
+    if !prompt::confirm("Continue with these packages?", true)? {
+        return Ok(None);
+    }
+    Ok(Some(expanded))
+}
 
-    // Check if already installed
-    if installed.packages.contains_key(&target_package) {
-        println!(
-            "Package '{}' v{} is already installed",
-            target_package, package.version
-        );
+// Newline separated package names for provisioning scripts, "-" is stdin
+fn read_package_list(path: &str) -> Result<Vec<String>, PieError> {
+    let content = if path == "-" {
+        io::read_to_string(io::stdin())?
+    } else {
+        fs::read_to_string(path).map_err(|e| format!("Failed to read '{path}': {e}"))?
+    };
+    Ok(content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+fn install_packages(names: &[String], overlay: bool) -> Result<(), PieError> {
+    progress::phase("resolve");
+    println!("Fetching repository information...");
+    let repo = fetch_repo()?;
+    let mut installed = get_installed_packages()?;
+    let mut pins = Vec::new();
+    let names: Vec<String> = names
+        .iter()
+        .map(|name| match name.split_once('=') {
+            Some((name, version)) => {
+                pins.push((name.to_string(), version.to_string()));
+                name.to_string()
+            }
+            None => name.clone(),
+        })
+        .collect();
+    let (names, groups) = expand_groups(&repo, &names)?;
+    let available: Vec<&String> = repo
+        .packages
+        .keys()
+        .filter(|name| !installed.packages.contains_key(*name) && !config::ignored(name))
+        .collect();
+    let Some(names) = expand_globs(names, &available)? else {
+        println!("Installation cancelled");
         return Ok(());
+    };
+
+    let mut targets: Vec<String> = Vec::new();
+    let mut marked_explicit = false;
+    for name in &names {
+        let Some(target_package) = resolve_install_target(&repo, name)? else {
+            println!("Skipping '{name}'");
+            continue;
+        };
+
+        // Check if already installed
+        if let Some(existing) = installed.packages.get_mut(&target_package) {
+            // Pins against the repo are checked with the rest of the graph
+            if let Some((_, range)) = pins.iter().find(|(pinned, _)| *pinned == target_package) {
+                if !version::matches_range(&existing.version, range) {
+                    return Err(PieError::Resolution(format!(
+                        "'{target_package}' is pinned to v{range}, but v{} is installed",
+                        existing.version
+                    )));
+                }
+            }
+            println!(
+                "Package '{}' v{} is already installed",
+                target_package, existing.version
+            );
+            if !existing.explicit {
+                existing.explicit = true;
+                marked_explicit = true;
+            }
+            continue;
+        }
+
+        if !targets.contains(&target_package) {
+            targets.push(target_package);
+        }
     }
 
-    // Check API compatibility
-    check_api_compatibility(package)?;
+    if marked_explicit {
+        save_installed_packages(&installed)?;
+    }
 
-    // Handle conflicts
-    handle_conflicts(package, &mut installed, no_confirm)?;
+    if targets.is_empty() {
+        return Ok(());
+    }
+
+    explain::check(&repo, &targets, &installed, &pins)?;
 
     // Resolve dependencies
-    let dependencies = resolve_dependencies(&repo, &target_package, &installed)?;
+    let mut dependencies: Vec<String> = Vec::new();
+    for target_package in &targets {
+        for dep in resolve_dependencies(&repo, target_package, &installed)? {
+            if !dependencies.contains(&dep) && !targets.contains(&dep) {
+                dependencies.push(dep);
+            }
+        }
+    }
+
+    let mut transaction = dependencies.clone();
+    transaction.extend(targets.iter().cloned());
+    let transaction = order_transaction(&repo, transaction)?;
+    let conflicts = find_conflicts(&repo, &transaction, &installed)?;
+    check_file_conflicts(&find_file_conflicts(
+        &repo,
+        &transaction,
+        &installed,
+        &conflicts,
+        overlay,
+    )?)?;
 
     // Calculate total download and installed sizes
     let arch = get_arch()?;
     let mut total_download = 0u64;
     let mut total_installed = 0u64;
 
-    // Add main package sizes
-    if let Some(main_arch) = package.architectures.get(&arch) {
-        total_download += main_arch.size;
-        total_installed += main_arch.uncompressed_size;
-    }
-
-    // Add dependency sizes
-    for dep in &dependencies {
-        if let Some(dep_pkg) = repo.packages.get(dep) {
-            if let Some(dep_arch) = dep_pkg.architectures.get(&arch) {
-                total_download += dep_arch.size;
-                total_installed += dep_arch.uncompressed_size;
+    for name in dependencies.iter().chain(&targets) {
+        if let Some(pkg) = repo.packages.get(name) {
+            if let Some(pkg_arch) = pkg.architectures.get(&arch) {
+                total_download += pkg_arch.size;
+                total_installed += pkg_arch.uncompressed_size;
             }
         }
     }
@@ -467,72 +2127,150 @@ fn install_package(name: &str, no_confirm: bool) -> Result<(), Box<dyn std::erro
     println!("INSTALLATION SUMMARY");
     println!("{}", get_separator());
 
+    for (group, members) in &groups {
+        println!("Group {group}: {}", members.join(", "));
+    }
+
     if !dependencies.is_empty() {
         println!("Dependencies to install ({}):", dependencies.len());
         for dep in &dependencies {
             let dep_pkg = repo.packages.get(dep).unwrap();
-            println!("  ├─ {} v{}", dep, dep_pkg.version);
+            println!("  {BRANCH} {} v{}", dep, dep_pkg.version);
         }
     }
 
-    println!("Main package:");
-    println!("  └─ {} v{}", target_package, package.version);
+    if targets.len() == 1 {
+        println!("Main package:");
+    } else {
+        println!("Packages to install ({}):", targets.len());
+    }
+    for (i, target_package) in targets.iter().enumerate() {
+        let branch = if i + 1 == targets.len() {
+            &LAST_BRANCH
+        } else {
+            &BRANCH
+        };
+        let package = &repo.packages[target_package];
+        println!("  {} {} v{}", branch, target_package, package.version);
+    }
+    print_conflicts(&conflicts, &installed);
 
     println!("\nTotal download size: {}", format_size(total_download));
     println!("Total installed size: {}", format_size(total_installed));
 
-    if !no_confirm {
-        print!("\nProceed with installation? [Y/n]: ");
-        io::stdout().flush()?;
-
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        let input = input.trim().to_lowercase();
-
-        if input == "n" || input == "no" {
-            println!("Installation cancelled");
-            return Ok(());
-        }
+    if !prompt::confirm("\nProceed with installation?", true)? {
+        println!("Installation cancelled");
+        return Ok(());
     }
 
+    remove_conflicts(&repo, &conflicts, &mut installed)?;
+    let hook_packages = hook_packages_repo(&transaction, &repo);
+    hooks::run_hooks("pre-install", &hook_packages)?;
+
     println!("\n{}", get_separator());
     println!("INSTALLING PACKAGES");
     println!("{}", get_separator());
-
-    // Install dependencies first
-    for (i, dep) in dependencies.iter().enumerate() {
-        println!(
-            "[{}/{}] Installing dependency: {}",
-            i + 1,
-            dependencies.len(),
-            dep
-        );
-        install_single_package(&repo, dep, &mut installed)?;
+    progress::phase("install");
+
+    let total = transaction.len();
+    // Where the files end up, a rollback removes exactly these
+    let planned = transaction
+        .iter()
+        .map(|name| {
+            package_architecture(&repo, name)
+                .map(|(p, a)| (name.clone(), package_contents(name, p, a, overlay)))
+        })
+        .collect::<Result<_, _>>()?;
+    let _critical = signal::critical();
+    journal::begin(&targets, overlay, planned)?;
+
+    // Each package is recorded as soon as it is in place, so a failure later in
+    // the transaction doesn't leave untracked files behind
+    for (i, name) in transaction.iter().enumerate() {
+        signal::check()?;
+        let explicit = targets.contains(name);
+        if !explicit {
+            println!("[{}/{}] Installing dependency: {}", i + 1, total, name);
+        } else if total > 1 {
+            println!("[{}/{}] Installing package: {}", i + 1, total, name);
+        }
+        progress::package("install", name, i + 1, total);
+        if let Some(next) = transaction.get(i + 1) {
+            prefetch::start(&repo, next);
+        }
+        install_single_package(&repo, name, &mut installed, overlay, explicit)?;
+        save_installed_packages(&installed)?;
+        journal::mark_done(name)?;
     }
+    journal::finish()?;
+
+    hooks::run_hooks("post-install", &hook_packages)?;
+    telemetry::report("install", &transaction, &repo);
+    let summary: Vec<String> = transaction
+        .iter()
+        .map(|name| format!("{name} v{}", repo.packages[name].version))
+        .collect();
+    notify::completed("installed", &summary);
+    progress::done("install", &transaction);
+    print_notes(&repo, &transaction);
 
-    // Install main package
-    if !dependencies.is_empty() {
-        println!(
-            "[{}/{}] Installing main package: {}",
-            dependencies.len() + 1,
-            dependencies.len() + 1,
-            target_package
-        );
+    println!("{}", get_separator());
+    println!("Installation completed successfully!");
+    if overlay {
+        println!("Reboot to make the overlay visible under /system");
     }
-    install_single_package(&repo, &target_package, &mut installed)?;
+    println!("{}", get_separator());
 
-    // Save updated installed packages
-    save_installed_packages(&installed)?;
+    Ok(())
+}
+
+// Notes scroll away during long transactions, so they are repeated at the end
+fn print_notes(repo: &Repo, names: &[String]) {
+    let notes: Vec<(&String, &String)> = names
+        .iter()
+        .filter_map(|name| Some((name, repo.packages.get(name)?.notes.as_ref()?)))
+        .collect();
+    if notes.is_empty() {
+        return;
+    }
 
     println!("{}", get_separator());
-    println!("Installation completed successfully!");
+    println!("NOTES");
     println!("{}", get_separator());
+    for (name, note) in notes {
+        println!("{BULLET} {name}");
+        for line in note.lines() {
+            println!("  {line}");
+        }
+    }
+}
+
+fn uninstall_packages(names: &[String]) -> Result<(), PieError> {
+    let repo = fetch_repo()?;
+    let (names, groups) = expand_groups(&repo, names)?;
+
+    // Group members that were never installed are skipped, not errors
+    let installed = get_installed_packages()?;
+    let candidates: Vec<&String> = installed.packages.keys().collect();
+    let Some(names) = expand_globs(names, &candidates)? else {
+        println!("Uninstallation cancelled");
+        return Ok(());
+    };
+    let group_members: Vec<&String> = groups.iter().flat_map(|(_, members)| members).collect();
+    for (group, members) in &groups {
+        println!("Group {group}: {}", members.join(", "));
+    }
 
+    for name in &names {
+        if group_members.contains(&name) && !installed.packages.contains_key(name) {
+            continue;
+        }
+        uninstall_package(&repo, name)?;
+    }
     Ok(())
 }
 
-fn uninstall_package(name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let repo = fetch_repo()?;
+fn uninstall_package(repo: &Repo, name: &str) -> Result<(), PieError> {
     let mut installed = get_installed_packages()?;
 
     // Check if it's a direct package or content search
@@ -540,38 +2278,64 @@ fn uninstall_package(name: &str) -> Result<(), Box<dyn std::error::Error>> {
         name.to_string()
     } else {
         // search for package containing this content
-        if let Some(pkg_name) = find_package_by_content(&repo, name) {
+        if let Some(pkg_name) = find_package_by_content(repo, name) {
             if installed.packages.contains_key(&pkg_name) {
                 println!("'{name}' is provided by package '{pkg_name}'");
-                print!("Uninstall '{pkg_name}'? [Y/n]: ");
-                io::stdout().flush()?;
-
-                let mut input = String::new();
-                io::stdin().read_line(&mut input)?;
-                let input = input.trim().to_lowercase();
-
-                if input == "n" || input == "no" {
+                if !prompt::confirm(&format!("Uninstall '{pkg_name}'?"), true)? {
                     println!("Uninstallation cancelled");
                     return Ok(());
                 }
                 pkg_name
             } else {
-                return Err(format!("Package containing '{name}' is not installed").into());
+                return Err(PieError::Resolution(format!(
+                    "Package containing '{name}' is not installed"
+                )));
             }
         } else {
-            return Err(format!("Package or content '{name}' not found or not installed").into());
+            return Err(PieError::Resolution(format!(
+                "Package or content '{name}' not found or not installed"
+            )));
         }
     };
 
-    let package = installed
-        .packages
-        .get(&target_package)
-        .ok_or(format!("Package '{target_package}' is not installed"))?;
+    let package = installed.packages.get(&target_package).ok_or_else(|| {
+        PieError::Resolution(format!("Package '{target_package}' is not installed"))
+    })?;
+
+    if !confirm_essential_removal(repo, std::slice::from_ref(&target_package))? {
+        println!("Uninstallation cancelled");
+        return Ok(());
+    }
+
+    let hook_packages = hook_packages_installed(std::slice::from_ref(&target_package), &installed);
+    hooks::run_hooks("pre-remove", &hook_packages)?;
 
     println!("\n{}", get_separator());
     println!("REMOVING PACKAGE");
     println!("{}", get_separator());
+    progress::phase("remove");
     println!("Package: {} v{}", target_package, package.version);
+    // Scripts are found the way they were at install, inside the opt prefix
+    let root = package_root(package.overlay);
+    let (script_root, script_contents) = if package.opt {
+        (
+            format!("{root}/{}", opt::prefix(&target_package)),
+            opt::archive_contents(&target_package, &package.contents),
+        )
+    } else {
+        (root, package.contents.clone())
+    };
+    scripts::run_maintainer_script(
+        &target_package,
+        &package.version,
+        "pre-remove",
+        &package.scripts,
+        &script_contents,
+        &script_root,
+    )?;
+    let services: Vec<String> = package.services.keys().cloned().collect();
+    service::remove_services(&services)?;
+
     print!("Removing files... ");
     io::stdout().flush()?;
 
@@ -582,70 +2346,630 @@ fn uninstall_package(name: &str) -> Result<(), Box<dyn std::error::Error>> {
     installed.packages.remove(&target_package);
     save_installed_packages(&installed)?;
 
-    println!("✓");
+    println!("{CHECK}");
     println!("Successfully removed {target_package}");
+    progress::done("remove", std::slice::from_ref(&target_package));
+    logcat::info(&format!("Removed {target_package}"));
     println!("{}", get_separator());
 
+    hooks::run_hooks("post-remove", &hook_packages)?;
+
     Ok(())
 }
 
-fn update_repo() -> Result<(), Box<dyn std::error::Error>> {
+fn update_repo() -> Result<(), PieError> {
     println!("Updating package repository...");
-    let _repo = fetch_repo()?; // just fetch to validate
-    println!("Repository updated successfully");
+    let previous = cache::load_cached_indices()
+        .ok()
+        .filter(|indices| !indices.is_empty())
+        .and_then(|indices| merge_indices(&indices).ok());
+    let indices = fetch_indices()?;
+    let repo = merge_indices(&indices)?; // validate before caching
+    cache::save_indices(&indices)?;
+    println!(
+        "Repository updated successfully ({} packages)",
+        repo.packages.len()
+    );
+
+    if let Some(previous) = previous {
+        print_index_changes(&previous, &repo);
+    }
+    news::show_news(&repo)?;
     Ok(())
 }
 
-fn search_packages(query: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Fetching repository information...");
-    let repo = fetch_repo()?;
+// What changed since the last `pie update`, from the cached copy
+fn print_index_changes(previous: &Repo, repo: &Repo) {
+    let mut added: Vec<&String> = repo
+        .packages
+        .keys()
+        .filter(|name| !previous.packages.contains_key(*name))
+        .collect();
+    let mut removed: Vec<&String> = previous
+        .packages
+        .keys()
+        .filter(|name| !repo.packages.contains_key(*name))
+        .collect();
+    let mut changed: Vec<(&String, &str, &str)> = repo
+        .packages
+        .iter()
+        .filter_map(|(name, package)| {
+            let old = previous.packages.get(name)?;
+            (old.version != package.version).then_some((
+                name,
+                old.version.as_str(),
+                package.version.as_str(),
+            ))
+        })
+        .collect();
+    added.sort();
+    removed.sort();
+    changed.sort();
+
+    if added.is_empty() && removed.is_empty() && changed.is_empty() {
+        println!("No changes since the last update");
+        return;
+    }
 
-    match query {
-        Some(q) => {
-            let mut found_packages = false;
+    if !added.is_empty() {
+        println!("\nNew packages ({}):", added.len());
+        for name in added {
+            println!("  + {} v{}", name, repo.packages[name].version);
+        }
+    }
+    if !removed.is_empty() {
+        println!("\nRemoved packages ({}):", removed.len());
+        for name in removed {
+            println!("  - {name}");
+        }
+    }
+    if !changed.is_empty() {
+        println!("\nUpdated packages ({}):", changed.len());
+        for (name, old, new) in changed {
+            println!("  {BULLET} {name} v{old} {ARROW} v{new}");
+        }
+    }
+}
 
-            println!("\nSearching for '{q}'...\n");
+// Ordered from most to least relevant, search results sort by this first
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum MatchReason {
+    ExactName,
+    NamePrefix,
+    NameSubstring,
+    Description,
+    Contents(String),
+}
+
+impl fmt::Display for MatchReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchReason::ExactName => write!(f, "exact name"),
+            MatchReason::NamePrefix => write!(f, "name prefix"),
+            MatchReason::NameSubstring => write!(f, "name"),
+            MatchReason::Description => write!(f, "description"),
+            MatchReason::Contents(path) => write!(f, "provides {path}"),
+        }
+    }
+}
 
-            // 1st search for direct package matches
-            for (name, package) in &repo.packages {
-                if name.to_lowercase().contains(&q.to_lowercase()) {
-                    println!("● {} v{}", name, package.version);
-                    found_packages = true;
+fn search_matches(
+    repo: &Repo,
+    query: &str,
+    regex: bool,
+) -> Result<Vec<(String, MatchReason)>, PieError> {
+    let re = if regex {
+        Some(
+            regex::RegexBuilder::new(query)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| format!("Invalid regex '{query}': {e}"))?,
+        )
+    } else {
+        None
+    };
+    let query_lower = query.to_lowercase();
+    let arch = get_arch().ok();
+
+    let mut matches = Vec::new();
+    for (name, package) in &repo.packages {
+        let description = package.description.as_deref().unwrap_or_default();
+        let contents = arch
+            .as_deref()
+            .and_then(|arch| package.architectures.get(arch))
+            .map_or(&[][..], |architecture| &architecture.contents[..]);
+
+        let reason = match &re {
+            Some(re) => match re.find(name) {
+                Some(m) if m.start() == 0 && m.end() == name.len() => Some(MatchReason::ExactName),
+                Some(m) if m.start() == 0 => Some(MatchReason::NamePrefix),
+                Some(_) => Some(MatchReason::NameSubstring),
+                None if re.is_match(description) => Some(MatchReason::Description),
+                None => contents
+                    .iter()
+                    .find(|content| re.is_match(content))
+                    .map(|content| MatchReason::Contents(content.clone())),
+            },
+            None => {
+                let name_lower = name.to_lowercase();
+                if name_lower == query_lower {
+                    Some(MatchReason::ExactName)
+                } else if name_lower.starts_with(&query_lower) {
+                    Some(MatchReason::NamePrefix)
+                } else if name_lower.contains(&query_lower) {
+                    Some(MatchReason::NameSubstring)
+                } else if description.to_lowercase().contains(&query_lower) {
+                    Some(MatchReason::Description)
+                } else {
+                    contents
+                        .iter()
+                        .find(|content| {
+                            content.ends_with(&format!("/{query}")) || *content == query
+                        })
+                        .map(|content| MatchReason::Contents(content.clone()))
                 }
             }
+        };
 
-            // then search for content matches
-            if let Some(pkg_name) = find_package_by_content(&repo, q) {
-                if let Some(package) = repo.packages.get(&pkg_name) {
-                    if !found_packages {
-                        println!("No direct package matches found.\n");
-                    }
-                    println!("→ '{q}' is provided by:");
-                    println!("   └─ {} v{}", pkg_name, package.version);
-                    found_packages = true;
+        if let Some(reason) = reason {
+            matches.push((name.clone(), reason));
+        }
+    }
+
+    matches.sort_by(|(a_name, a_reason), (b_name, b_reason)| {
+        a_reason.cmp(b_reason).then_with(|| a_name.cmp(b_name))
+    });
+    Ok(matches)
+}
+
+fn repo_fields(name: &str, package: &Package, arch: Option<&str>) -> Vec<(&'static str, String)> {
+    let architecture = arch.and_then(|arch| package.architectures.get(arch));
+    let mut arches: Vec<&str> = package.architectures.keys().map(String::as_str).collect();
+    arches.sort();
+
+    vec![
+        ("name", name.to_string()),
+        ("version", package.version.clone()),
+        ("repo", package.repo.clone()),
+        (
+            "description",
+            package.description.clone().unwrap_or_default(),
+        ),
+        ("min_api", package.min_api.clone().unwrap_or_default()),
+        ("dependencies", package.dependencies.join(",")),
+        ("conflicts", package.conflicts.join(",")),
+        ("architectures", arches.join(",")),
+        (
+            "size",
+            architecture.map_or(String::new(), |a| format_size(a.size)),
+        ),
+        (
+            "size_bytes",
+            architecture.map_or(String::new(), |a| a.size.to_string()),
+        ),
+        (
+            "installed_size",
+            architecture.map_or(String::new(), |a| format_size(a.uncompressed_size)),
+        ),
+        (
+            "installed_size_bytes",
+            architecture.map_or(String::new(), |a| a.uncompressed_size.to_string()),
+        ),
+    ]
+}
+
+fn installed_fields(package: &InstalledPackage) -> Vec<(&'static str, String)> {
+    vec![
+        ("name", package.name.clone()),
+        ("version", package.version.clone()),
+        ("repo", package.repo.clone()),
+        (
+            "description",
+            package.description.clone().unwrap_or_default(),
+        ),
+        ("size", format_size(package.size)),
+        ("size_bytes", package.size.to_string()),
+        ("files", package.contents.len().to_string()),
+        ("explicit", package.explicit.to_string()),
+    ]
+}
+
+// " [installed]" or " [upgradable: 1.2→1.4]" for packages already on the device
+fn install_marker(installed: &InstalledPackages, name: &str, package: &Package) -> String {
+    match installed.packages.get(name) {
+        Some(pkg)
+            if version::compare_versions(&package.version, &pkg.version)
+                == std::cmp::Ordering::Greater =>
+        {
+            format!(" [upgradable: {}{ARROW}{}]", pkg.version, package.version)
+        }
+        Some(_) => " [installed]".to_string(),
+        None => String::new(),
+    }
+}
+
+fn search_packages(
+    query: Option<&str>,
+    regex: bool,
+    installed_filter: Option<bool>,
+    template: Option<&str>,
+) -> Result<(), PieError> {
+    if template.is_none() {
+        println!("Fetching repository information...");
+    }
+    let repo = fetch_repo()?;
+    let installed = get_installed_packages()?;
+
+    let mut results: Vec<(String, Option<MatchReason>)> = match query {
+        Some(q) => search_matches(&repo, q, regex)?
+            .into_iter()
+            .map(|(name, reason)| (name, Some(reason)))
+            .collect(),
+        None => {
+            let mut names: Vec<String> = repo.packages.keys().cloned().collect();
+            names.sort();
+            names.into_iter().map(|name| (name, None)).collect()
+        }
+    };
+    results.retain(|(name, _)| !config::ignored(name) || installed.packages.contains_key(name));
+    if let Some(want_installed) = installed_filter {
+        results.retain(|(name, _)| installed.packages.contains_key(name) == want_installed);
+    }
+
+    if let Some(template) = template {
+        let arch = get_arch().ok();
+        for (name, _) in results {
+            let package = &repo.packages[&name];
+            let mut fields = repo_fields(&name, package, arch.as_deref());
+            let state = match installed.packages.get(&name) {
+                Some(pkg)
+                    if version::compare_versions(&package.version, &pkg.version)
+                        == std::cmp::Ordering::Greater =>
+                {
+                    "upgradable"
                 }
+                Some(_) => "installed",
+                None => "",
+            };
+            fields.push(("state", state.to_string()));
+            println!("{}", format::render(template, &fields));
+        }
+        return Ok(());
+    }
+
+    match query {
+        Some(q) => {
+            let mut hits: Vec<String> = Vec::new();
+
+            println!("\nSearching for '{q}'...\n");
+
+            for (name, reason) in results {
+                let package = &repo.packages[&name];
+                println!(
+                    "{:>3}) {BULLET} {} v{}{} ({})",
+                    hits.len() + 1,
+                    name,
+                    package.version,
+                    install_marker(&installed, &name, package),
+                    reason.map(|reason| reason.to_string()).unwrap_or_default()
+                );
+                hits.push(name);
             }
 
-            if !found_packages {
-                println!("✗ No packages or content found matching '{q}'");
+            if hits.is_empty() {
+                println!("{CROSS} No packages or content found matching '{q}'");
+            } else if hits.len() > 1 {
+                pick_and_install(&hits)?;
             }
         }
         None => {
             println!("\nAvailable packages:\n");
-            let mut packages: Vec<_> = repo.packages.iter().collect();
-            packages.sort_by_key(|(name, _)| *name);
+            for (name, _) in results {
+                let package = &repo.packages[&name];
+                println!(
+                    "{BULLET} {} v{}{}",
+                    name,
+                    package.version,
+                    install_marker(&installed, &name, package)
+                );
+            }
+        }
+    }
 
-            for (name, package) in packages {
-                println!("● {} v{}", name, package.version);
+    Ok(())
+}
+
+// Unlike find_package_by_content this looks at every architecture and reports
+// all providers, so maintainers can spot files shipped by several packages
+fn search_contents(
+    fragment: &str,
+    installed_filter: Option<bool>,
+    template: Option<&str>,
+) -> Result<(), PieError> {
+    if template.is_none() {
+        println!("Fetching repository information...");
+    }
+    let repo = fetch_repo()?;
+    let installed = get_installed_packages()?;
+
+    let mut providers: BTreeMap<&String, BTreeMap<&String, Vec<&str>>> = BTreeMap::new();
+    for (name, package) in &repo.packages {
+        if installed_filter.is_some_and(|want| installed.packages.contains_key(name) != want) {
+            continue;
+        }
+        for (arch, architecture) in &package.architectures {
+            for content in &architecture.contents {
+                if content.contains(fragment) {
+                    providers
+                        .entry(name)
+                        .or_default()
+                        .entry(content)
+                        .or_default()
+                        .push(arch.as_str());
+                }
+            }
+        }
+    }
+
+    for arches in providers.values_mut().flat_map(|paths| paths.values_mut()) {
+        arches.sort();
+    }
+
+    if let Some(template) = template {
+        let arch = get_arch().ok();
+        for (name, paths) in providers {
+            for (path, arches) in paths {
+                let mut fields = repo_fields(name, &repo.packages[name], arch.as_deref());
+                fields.push(("path", path.clone()));
+                fields.push(("path_architectures", arches.join(",")));
+                println!("{}", format::render(template, &fields));
             }
         }
+        return Ok(());
+    }
+
+    if providers.is_empty() {
+        println!("{CROSS} No package ships a file matching '{fragment}'");
+        return Ok(());
+    }
+
+    println!("\nPackages shipping '{fragment}' ({}):\n", providers.len());
+    for (name, paths) in providers {
+        let package = &repo.packages[name];
+        println!(
+            "{BULLET} {} v{}{}",
+            name,
+            package.version,
+            install_marker(&installed, name, package)
+        );
+        for (path, arches) in paths {
+            println!("    {path} ({})", arches.join(", "));
+        }
     }
 
     Ok(())
 }
 
-fn list_installed() -> Result<(), Box<dyn std::error::Error>> {
+fn package_info(name: &str, template: Option<&str>) -> Result<(), PieError> {
+    let repo = fetch_repo()?;
     let installed = get_installed_packages()?;
+    let package = repo
+        .packages
+        .get(name)
+        .ok_or_else(|| PieError::Resolution(format!("Package '{name}' not found")))?;
+    let arch = get_arch().ok();
+
+    if let Some(template) = template {
+        let mut fields = repo_fields(name, package, arch.as_deref());
+        let installed_version = installed
+            .packages
+            .get(name)
+            .map(|pkg| pkg.version.clone())
+            .unwrap_or_default();
+        fields.push(("installed", installed_version));
+        println!("{}", format::render(template, &fields));
+        return Ok(());
+    }
+
+    fn none(list: &[String]) -> String {
+        if list.is_empty() {
+            "none".to_string()
+        } else {
+            list.join(", ")
+        }
+    }
+    let mut arches: Vec<String> = package.architectures.keys().cloned().collect();
+    arches.sort();
+
+    println!("{}", get_separator());
+    println!("Package: {name}");
+    println!("Version: {}", package.version);
+    println!("Repository: {}", package.repo);
+    if let Some(origin) = &package.origin {
+        println!("Subpackage of: {origin}");
+    }
+    if let Some(description) = &package.description {
+        println!("Description: {description}");
+    }
+    if let Some(min_api) = package
+        .min_api
+        .as_deref()
+        .filter(|api| !api.trim().is_empty())
+    {
+        println!("Minimum API level: {min_api}");
+    }
+    println!("Dependencies: {}", none(&package.dependencies));
+    if !package.pre_depends.is_empty() {
+        println!("Pre-dependencies: {}", package.pre_depends.join(", "));
+    }
+    println!("Conflicts: {}", none(&package.conflicts));
+    println!("Architectures: {}", none(&arches));
+    if let Some(source) = &package.source {
+        if let Some(url) = &source.url {
+            println!("Source: {url}");
+        }
+        if let Some(vcs) = &source.vcs {
+            let rev = vcs.rev.as_deref().unwrap_or("default branch");
+            println!("Source repository: {} ({rev})", vcs.url);
+        }
+    }
+    match arch
+        .as_deref()
+        .and_then(|arch| package.architectures.get(arch))
+    {
+        Some(architecture) => {
+            println!(
+                "Download size: {} | Installed size: {}",
+                format_size(architecture.size),
+                format_size(architecture.uncompressed_size)
+            );
+        }
+        None => println!("Not available for this device"),
+    }
+    match installed.packages.get(name) {
+        Some(pkg) => println!("Status: installed (v{})", pkg.version),
+        None => println!("Status: not installed"),
+    }
+    println!("{}", get_separator());
+
+    Ok(())
+}
+
+fn list_outdated(template: Option<&str>) -> Result<(), PieError> {
+    let repo = fetch_repo()?;
+    let installed = get_installed_packages()?;
+
+    let mut outdated: Vec<(&InstalledPackage, &Package)> = installed
+        .packages
+        .values()
+        .filter_map(|pkg| {
+            repo.packages
+                .get(&pkg.name)
+                .map(|available| (pkg, available))
+        })
+        .filter(|(pkg, available)| {
+            version::compare_versions(&available.version, &pkg.version)
+                == std::cmp::Ordering::Greater
+        })
+        .collect();
+    outdated.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+
+    if let Some(template) = template {
+        for (pkg, available) in outdated {
+            let mut fields = installed_fields(pkg);
+            fields.push(("installed", pkg.version.clone()));
+            fields.push(("available", available.version.clone()));
+            println!("{}", format::render(template, &fields));
+        }
+        return Ok(());
+    }
+
+    if outdated.is_empty() {
+        println!("All packages are up to date");
+        return Ok(());
+    }
+
+    println!("Upgradable packages ({}):\n", outdated.len());
+    for (pkg, available) in outdated {
+        println!(
+            "{BULLET} {} v{} {ARROW} v{}",
+            pkg.name, pkg.version, available.version
+        );
+    }
+
+    Ok(())
+}
+
+// Accepts numbers and ranges separated by spaces or commas, e.g. "1 3-5,7"
+fn parse_selection(input: &str, max: usize) -> Result<Vec<usize>, String> {
+    let mut selected = Vec::new();
+
+    for token in input.split(|c: char| c == ',' || c.is_whitespace()) {
+        if token.is_empty() {
+            continue;
+        }
+
+        let (start, end) = match token.split_once('-') {
+            Some((start, end)) => (start, end),
+            None => (token, token),
+        };
+        let start: usize = start
+            .parse()
+            .map_err(|_| format!("Invalid selection '{token}'"))?;
+        let end: usize = end
+            .parse()
+            .map_err(|_| format!("Invalid selection '{token}'"))?;
+
+        if start == 0 || end > max || start > end {
+            return Err(format!("Selection '{token}' is out of range 1-{max}"));
+        }
+        for index in start..=end {
+            if !selected.contains(&index) {
+                selected.push(index);
+            }
+        }
+    }
+
+    Ok(selected)
+}
+
+fn pick_and_install(hits: &[String]) -> Result<(), PieError> {
+    // There is nothing sensible to pick on the user's behalf
+    if prompt::assumed_answer().is_some() || !prompt::interactive() {
+        return Ok(());
+    }
+
+    let selected = loop {
+        print!("\nSelect packages to install (e.g. 1 3-4, empty to skip): ");
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+
+        match parse_selection(input.trim(), hits.len()) {
+            Ok(selected) => break selected,
+            Err(e) => println!("{e}"),
+        }
+    };
+
+    if selected.is_empty() {
+        return Ok(());
+    }
+
+    let names: Vec<String> = selected.iter().map(|i| hits[i - 1].clone()).collect();
+    install_packages(&names, config::overlay_default()?)
+}
+
+// Meant for scripts, so the only output is the bare version
+fn package_status(name: &str, min_version: Option<&str>) -> Result<bool, PieError> {
+    let installed = get_installed_packages()?;
+
+    let Some(package) = installed.packages.get(name) else {
+        return Ok(false);
+    };
+    println!("{}", package.version);
+
+    Ok(min_version.is_none_or(|min| {
+        version::compare_versions(&package.version, min) != std::cmp::Ordering::Less
+    }))
+}
+
+fn list_installed(pattern: Option<&str>, template: Option<&str>) -> Result<(), PieError> {
+    let mut installed = get_installed_packages()?;
+    if let Some(pattern) = pattern {
+        installed
+            .packages
+            .retain(|name, _| glob::matches(pattern, name));
+    }
+
+    if let Some(template) = template {
+        let mut packages: Vec<_> = installed.packages.values().collect();
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        for package in packages {
+            println!("{}", format::render(template, &installed_fields(package)));
+        }
+        return Ok(());
+    }
 
     if installed.packages.is_empty() {
         println!("No packages installed");
@@ -657,7 +2981,7 @@ fn list_installed() -> Result<(), Box<dyn std::error::Error>> {
     packages.sort_by_key(|(name, _)| *name);
 
     for (name, package) in packages {
-        println!("● {} v{}", name, package.version);
+        println!("{BULLET} {} v{}", name, package.version);
     }
 
     Ok(())
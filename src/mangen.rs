@@ -0,0 +1,36 @@
+use clap::CommandFactory;
+use std::fs;
+use std::path::Path;
+
+use crate::error::PieError;
+use crate::Cli;
+
+// One page for pie itself plus pie-<subcommand> pages, as packagers expect
+pub fn generate_man_pages(out_dir: &Path) -> Result<(), PieError> {
+    fs::create_dir_all(out_dir)?;
+
+    let mut cmd = Cli::command();
+    cmd.build();
+
+    write_page(out_dir, "pie", cmd.clone())?;
+    for sub in cmd.get_subcommands() {
+        if sub.is_hide_set() {
+            continue;
+        }
+        write_page(out_dir, &format!("pie-{}", sub.get_name()), sub.clone())?;
+    }
+
+    Ok(())
+}
+
+fn write_page(out_dir: &Path, title: &str, cmd: clap::Command) -> Result<(), PieError> {
+    let mut buffer = Vec::new();
+    clap_mangen::Man::new(cmd)
+        .title(title)
+        .render(&mut buffer)?;
+
+    let path = out_dir.join(format!("{title}.1"));
+    fs::write(&path, buffer)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,268 @@
+use rayon::prelude::*;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::config;
+use crate::error::PieError;
+use crate::glyph::{CHECK, CROSS};
+use crate::net;
+use crate::prefetch::{self, Source};
+use crate::{fetch_index, format_size, get_separator, Architecture};
+
+// Artifacts are stored by their SHA-256, so unchanged ones are found again
+// on the next sync whatever their upstream url was
+const ARCHIVES: &str = "archives";
+const DICTIONARIES: &str = "dictionaries";
+
+fn archive_name(sha256: &str) -> String {
+    format!("{ARCHIVES}/{sha256}.tar.zst")
+}
+
+fn dictionary_name(sha256: &str) -> String {
+    format!("{DICTIONARIES}/{sha256}.zst")
+}
+
+// The architectures of every package and subpackage in an index
+fn architecture_maps(index: &mut Value) -> Vec<&mut Map<String, Value>> {
+    let mut maps = Vec::new();
+    let Some(packages) = index.get_mut("packages").and_then(Value::as_object_mut) else {
+        return maps;
+    };
+    for package in packages.values_mut().filter_map(Value::as_object_mut) {
+        for (key, value) in package.iter_mut() {
+            match key.as_str() {
+                "architectures" => maps.extend(value.as_object_mut()),
+                "subpackages" => maps.extend(
+                    value
+                        .as_object_mut()
+                        .into_iter()
+                        .flat_map(|subpackages| subpackages.values_mut())
+                        .filter_map(|subpackage| subpackage.get_mut("architectures"))
+                        .filter_map(Value::as_object_mut),
+                ),
+                _ => {}
+            }
+        }
+    }
+    maps
+}
+
+// Copies the configured repos into `dir`, one directory per repo holding a
+// repo.json with relative urls, so it can be served over HTTP or used as a
+// local path. Artifacts already present are kept, ones no longer listed are
+// removed
+pub fn mirror(dir: &Path, arches: &[String], repos: &[String]) -> Result<(), PieError> {
+    let config = config::load_config()?;
+    for name in repos {
+        if !config.repos.iter().any(|repo| repo.name == *name) {
+            return Err(format!("No repo named '{name}' is configured").into());
+        }
+    }
+
+    let repo_count = config.repos.len();
+    for repo_config in &config.repos {
+        if !repos.is_empty() && !repos.contains(&repo_config.name) {
+            continue;
+        }
+        println!("\n{}", get_separator());
+        println!("MIRRORING {}", repo_config.name.to_uppercase());
+        println!("{}", get_separator());
+
+        print!("Fetching index... ");
+        io::stdout().flush()?;
+        let content = fetch_index(repo_config, repo_count)?;
+        let mut index: Value = serde_json::from_slice(&content).map_err(|e| {
+            PieError::Schema(format!("Invalid index of repo '{}': {e}", repo_config.name))
+        })?;
+        println!("{CHECK}");
+
+        let repo_dir = dir.join(&repo_config.name);
+        fs::create_dir_all(repo_dir.join(ARCHIVES))?;
+        fs::create_dir_all(repo_dir.join(DICTIONARIES))?;
+        let auth = repo_config.auth()?;
+
+        // Archives and dictionaries to fetch, by SHA-256
+        let mut archives: HashMap<String, Source> = HashMap::new();
+        let mut dictionaries: HashMap<String, String> = HashMap::new();
+        let mut present = 0;
+        for map in architecture_maps(&mut index) {
+            if !arches.is_empty() {
+                map.retain(|arch, _| arches.contains(arch));
+            }
+            for (arch, value) in map.iter() {
+                let architecture: Architecture = serde_json::from_value(value.clone())
+                    .map_err(|e| PieError::Schema(format!("Invalid {arch} entry: {e}")))?;
+                if let Some(dictionary) = &architecture.dictionary {
+                    if !repo_dir.join(dictionary_name(&dictionary.sha256)).exists() {
+                        dictionaries.insert(
+                            dictionary.sha256.clone(),
+                            net::resolve_url(&repo_config.url, &dictionary.url),
+                        );
+                    }
+                }
+                let path = repo_dir.join(archive_name(&architecture.sha256));
+                if fs::metadata(&path).is_ok_and(|meta| meta.len() == architecture.size) {
+                    present += 1;
+                    continue;
+                }
+                if !archives.contains_key(&architecture.sha256) {
+                    let source = Source::unmerged(&repo_config.url, auth.as_ref(), &architecture)?;
+                    archives.insert(architecture.sha256.clone(), source);
+                }
+            }
+        }
+
+        let mut failed: HashSet<String> = HashSet::new();
+        for (sha256, url) in &dictionaries {
+            let fetched = config::url_auth(&repo_config.url, auth.as_ref(), url)
+                .and_then(|url_auth| net::fetch_bytes(url, url_auth.as_ref()))
+                .and_then(|content| {
+                    if hex::encode(Sha256::digest(&content)) != *sha256 {
+                        return Err(PieError::Checksum(format!("dictionary {url}")));
+                    }
+                    fs::write(repo_dir.join(dictionary_name(sha256)), content)?;
+                    Ok(())
+                });
+            if let Err(e) = fetched {
+                eprintln!("Warning: {e}");
+                failed.insert(sha256.clone());
+            }
+        }
+
+        let size: u64 = archives.values().map(|source| source.size()).sum();
+        print!(
+            "Downloading {} archives ({}), {present} up to date... ",
+            archives.len(),
+            format_size(size)
+        );
+        io::stdout().flush()?;
+        let results: Vec<(&String, Result<(), PieError>)> = archives
+            .par_iter()
+            .map(|(sha256, source)| {
+                let path = repo_dir.join(archive_name(sha256));
+                (sha256, save_archive(source, &path))
+            })
+            .collect();
+        let errors: Vec<(&String, PieError)> = results
+            .into_iter()
+            .filter_map(|(sha256, result)| Some((sha256, result.err()?)))
+            .collect();
+        if errors
+            .iter()
+            .any(|(_, e)| matches!(e, PieError::Interrupted))
+        {
+            return Err(PieError::Interrupted);
+        }
+        if errors.is_empty() {
+            println!("{CHECK}");
+        } else {
+            println!("{CROSS} ({} failed)", errors.len());
+            for (sha256, e) in &errors {
+                println!("  {CROSS} {e}");
+                failed.insert(sha256.to_string());
+            }
+        }
+
+        // Builds that couldn't be fetched are left out rather than listed
+        // with a missing archive
+        let mut kept: HashSet<String> = HashSet::new();
+        for map in architecture_maps(&mut index) {
+            map.retain(|_, value| {
+                let archive = value["sha256"].as_str().unwrap_or_default();
+                let dictionary = value["dictionary"]["sha256"].as_str();
+                !failed.contains(archive) && dictionary.is_none_or(|sha| !failed.contains(sha))
+            });
+            for value in map.values_mut() {
+                let Some(entry) = value.as_object_mut() else {
+                    continue;
+                };
+                let sha256 = entry
+                    .get("sha256")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string();
+                entry.insert("url".to_string(), json!(archive_name(&sha256)));
+                entry.remove("github");
+                entry.remove("ipfs");
+                kept.insert(archive_name(&sha256));
+                if let Some(dictionary) = entry.get_mut("dictionary").filter(|d| d.is_object()) {
+                    let sha256 = dictionary["sha256"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string();
+                    dictionary["url"] = json!(dictionary_name(&sha256));
+                    kept.insert(dictionary_name(&sha256));
+                }
+            }
+        }
+        finish_index(&mut index, &repo_config.url);
+
+        // Written last, so a mirror being synced keeps serving the old
+        // index with its archives in place
+        let index_path = repo_dir.join("repo.json");
+        let staged = repo_dir.join("repo.json.tmp");
+        fs::write(&staged, serde_json::to_string_pretty(&index)? + "\n")?;
+        fs::rename(&staged, &index_path)?;
+
+        let mut pruned = 0;
+        for subdir in [ARCHIVES, DICTIONARIES] {
+            for entry in fs::read_dir(repo_dir.join(subdir))?.flatten() {
+                let name = format!("{subdir}/{}", entry.file_name().to_string_lossy());
+                if !kept.contains(&name) {
+                    fs::remove_file(entry.path())?;
+                    pruned += 1;
+                }
+            }
+        }
+        if pruned > 0 {
+            println!("Removed {pruned} artifacts no longer in the index");
+        }
+        println!("{CHECK} {}", index_path.display());
+    }
+    Ok(())
+}
+
+fn save_archive(source: &Source, path: &Path) -> Result<(), PieError> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    prefetch::save(source, file.as_file_mut())?;
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+// Source urls stay upstream, so relative ones are resolved. Packages whose
+// builds were all filtered out or failed are dropped
+fn finish_index(index: &mut Value, base: &str) {
+    let Some(packages) = index.get_mut("packages").and_then(Value::as_object_mut) else {
+        return;
+    };
+    packages.retain(|_, package| {
+        let empty = |value: &Value| value.as_object().is_none_or(Map::is_empty);
+        !empty(&package["architectures"])
+            || package["subpackages"]
+                .as_object()
+                .is_some_and(|subpackages| {
+                    subpackages
+                        .values()
+                        .any(|subpackage| !empty(&subpackage["architectures"]))
+                })
+    });
+    for package in packages.values_mut() {
+        if let Some(url) = package
+            .get_mut("source")
+            .and_then(|source| source.get_mut("url"))
+        {
+            let resolved = url
+                .as_str()
+                .filter(|url| !url.contains("://"))
+                .map(|relative| net::resolve_url(base, relative));
+            if let Some(resolved) = resolved {
+                *url = json!(resolved);
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use clap::Subcommand;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::error::PieError;
+use crate::glyph::CHECK;
+use crate::{
+    format_size, get_installed_packages, get_separator, overlay, service, unix_now,
+    InstalledPackages, ANDSTORE_ROOT, PIE_DATA,
+};
+
+const MODULE_ID: &str = "pie-environment";
+
+// The stock Magisk module installer, also understood by KernelSU
+const UPDATE_BINARY: &str = r#"#!/sbin/sh
+umask 022
+ui_print() { echo "$1"; }
+OUTFD=$2
+ZIPFILE=$3
+mount /data 2>/dev/null
+if [ ! -f /data/adb/magisk/util_functions.sh ]; then
+    ui_print "Please install Magisk v20.4+ or KernelSU"
+    exit 1
+fi
+. /data/adb/magisk/util_functions.sh
+install_module
+exit 0
+"#;
+
+#[derive(Subcommand)]
+pub enum ModuleAction {
+    /// Package installed packages into a flashable Magisk/KernelSU module zip
+    Export {
+        /// Packages to export, every installed package if omitted
+        packages: Vec<String>,
+        /// Zip file to write
+        #[arg(long, default_value = "pie-environment.zip")]
+        output: String,
+    },
+}
+
+pub fn run_module(action: ModuleAction) -> Result<(), PieError> {
+    match action {
+        ModuleAction::Export { packages, output } => export(&packages, &output),
+    }
+}
+
+// Overlay packages go into the module's system/ like they do on this device.
+// Everything else is unpacked to the andstore root by customize.sh, which
+// also seeds the database and enabled services unless pie is already set up
+fn export(names: &[String], output: &str) -> Result<(), PieError> {
+    let installed = get_installed_packages()?;
+    for name in names {
+        if !installed.packages.contains_key(name) {
+            return Err(PieError::Resolution(format!(
+                "Package '{name}' is not installed"
+            )));
+        }
+    }
+
+    let exported = InstalledPackages {
+        packages: installed
+            .packages
+            .into_iter()
+            .filter(|(name, _)| names.is_empty() || names.contains(name))
+            .collect(),
+    };
+    if exported.packages.is_empty() {
+        println!("No packages installed, nothing to export");
+        return Ok(());
+    }
+
+    let services: BTreeSet<String> = exported
+        .packages
+        .values()
+        .flat_map(|pkg| pkg.services.keys().cloned())
+        .collect();
+    let enabled: BTreeSet<String> = service::enabled_services()?
+        .intersection(&services)
+        .cloned()
+        .collect();
+
+    println!("{}", get_separator());
+    println!("EXPORTING MODULE");
+    println!("{}", get_separator());
+    print!(
+        "Writing {} packages to {output}... ",
+        exported.packages.len()
+    );
+    io::stdout().flush()?;
+
+    let mut zip = ZipWriter::new(fs::File::create(output)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let executable = options.unix_permissions(0o755);
+
+    zip.start_file("META-INF/com/google/android/update-binary", executable)?;
+    zip.write_all(UPDATE_BINARY.as_bytes())?;
+    zip.start_file("META-INF/com/google/android/updater-script", options)?;
+    zip.write_all(b"#MAGISK\n")?;
+
+    zip.start_file("module.prop", options)?;
+    zip.write_all(module_prop(&exported).as_bytes())?;
+    zip.start_file("customize.sh", options)?;
+    zip.write_all(customize_script().as_bytes())?;
+    if !enabled.is_empty() {
+        zip.start_file("service.sh", executable)?;
+        zip.write_all(service_script().as_bytes())?;
+    }
+
+    zip.start_file("pie/installed.json", options)?;
+    zip.write_all(serde_json::to_string_pretty(&exported)?.as_bytes())?;
+    zip.start_file("pie/enabled.json", options)?;
+    zip.write_all(serde_json::to_string_pretty(&enabled)?.as_bytes())?;
+
+    let mut names: Vec<&String> = exported.packages.keys().collect();
+    names.sort();
+    for name in names {
+        let package = &exported.packages[name];
+        let (root, prefix) = if package.overlay {
+            (overlay::overlay_root(), "system")
+        } else {
+            (ANDSTORE_ROOT.to_string(), "andstore")
+        };
+        for file in &package.contents {
+            add_file(
+                &mut zip,
+                &Path::new(&root).join(file),
+                &format!("{prefix}/{file}"),
+                options,
+            )?;
+        }
+    }
+
+    // Services are started by pie itself on the target device
+    let pie_in_root = exported
+        .packages
+        .values()
+        .any(|pkg| !pkg.overlay && pkg.contents.iter().any(|f| f == "bin/pie"));
+    if !enabled.is_empty() && !pie_in_root {
+        add_file(
+            &mut zip,
+            &std::env::current_exe()?,
+            "andstore/bin/pie",
+            options,
+        )?;
+    }
+
+    zip.finish()?;
+    println!("{CHECK} ({})", format_size(fs::metadata(output)?.len()));
+    println!("Flash {output} with Magisk or KernelSU on the target device");
+    println!("{}", get_separator());
+    Ok(())
+}
+
+fn add_file(
+    zip: &mut ZipWriter<fs::File>,
+    path: &Path,
+    name: &str,
+    options: SimpleFileOptions,
+) -> Result<(), PieError> {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        eprintln!("Warning: {} is missing, skipped", path.display());
+        return Ok(());
+    };
+
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(path)?;
+        zip.add_symlink(name, target.to_string_lossy(), options)?;
+    } else if meta.is_file() {
+        zip.start_file(
+            name,
+            options.unix_permissions(meta.permissions().mode() & 0o7777),
+        )?;
+        io::copy(&mut fs::File::open(path)?, zip)?;
+    }
+    Ok(())
+}
+
+fn module_prop(exported: &InstalledPackages) -> String {
+    format!(
+        "id={MODULE_ID}\n\
+         name=pie environment\n\
+         version={}\n\
+         versionCode={}\n\
+         author=pie\n\
+         description={} packages exported by pie\n",
+        env!("CARGO_PKG_VERSION"),
+        unix_now(),
+        exported.packages.len()
+    )
+}
+
+fn customize_script() -> String {
+    format!(
+        "# generated by pie module export\n\
+         ui_print \"- Installing packages into {ANDSTORE_ROOT}\"\n\
+         mkdir -p {ANDSTORE_ROOT}\n\
+         [ -d \"$MODPATH/andstore\" ] && cp -a \"$MODPATH/andstore/.\" {ANDSTORE_ROOT}/\n\
+         rm -rf \"$MODPATH/andstore\"\n\
+         mkdir -p {PIE_DATA}/services\n\
+         if [ ! -f {PIE_DATA}/installed.json ]; then\n\
+         \x20   cp \"$MODPATH/pie/installed.json\" {PIE_DATA}/installed.json\n\
+         \x20   cp \"$MODPATH/pie/enabled.json\" {PIE_DATA}/services/enabled.json\n\
+         else\n\
+         \x20   ui_print \"- pie is already set up, keeping its database\"\n\
+         fi\n\
+         rm -rf \"$MODPATH/pie\"\n\
+         set_perm_recursive \"$MODPATH\" 0 0 0755 0644\n\
+         [ -d \"$MODPATH/system/bin\" ] && set_perm_recursive \"$MODPATH/system/bin\" 0 2000 0755 0755\n"
+    )
+}
+
+fn service_script() -> String {
+    format!(
+        "#!/system/bin/sh\n\
+         # generated by pie module export\n\
+         until [ \"$(getprop sys.boot_completed)\" = 1 ]; do sleep 1; done\n\
+         {ANDSTORE_ROOT}/bin/pie service boot\n"
+    )
+}
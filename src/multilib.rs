@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::error::PieError;
+use crate::{compat_abi, extract, Architecture, Package};
+
+// 32-bit variants of library packages ("multilib": true in the index) for
+// 64-bit devices, named like foo:armeabi-v7a. Only the variant's lib/ is
+// installed, into lib32/ next to the native libraries; everything else is
+// the same as in the native package and comes from there
+pub const LIB_DIR: &str = "lib32";
+
+fn relocated(file: &str) -> Option<String> {
+    file.strip_prefix("lib/")
+        .map(|rest| format!("{LIB_DIR}/{rest}"))
+}
+
+pub fn contents(architecture: &Architecture) -> Vec<String> {
+    architecture
+        .contents
+        .iter()
+        .filter_map(|file| relocated(file))
+        .collect()
+}
+
+// Adds a variant for every multilib package with a build for the compat ABI
+// of `arch`. Dependencies on other multilib packages point at their variants
+pub fn expand(packages: &mut HashMap<String, Package>, arch: &str) {
+    let Some(abi) = compat_abi(arch) else {
+        return;
+    };
+    let has_variant =
+        |package: &Package| package.multilib && package.architectures.contains_key(abi);
+
+    let mut variants = Vec::new();
+    for (name, package) in packages.iter() {
+        if !has_variant(package) {
+            continue;
+        }
+        let dependencies = package
+            .dependencies
+            .iter()
+            .map(|dep| match packages.get(dep) {
+                Some(dependency) if has_variant(dependency) => format!("{dep}:{abi}"),
+                _ => dep.clone(),
+            })
+            .collect();
+        let mut architectures = HashMap::new();
+        architectures.insert(arch.to_string(), package.architectures[abi].clone());
+
+        variants.push((
+            format!("{name}:{abi}"),
+            Package {
+                repo: package.repo.clone(),
+                version: package.version.clone(),
+                min_api: package.min_api.clone(),
+                description: package.description.clone(),
+                license: package.license.clone(),
+                dependencies,
+                pre_depends: Vec::new(),
+                conflicts: Vec::new(),
+                replaces: Vec::new(),
+                notes: None,
+                alternatives: Vec::new(),
+                source: package.source.clone(),
+                scripts: HashMap::new(),
+                boot_scripts: Vec::new(),
+                essential: false,
+                opt: false,
+                bin: Vec::new(),
+                multilib: false,
+                abi: Some(abi.to_string()),
+                architectures,
+                services: HashMap::new(),
+                subpackages: HashMap::new(),
+                origin: package.origin.clone(),
+            },
+        ));
+    }
+    for (name, variant) in variants {
+        packages.entry(name).or_insert(variant);
+    }
+}
+
+// Moves the libraries of a variant unpacked into `scratch` to lib32/ under
+// `root` and remaps what was recorded for them. The rest stays behind and is
+// removed with `scratch`
+pub fn relocate(
+    scratch: &Path,
+    root: &str,
+    xattrs: &mut HashMap<String, HashMap<String, String>>,
+    hardlinks: &mut HashMap<String, String>,
+) -> Result<(), PieError> {
+    let libs = scratch.join("lib");
+    if libs.is_dir() {
+        let target = Path::new(root).join(LIB_DIR);
+        fs::create_dir_all(&target)?;
+        extract::move_tree(&libs, &target)?;
+    }
+
+    *xattrs = std::mem::take(xattrs)
+        .into_iter()
+        .filter_map(|(file, attrs)| Some((relocated(&file)?, attrs)))
+        .collect();
+    *hardlinks = std::mem::take(hardlinks)
+        .into_iter()
+        .filter_map(|(file, target)| Some((relocated(&file)?, relocated(&target)?)))
+        .collect();
+    Ok(())
+}
@@ -0,0 +1,241 @@
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::{ACCEPT, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::PieError;
+
+// Deliberately not Debug, credentials must never end up in output
+#[derive(Clone)]
+pub enum Auth {
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Bearer(String),
+}
+
+// A url without a scheme is treated as a path on the local filesystem
+pub fn local_path(url: &str) -> Option<&str> {
+    if let Some(path) = url.strip_prefix("file://") {
+        Some(path)
+    } else if !url.contains("://") {
+        Some(url)
+    } else {
+        None
+    }
+}
+
+// Artifact urls may be relative to the index, so a local repo can keep its
+// archives next to repo.json
+pub fn resolve_url(base: &str, url: &str) -> String {
+    if url.contains("://") || url.starts_with('/') {
+        return url.to_string();
+    }
+
+    match base.rfind('/') {
+        Some(idx) => format!("{}/{}", &base[..idx], url),
+        None => url.to_string(),
+    }
+}
+
+pub fn host(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split('/').next()?;
+    let authority = authority.rsplit('@').next()?;
+    authority.split(':').next()
+}
+
+pub fn fetch_bytes(url: &str, auth: Option<&Auth>) -> Result<Vec<u8>, PieError> {
+    if let Some(path) = local_path(url) {
+        return fs::read(path).map_err(|e| format!("Failed to read '{path}': {e}").into());
+    }
+
+    let response = send(url, auth)?;
+    Ok(response.bytes()?.to_vec())
+}
+
+pub fn post_json<T: Serialize>(url: &str, body: &T) -> Result<(), PieError> {
+    reqwest::blocking::Client::builder()
+        .user_agent(concat!("pie/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(10))
+        .build()?
+        .post(url)
+        .json(body)
+        .send()?
+        .error_for_status()?;
+    Ok(())
+}
+
+// WebDAV servers take uploads as a plain PUT
+pub fn put_file(url: &str, path: &Path, auth: Option<&Auth>) -> Result<(), PieError> {
+    let client = reqwest::blocking::Client::builder()
+        .user_agent(concat!("pie/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    authorize(client.put(url).body(fs::File::open(path)?), auth)
+        .send()?
+        .error_for_status()?;
+    Ok(())
+}
+
+// Streams the body instead of buffering it, for artifacts that are extracted
+// as they arrive. `size` is the length the index advertises, a mismatch is
+// reported up front from Content-Length or as soon as the transfer ends
+pub fn open(url: &str, auth: Option<&Auth>, size: u64) -> Result<Box<dyn Read>, PieError> {
+    let (reader, length) = open_raw(url, auth)?;
+
+    if let Some(length) = length {
+        if length != size {
+            return Err(PieError::Network(format!(
+                "Size mismatch for {url}: expected {size} bytes, server has {length}"
+            )));
+        }
+    }
+
+    Ok(Box::new(SizedReader {
+        inner: reader,
+        expected: size,
+        read: 0,
+    }))
+}
+
+// For artifacts outside any repo, where only the hash is known up front
+pub fn open_unsized(url: &str) -> Result<Box<dyn Read>, PieError> {
+    Ok(open_raw(url, None)?.0)
+}
+
+fn open_raw(url: &str, auth: Option<&Auth>) -> Result<(Box<dyn Read>, Option<u64>), PieError> {
+    match local_path(url) {
+        Some(path) => {
+            let file = fs::File::open(path).map_err(|e| format!("Failed to read '{path}': {e}"))?;
+            let length = file.metadata()?.len();
+            Ok((Box::new(file), Some(length)))
+        }
+        None => {
+            let response = send(url, auth)?;
+            let length = response.content_length();
+            Ok((Box::new(response), length))
+        }
+    }
+}
+
+// Catches truncated or oversized transfers when the server sent no
+// Content-Length, or the connection dropped
+struct SizedReader<R> {
+    inner: R,
+    expected: u64,
+    read: u64,
+}
+
+impl<R: Read> Read for SizedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+
+        if self.read > self.expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Transfer exceeds the expected {} bytes", self.expected),
+            ));
+        }
+        if n == 0 && !buf.is_empty() && self.read < self.expected {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "Transfer truncated after {} of {} bytes",
+                    self.read, self.expected
+                ),
+            ));
+        }
+        Ok(n)
+    }
+}
+
+// Short rate limit windows are waited out, longer ones are reported
+const MAX_RATE_LIMIT_WAIT: u64 = 60;
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+
+fn authorize(request: RequestBuilder, auth: Option<&Auth>) -> RequestBuilder {
+    match auth {
+        Some(Auth::Basic { username, password }) => request.basic_auth(username, password.as_ref()),
+        Some(Auth::Bearer(token)) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+fn send(url: &str, auth: Option<&Auth>) -> Result<Response, PieError> {
+    // GitHub rejects API requests without a User-Agent
+    let client = reqwest::blocking::Client::builder()
+        .user_agent(concat!("pie/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let mut attempts = 0;
+
+    loop {
+        let mut request = client.get(url);
+        // Release asset API urls return metadata unless the binary is asked for
+        if url.contains("api.github.com/") && url.contains("/releases/assets/") {
+            request = request.header(ACCEPT, "application/octet-stream");
+        }
+        let response = authorize(request, auth).send()?;
+        if !is_rate_limited(&response) {
+            return Ok(response.error_for_status()?);
+        }
+
+        let host = host(url).unwrap_or(url);
+        match retry_after(&response) {
+            Some(secs) if secs <= MAX_RATE_LIMIT_WAIT && attempts < MAX_RATE_LIMIT_RETRIES => {
+                eprintln!("Rate limited by {host}, retrying in {secs} seconds...");
+                thread::sleep(Duration::from_secs(secs));
+                attempts += 1;
+            }
+            Some(secs) => {
+                return Err(PieError::Network(format!(
+                    "Rate limited by {host}, retry in {secs} seconds"
+                )));
+            }
+            None => {
+                return Err(PieError::Network(format!(
+                    "Rate limited by {host}, retry later"
+                )))
+            }
+        }
+    }
+}
+
+// GitHub answers 403 instead of 429 once the hourly quota is used up
+fn is_rate_limited(response: &Response) -> bool {
+    match response.status() {
+        StatusCode::TOO_MANY_REQUESTS => true,
+        StatusCode::FORBIDDEN => {
+            response.headers().contains_key(RETRY_AFTER)
+                || header_value(response, "x-ratelimit-remaining") == Some(0)
+        }
+        _ => false,
+    }
+}
+
+fn retry_after(response: &Response) -> Option<u64> {
+    if let Some(secs) = header_value(response, RETRY_AFTER.as_str()) {
+        return Some(secs);
+    }
+
+    let reset = header_value(response, "x-ratelimit-reset")?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(reset.saturating_sub(now))
+}
+
+fn header_value(response: &Response, name: &str) -> Option<u64> {
+    response
+        .headers()
+        .get(name)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
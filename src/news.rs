@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use crate::error::PieError;
+use crate::glyph::BULLET;
+use crate::{get_separator, version, Repo, PIE_DATA};
+
+#[derive(Serialize, Deserialize)]
+pub struct NewsEntry {
+    // Filled in while merging
+    #[serde(default)]
+    pub repo: String,
+    pub id: String,
+    pub date: String,
+    pub title: String,
+    #[serde(default)]
+    pub body: String,
+    // Only shown to pie versions in this range, e.g. to announce a breaking
+    // change to users still on an old release
+    #[serde(default)]
+    pub min_pie: Option<String>,
+    #[serde(default)]
+    pub max_pie: Option<String>,
+}
+
+fn seen_file() -> String {
+    format!("{PIE_DATA}/news_seen.json")
+}
+
+fn seen_news() -> Result<BTreeSet<String>, PieError> {
+    if !Path::new(&seen_file()).exists() {
+        return Ok(BTreeSet::new());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(seen_file())?)?)
+}
+
+fn targets_this_version(entry: &NewsEntry) -> bool {
+    let current = env!("CARGO_PKG_VERSION");
+    entry
+        .min_pie
+        .as_deref()
+        .is_none_or(|min| version::compare_versions(current, min) != Ordering::Less)
+        && entry
+            .max_pie
+            .as_deref()
+            .is_none_or(|max| version::compare_versions(current, max) != Ordering::Greater)
+}
+
+// Each entry is shown once, ids are namespaced by repo
+pub fn show_news(repo: &Repo) -> Result<(), PieError> {
+    let mut seen = seen_news()?;
+
+    let mut unseen: Vec<&NewsEntry> = repo
+        .news
+        .iter()
+        .filter(|entry| !seen.contains(&format!("{}/{}", entry.repo, entry.id)))
+        .filter(|entry| targets_this_version(entry))
+        .collect();
+    if unseen.is_empty() {
+        return Ok(());
+    }
+    unseen.sort_by(|a, b| a.date.cmp(&b.date));
+
+    println!("\n{}", get_separator());
+    println!("NEWS");
+    println!("{}", get_separator());
+    for entry in unseen {
+        println!("{BULLET} {} [{}] {}", entry.date, entry.repo, entry.title);
+        for line in entry.body.lines() {
+            println!("  {line}");
+        }
+        seen.insert(format!("{}/{}", entry.repo, entry.id));
+    }
+    println!("{}", get_separator());
+
+    fs::create_dir_all(&PIE_DATA)?;
+    fs::write(seen_file(), serde_json::to_string_pretty(&seen)?)?;
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use std::io::{self, IsTerminal};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+const TAG: &str = "pie";
+
+// Set when the command changes installed packages, so a failing search run
+// from a script doesn't end up in the notification shade
+static TRANSACTION: OnceLock<bool> = OnceLock::new();
+
+pub fn set_transaction(transaction: bool) {
+    let _ = TRANSACTION.set(transaction);
+}
+
+// Without a terminal nobody saw the output, e.g. a boot script or a
+// scheduled upgrade
+fn unattended() -> bool {
+    TRANSACTION.get().copied().unwrap_or(false) && !io::stdin().is_terminal()
+}
+
+pub fn completed(action: &str, lines: &[String]) {
+    if lines.is_empty() {
+        return;
+    }
+    post(
+        &format!("pie: {action} {} packages", lines.len()),
+        &lines.join("\n"),
+    );
+}
+
+pub fn failed(message: &str) {
+    post("pie: transaction failed", message);
+}
+
+fn post(title: &str, text: &str) {
+    if !unattended() {
+        return;
+    }
+    let _ = Command::new("cmd")
+        .args([
+            "notification",
+            "post",
+            "-S",
+            "bigtext",
+            "-t",
+            title,
+            TAG,
+            text,
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
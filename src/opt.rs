@@ -0,0 +1,107 @@
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use crate::error::PieError;
+use crate::{Architecture, Package};
+
+// Large self-contained packages ("opt": true in the index) are unpacked into
+// opt/<name> under the andstore root instead of the shared tree. Only their
+// public binaries, "bin" in the index or everything in their bin/ by default,
+// are linked into the shared bin directory
+pub fn prefix(name: &str) -> String {
+    format!("opt/{name}")
+}
+
+fn public_binaries<'a>(package: &'a Package, architecture: &'a Architecture) -> Vec<&'a String> {
+    if !package.bin.is_empty() {
+        return package.bin.iter().collect();
+    }
+    architecture
+        .contents
+        .iter()
+        .filter(|file| file.starts_with("bin/"))
+        .collect()
+}
+
+// Relative to the andstore root like any other package's contents: the files
+// under the prefix and the links pointing into it
+pub fn contents(name: &str, package: &Package, architecture: &Architecture) -> Vec<String> {
+    let prefix = prefix(name);
+    let mut contents: Vec<String> = architecture
+        .contents
+        .iter()
+        .map(|file| format!("{prefix}/{file}"))
+        .collect();
+    for binary in public_binaries(package, architecture) {
+        if let Some(file_name) = Path::new(binary).file_name() {
+            contents.push(format!("bin/{}", file_name.to_string_lossy()));
+        }
+    }
+    contents
+}
+
+// Every entry outside the prefix is a link to the file of the same name in
+// it, bin/ preferred. Works from the recorded contents alone, so rollback
+// can restore links without the index
+pub fn link_binaries(name: &str, contents: &[String], root: &str) -> Result<(), PieError> {
+    let prefix = format!("{}/", prefix(name));
+    for link in contents.iter().filter(|file| !file.starts_with(&prefix)) {
+        let Some(file_name) = Path::new(link).file_name() else {
+            continue;
+        };
+        let preferred = format!("{prefix}bin/{}", file_name.to_string_lossy());
+        let Some(target) = contents
+            .iter()
+            .find(|file| **file == preferred)
+            .or_else(|| {
+                contents.iter().find(|file| {
+                    file.starts_with(&prefix) && Path::new(file).file_name() == Some(file_name)
+                })
+            })
+        else {
+            continue;
+        };
+
+        let path = Path::new(root).join(link);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if fs::symlink_metadata(&path).is_ok() {
+            fs::remove_file(&path)?;
+        }
+        symlink(format!("../{target}"), &path)?;
+    }
+    Ok(())
+}
+
+// The recorded contents as paths inside the archive, the way the index
+// declares maintainer scripts. Links outside the prefix are left out
+pub fn archive_contents(name: &str, contents: &[String]) -> Vec<String> {
+    let prefix = format!("{}/", prefix(name));
+    contents
+        .iter()
+        .filter_map(|file| file.strip_prefix(&prefix))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_contents_strip_the_prefix() {
+        let contents = [
+            "opt/jdk/bin/java",
+            "opt/jdk/share/jdk/pre-remove.sh",
+            "opt/jdk-tools/bin/jlink",
+            "bin/java",
+        ]
+        .map(str::to_string);
+        assert_eq!(
+            archive_contents("jdk", &contents),
+            ["bin/java", "share/jdk/pre-remove.sh"]
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::alternatives;
+use crate::error::PieError;
+use crate::glyph::CHECK;
+use crate::{
+    format_size, get_installed_packages, get_separator, save_installed_packages, ANDSTORE_ROOT,
+};
+
+// Files pie writes itself and that never belong to a package
+const PIE_OWNED: &[&str] = &["etc/profile"];
+
+pub fn scan_orphans(delete: bool, adopt: Option<&str>) -> Result<(), PieError> {
+    let mut installed = get_installed_packages()?;
+
+    if let Some(owner) = adopt {
+        if !installed.packages.contains_key(owner) {
+            return Err(PieError::Resolution(format!(
+                "Package '{owner}' is not installed"
+            )));
+        }
+    }
+
+    let managed = alternatives::managed_paths(&installed);
+    let owned: HashSet<&str> = installed
+        .packages
+        .values()
+        .filter(|pkg| !pkg.overlay)
+        .flat_map(|pkg| pkg.contents.iter().map(String::as_str))
+        .chain(PIE_OWNED.iter().copied())
+        .chain(managed.iter().map(String::as_str))
+        .collect();
+
+    let mut files = Vec::new();
+    if Path::new(&ANDSTORE_ROOT).exists() {
+        walk_files(Path::new(&ANDSTORE_ROOT), &mut files)?;
+    }
+
+    let mut orphans: Vec<String> = files
+        .into_iter()
+        .filter(|file| !owned.contains(file.as_str()))
+        .collect();
+    orphans.sort();
+
+    if orphans.is_empty() {
+        println!("No orphaned files found under {ANDSTORE_ROOT}");
+        return Ok(());
+    }
+
+    println!("{}", get_separator());
+    println!("ORPHANED FILES ({})", orphans.len());
+    println!("{}", get_separator());
+    let mut total_size = 0u64;
+    for orphan in &orphans {
+        let size = fs::symlink_metadata(format!("{ANDSTORE_ROOT}/{orphan}"))
+            .map(|meta| meta.len())
+            .unwrap_or(0);
+        total_size += size;
+        println!("  {orphan} ({})", format_size(size));
+    }
+    println!("\nTotal size: {}", format_size(total_size));
+
+    if let Some(owner) = adopt {
+        if let Some(package) = installed.packages.get_mut(owner) {
+            package.contents.extend(orphans.iter().cloned());
+        }
+        save_installed_packages(&installed)?;
+        println!("Adopted {} files into '{owner}'", orphans.len());
+    } else if delete {
+        print!("Removing orphaned files... ");
+        io::stdout().flush()?;
+        for orphan in &orphans {
+            fs::remove_file(format!("{ANDSTORE_ROOT}/{orphan}"))?;
+        }
+        println!("{CHECK}");
+    } else {
+        println!("Use --delete to remove them or --adopt <package> to take ownership");
+    }
+
+    Ok(())
+}
+
+// Collects paths relative to ANDSTORE_ROOT, symlinks are reported as files
+fn walk_files(dir: &Path, files: &mut Vec<String>) -> Result<(), PieError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_dir() {
+            walk_files(&path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(&ANDSTORE_ROOT) {
+            files.push(relative.to_string_lossy().into_owned());
+        }
+    }
+    Ok(())
+}
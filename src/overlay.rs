@@ -0,0 +1,35 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::PieError;
+use crate::paths;
+
+pub const OVERLAY_MODULE: &str = "/data/adb/modules/pie-overlay";
+
+// Magisk and KernelSU both mount a module's system/ over /system at boot
+pub fn overlay_root() -> String {
+    format!("{}/system", paths::on_device(OVERLAY_MODULE))
+}
+
+pub fn ensure_module() -> Result<(), PieError> {
+    if paths::termux() {
+        return Err("Overlay installs need root and are not available in Termux".into());
+    }
+    fs::create_dir_all(overlay_root())?;
+
+    let module_prop = format!("{}/module.prop", paths::on_device(OVERLAY_MODULE));
+    if !Path::new(&module_prop).exists() {
+        let prop = format!(
+            "id=pie-overlay\n\
+             name=pie overlay\n\
+             version={}\n\
+             versionCode=1\n\
+             author=andstore\n\
+             description=Packages installed systemlessly by pie\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        fs::write(module_prop, prop)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,571 @@
+use clap::Args;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tar::{Builder, EntryType, Header, HeaderMode};
+use zstd::stream::write::Encoder;
+
+use crate::error::PieError;
+use crate::glyph::ARROW;
+use crate::{cache, format_size, libs, merge_indices, parse_size, sha256_file, DictionaryRef};
+
+#[derive(Args)]
+pub struct PackageOptions {
+    /// Staging directory laid out like the install root
+    pub dir: PathBuf,
+    #[arg(long)]
+    pub name: String,
+    #[arg(long)]
+    pub version: String,
+    #[arg(long)]
+    pub arch: String,
+    /// Directory to write the archive to
+    #[arg(long, default_value = ".")]
+    pub output: PathBuf,
+    /// Zstd compression level (1-22)
+    #[arg(long, default_value_t = 19, value_parser = clap::value_parser!(i32).range(1..=22))]
+    pub level: i32,
+    /// Enable long distance matching, for large packages with repeated content
+    #[arg(long)]
+    pub long: bool,
+    /// Compress with a shared dictionary created by `pie dict`
+    #[arg(long, value_name = "FILE")]
+    pub dict: Option<PathBuf>,
+    /// URL of the dictionary recorded in the entry, defaults to its file name
+    #[arg(long, value_name = "URL", requires = "dict")]
+    pub dict_url: Option<String>,
+    /// Move files under the given path prefixes into a subpackage, e.g.
+    /// dev=include/,lib/pkgconfig/ for NAME-dev
+    #[arg(long, value_name = "SUFFIX=PREFIXES")]
+    pub split: Vec<String>,
+    /// Depend on the repo packages providing the shared libraries the staged
+    /// binaries link against
+    #[arg(long)]
+    pub auto_deps: bool,
+    /// Strip debug information and unneeded symbols from ELF files, with
+    /// $STRIP or llvm-strip
+    #[arg(long)]
+    pub strip: bool,
+    /// Compress executables with $UPX or upx
+    #[arg(long)]
+    pub upx: bool,
+    /// Leave executables smaller than this uncompressed
+    #[arg(long, value_name = "SIZE", default_value = "1MB", requires = "upx")]
+    pub upx_min_size: String,
+}
+
+// Mirrors the per-architecture entry of repo.json
+#[derive(Serialize)]
+struct ArchitectureEntry {
+    url: String,
+    sha256: String,
+    size: u64,
+    uncompressed_size: u64,
+    contents: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dictionary: Option<DictionaryRef>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    dependencies: Vec<String>,
+}
+
+// Shared libraries every Android release provides, see the NDK's stable APIs
+const SYSTEM_LIBRARIES: &[&str] = &[
+    "libc.so",
+    "libm.so",
+    "libdl.so",
+    "liblog.so",
+    "libz.so",
+    "libstdc++.so",
+    "libandroid.so",
+    "libEGL.so",
+    "libGLESv1_CM.so",
+    "libGLESv2.so",
+    "libGLESv3.so",
+    "libvulkan.so",
+    "libjnigraphics.so",
+    "libOpenSLES.so",
+    "libOpenMAXAL.so",
+    "libmediandk.so",
+    "libcamera2ndk.so",
+    "libnativewindow.so",
+    "libneuralnetworks.so",
+    "libsync.so",
+    "libaaudio.so",
+    "libamidi.so",
+    "libbinder_ndk.so",
+];
+
+// Library file names in the repo's packages for the target ABI and the
+// package shipping each, from the cached indices
+fn soname_index(options: &PackageOptions) -> HashMap<String, String> {
+    let Some(repo) = cache::load_cached_indices()
+        .ok()
+        .and_then(|indices| merge_indices(&indices).ok())
+    else {
+        return HashMap::new();
+    };
+    let mut names: Vec<&String> = repo.packages.keys().collect();
+    names.sort();
+
+    let mut index = HashMap::new();
+    for name in names {
+        let package = &repo.packages[name];
+        // The package being built and its subpackages provide nothing to it
+        if *name == options.name || package.origin.as_deref() == Some(options.name.as_str()) {
+            continue;
+        }
+        let Some(architecture) = package.architectures.get(&options.arch) else {
+            continue;
+        };
+        for file in &architecture.contents {
+            if let Some(file_name) = Path::new(file).file_name() {
+                let file_name = file_name.to_string_lossy();
+                if file_name.contains(".so") {
+                    index
+                        .entry(file_name.into_owned())
+                        .or_insert_with(|| name.clone());
+                }
+            }
+        }
+    }
+    index
+}
+
+// Repo packages providing what the ELF files among `entries` need. Sonames
+// the staging tree or the system provides are skipped, unknown ones are
+// reported so the maintainer can add them by hand
+fn detect_dependencies(
+    options: &PackageOptions,
+    entries: &[PathBuf],
+    staged: &HashSet<String>,
+    index: &HashMap<String, String>,
+) -> Vec<String> {
+    let mut dependencies = Vec::new();
+    for entry in entries {
+        let path = options.dir.join(entry);
+        if !fs::symlink_metadata(&path).is_ok_and(|meta| meta.is_file()) {
+            continue;
+        }
+        let Some(elf) = libs::read_elf(&path) else {
+            continue;
+        };
+        for soname in &elf.needed {
+            if staged.contains(soname) || SYSTEM_LIBRARIES.contains(&soname.as_str()) {
+                continue;
+            }
+            match index.get(soname) {
+                Some(provider) if !dependencies.contains(provider) => {
+                    eprintln!(
+                        "{} needs {soname} {ARROW} depending on {provider}",
+                        entry.display()
+                    );
+                    dependencies.push(provider.clone());
+                }
+                Some(_) => {}
+                None => eprintln!(
+                    "Warning: {} needs {soname}, which no repo package provides",
+                    entry.display()
+                ),
+            }
+        }
+    }
+    dependencies.sort();
+    dependencies
+}
+
+// Window used with long distance matching; installs raise the decoder limit
+// to match
+const LONG_WINDOW_LOG: u32 = 27;
+
+// Paths relative to `root` in sorted order, directories included so they get
+// their own archive entries
+fn walk_staging(root: &Path, dir: &Path, entries: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut children: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    children.sort_by_key(|entry| entry.file_name());
+
+    for entry in children {
+        let path = entry.path();
+        if let Ok(relative) = path.strip_prefix(root) {
+            entries.push(relative.to_path_buf());
+        }
+        if entry.file_type()?.is_dir() {
+            walk_staging(root, &path, entries)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn build_package(options: &PackageOptions) -> Result<(), PieError> {
+    let fragment = build_fragment(options)?;
+    if options.split.is_empty() {
+        let main = &fragment["architectures"][options.arch.as_str()];
+        println!("{}", serde_json::to_string_pretty(main)?);
+    } else {
+        println!("{}", serde_json::to_string_pretty(&fragment)?);
+    }
+    Ok(())
+}
+
+// Writes the archives and returns the package fragment for the index.
+// Subpackages depend on the main package by default
+pub fn build_fragment(options: &PackageOptions) -> Result<serde_json::Value, PieError> {
+    if !options.dir.is_dir() {
+        return Err(format!("'{}' is not a directory", options.dir.display()).into());
+    }
+
+    let mut entries = Vec::new();
+    walk_staging(&options.dir, &options.dir, &mut entries)?;
+
+    let splits = options
+        .split
+        .iter()
+        .map(|split| parse_split(split))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let dictionary = match &options.dict {
+        Some(path) => Some(fs::read(path)?),
+        None => None,
+    };
+
+    // Files go to the first split whose prefixes match, everything else stays
+    // in the main package
+    let mut main_entries = Vec::new();
+    let mut split_entries: Vec<Vec<PathBuf>> = vec![Vec::new(); splits.len()];
+    for entry in &entries {
+        let is_dir = fs::symlink_metadata(options.dir.join(entry))?.is_dir();
+        let split = splits.iter().position(|(_, prefixes)| {
+            prefixes
+                .iter()
+                .any(|prefix| entry.to_string_lossy().starts_with(prefix.as_str()))
+        });
+        match split {
+            Some(i) if !is_dir => split_entries[i].push(entry.clone()),
+            _ => main_entries.push(entry.clone()),
+        }
+    }
+
+    let index = if options.auto_deps {
+        let index = soname_index(options);
+        if index.is_empty() {
+            eprintln!("Warning: no cached repo index, run `pie update` to detect dependencies");
+        }
+        index
+    } else {
+        HashMap::new()
+    };
+    let staged: HashSet<String> = entries
+        .iter()
+        .filter_map(|entry| Some(entry.file_name()?.to_string_lossy().into_owned()))
+        .collect();
+
+    // Stripped and compressed copies are packaged in place of the originals,
+    // the staging tree is left alone
+    let scratch = tempfile::tempdir()?;
+    let optimized = if options.strip || options.upx {
+        optimize_binaries(options, &entries, scratch.path())?
+    } else {
+        HashMap::new()
+    };
+
+    let mut main = write_archive(
+        options,
+        &options.name,
+        &main_entries,
+        &optimized,
+        dictionary.as_deref(),
+    )?;
+    if options.auto_deps {
+        main.dependencies = detect_dependencies(options, &main_entries, &staged, &index);
+    }
+    let mut subpackages = serde_json::Map::new();
+    for ((suffix, _), files) in splits.iter().zip(split_entries) {
+        let name = format!("{}-{suffix}", options.name);
+        let entries = with_parent_dirs(&files);
+        let mut entry = write_archive(options, &name, &entries, &optimized, dictionary.as_deref())?;
+        if options.auto_deps {
+            entry.dependencies = detect_dependencies(options, &entries, &staged, &index);
+        }
+        subpackages.insert(
+            name,
+            json!({
+                "dependencies": [options.name],
+                "architectures": { options.arch.as_str(): entry },
+            }),
+        );
+    }
+
+    Ok(json!({
+        "version": options.version,
+        "architectures": { options.arch.as_str(): main },
+        "subpackages": subpackages,
+    }))
+}
+
+// "dev=include/,lib/pkgconfig/" -> ("dev", ["include/", "lib/pkgconfig/"])
+fn parse_split(split: &str) -> Result<(String, Vec<String>), PieError> {
+    let (suffix, prefixes) = split
+        .split_once('=')
+        .filter(|(suffix, prefixes)| !suffix.is_empty() && !prefixes.is_empty())
+        .ok_or_else(|| format!("Invalid split '{split}', expected SUFFIX=PREFIX[,PREFIX...]"))?;
+    let prefixes = prefixes.split(',').map(str::to_string).collect();
+    Ok((suffix.to_string(), prefixes))
+}
+
+// Directory entries keep their permissions when a split archive is unpacked
+fn with_parent_dirs(files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = files
+        .iter()
+        .flat_map(|file| file.ancestors().skip(1))
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+        .collect();
+    entries.extend(files.iter().cloned());
+    entries.sort();
+    entries.dedup();
+    entries
+}
+
+// The same staging tree always gives the same archive: entries are sorted,
+// owners and times normalized and only the mode is kept. Modification times
+// come from SOURCE_DATE_EPOCH, the epoch if unset
+fn source_date_epoch() -> Result<u64, PieError> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid SOURCE_DATE_EPOCH '{value}'").into()),
+        Err(_) => Ok(0),
+    }
+}
+
+fn entry_header(meta: &fs::Metadata, mtime: u64) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_metadata_in_mode(meta, HeaderMode::Complete);
+    header.set_mtime(mtime);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_size(0);
+    // GNU headers carry access and change times as well
+    if let Some(gnu) = header.as_gnu_mut() {
+        gnu.set_atime(mtime);
+        gnu.set_ctime(mtime);
+    }
+    header
+}
+
+fn write_archive(
+    options: &PackageOptions,
+    name: &str,
+    entries: &[PathBuf],
+    optimized: &HashMap<PathBuf, PathBuf>,
+    dictionary: Option<&[u8]>,
+) -> Result<ArchitectureEntry, PieError> {
+    let source = |entry: &PathBuf| {
+        optimized
+            .get(entry)
+            .cloned()
+            .unwrap_or_else(|| options.dir.join(entry))
+    };
+    // Further names of a file with several links become hardlink entries to
+    // the first one, so the data is stored and counted once
+    let mut links: HashMap<(u64, u64), &PathBuf> = HashMap::new();
+    let mut link_targets: HashMap<&PathBuf, &PathBuf> = HashMap::new();
+    let mut contents = Vec::new();
+    let mut uncompressed_size = 0u64;
+    for entry in entries {
+        let meta = fs::symlink_metadata(options.dir.join(entry))?;
+        if meta.is_dir() {
+            continue;
+        }
+        contents.push(entry.to_string_lossy().into_owned());
+        if meta.is_file() && meta.nlink() > 1 {
+            if let Some(first) = links.get(&(meta.dev(), meta.ino())) {
+                link_targets.insert(entry, first);
+                continue;
+            }
+            links.insert((meta.dev(), meta.ino()), entry);
+        }
+        uncompressed_size += fs::symlink_metadata(source(entry))?.len();
+    }
+
+    let file_name = format!("{}-{}-{}.tar.zst", name, options.version, options.arch);
+    fs::create_dir_all(&options.output)?;
+    let archive_path = options.output.join(&file_name);
+
+    eprintln!("Packaging {} v{}...", name, options.version);
+    let file = fs::File::create(&archive_path)?;
+    let mut encoder = match dictionary {
+        Some(dictionary) => Encoder::with_dictionary(file, options.level, dictionary)?,
+        None => Encoder::new(file, options.level)?,
+    };
+    if options.long {
+        encoder.long_distance_matching(true)?;
+        encoder.window_log(LONG_WINDOW_LOG)?;
+    }
+
+    let mtime = source_date_epoch()?;
+    let mut builder = Builder::new(encoder);
+    for entry in entries {
+        let path = source(entry);
+        let meta = fs::symlink_metadata(&path)?;
+        let mut header = entry_header(&meta, mtime);
+        if let Some(target) = link_targets.get(entry) {
+            header.set_entry_type(EntryType::Link);
+            builder.append_link(&mut header, entry, target)?;
+        } else if meta.is_symlink() {
+            builder.append_link(&mut header, entry, fs::read_link(&path)?)?;
+        } else if meta.is_file() {
+            header.set_size(meta.len());
+            builder.append_data(&mut header, entry, fs::File::open(&path)?)?;
+        } else {
+            builder.append_data(&mut header, entry, io::empty())?;
+        }
+    }
+    builder.into_inner()?.finish()?;
+
+    let size = fs::metadata(&archive_path)?.len();
+    let dictionary = match &options.dict {
+        Some(path) => Some(DictionaryRef {
+            url: options.dict_url.clone().unwrap_or_else(|| {
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            }),
+            sha256: sha256_file(path)?,
+        }),
+        None => None,
+    };
+
+    eprintln!(
+        "Wrote {} ({} {ARROW} {})",
+        archive_path.display(),
+        format_size(uncompressed_size),
+        format_size(size)
+    );
+    Ok(ArchitectureEntry {
+        url: file_name,
+        sha256: sha256_file(&archive_path)?,
+        size,
+        uncompressed_size,
+        contents,
+        dictionary,
+        dependencies: Vec::new(),
+    })
+}
+
+fn is_elf(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    fs::File::open(path)
+        .and_then(|file| file.read_exact_at(&mut magic, 0))
+        .is_ok()
+        && magic == *b"\x7fELF"
+}
+
+fn run_tool(tool: &str, args: &[&str], path: &Path) -> Result<bool, PieError> {
+    let status = Command::new(tool)
+        .args(args)
+        .arg(path)
+        .stdout(Stdio::null())
+        .status()
+        .map_err(|e| PieError::Other(format!("Failed to run {tool}: {e}")))?;
+    Ok(status.success())
+}
+
+// Copies of the staged ELF files, stripped and executables above the size
+// threshold compressed, by staging path. Each file with several links is
+// handled once, its other names share the copy
+fn optimize_binaries(
+    options: &PackageOptions,
+    entries: &[PathBuf],
+    scratch: &Path,
+) -> Result<HashMap<PathBuf, PathBuf>, PieError> {
+    let strip = std::env::var("STRIP").unwrap_or_else(|_| "llvm-strip".to_string());
+    let upx = std::env::var("UPX").unwrap_or_else(|_| "upx".to_string());
+    let upx_min_size = parse_size(&options.upx_min_size)
+        .ok_or_else(|| format!("Invalid size '{}'", options.upx_min_size))?;
+
+    let mut optimized = HashMap::new();
+    let mut copies: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    let (mut before, mut after) = (0u64, 0u64);
+    for (i, entry) in entries.iter().enumerate() {
+        let path = options.dir.join(entry);
+        let meta = fs::symlink_metadata(&path)?;
+        if !meta.is_file() || !is_elf(&path) {
+            continue;
+        }
+        if let Some(copy) = copies.get(&(meta.dev(), meta.ino())) {
+            optimized.insert(entry.clone(), copy.clone());
+            continue;
+        }
+        let copy = scratch.join(i.to_string());
+        fs::copy(&path, &copy)?;
+
+        if options.strip && !run_tool(&strip, &["--strip-unneeded"], &copy)? {
+            return Err(PieError::Other(format!(
+                "{strip} failed on {}",
+                entry.display()
+            )));
+        }
+        // Shared libraries can't be packed, only executables
+        let library = entry.to_string_lossy().contains(".so");
+        if options.upx
+            && !library
+            && meta.mode() & 0o111 != 0
+            && fs::metadata(&copy)?.len() >= upx_min_size
+            && !run_tool(&upx, &["--best", "-q"], &copy)?
+        {
+            eprintln!("Warning: {upx} could not compress {}", entry.display());
+        }
+
+        before += meta.len();
+        after += fs::metadata(&copy)?.len();
+        copies.insert((meta.dev(), meta.ino()), copy.clone());
+        optimized.insert(entry.clone(), copy);
+    }
+
+    eprintln!(
+        "Optimized {} binaries: {} {ARROW} {} (saved {})",
+        copies.len(),
+        format_size(before),
+        format_size(after),
+        format_size(before.saturating_sub(after))
+    );
+    Ok(optimized)
+}
+
+// Dictionaries pay off for many small, similar packages: train on their
+// staging trees and reference the result from each package entry
+pub fn train_dictionary(output: &Path, dirs: &[PathBuf], max_size: usize) -> Result<(), PieError> {
+    let mut samples = Vec::new();
+    for dir in dirs {
+        let mut entries = Vec::new();
+        walk_staging(dir, dir, &mut entries)?;
+        for entry in entries {
+            let path = dir.join(entry);
+            if fs::symlink_metadata(&path)?.is_file() {
+                samples.push(path);
+            }
+        }
+    }
+
+    if samples.is_empty() {
+        return Err("No sample files found".into());
+    }
+
+    println!("Training dictionary on {} files...", samples.len());
+    let dictionary = zstd::dict::from_files(&samples, max_size)?;
+    fs::write(output, &dictionary)?;
+
+    println!(
+        "Wrote {} ({}, sha256 {})",
+        output.display(),
+        format_size(dictionary.len() as u64),
+        sha256_file(output)?
+    );
+    Ok(())
+}
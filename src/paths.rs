@@ -0,0 +1,122 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::ops::Deref;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+pub const DEFAULT_ROOT: &str = "/data/local/andstore";
+const DEFAULT_DATA: &str = "/data/adb/pie";
+
+struct Paths {
+    root: String,
+    data: String,
+    termux: bool,
+}
+
+static HOST_ROOT: OnceLock<String> = OnceLock::new();
+
+// Host mode: pie runs on a workstation and every device path lives below a
+// mounted image or staging directory. Must be set before any path is used
+pub fn set_host_root(root: &str) {
+    let _ = HOST_ROOT.set(root.trim_end_matches('/').to_string());
+}
+
+pub fn host() -> bool {
+    HOST_ROOT.get().is_some()
+}
+
+// Where a fixed device path like /data/adb/modules is found from this run
+pub fn on_device(path: &str) -> String {
+    match HOST_ROOT.get() {
+        Some(root) => format!("{root}{path}"),
+        None => path.to_string(),
+    }
+}
+
+// The reverse, for paths written into files the device itself reads
+pub fn device_path(path: &str) -> &str {
+    HOST_ROOT
+        .get()
+        .and_then(|root| path.strip_prefix(root.as_str()))
+        .unwrap_or(path)
+}
+
+// Decided once per run. Inside Termux pie has no root and /data/local and
+// /data/adb are out of reach, so everything lives under the Termux prefix
+fn paths() -> &'static Paths {
+    static PATHS: OnceLock<Paths> = OnceLock::new();
+    PATHS.get_or_init(|| match HOST_ROOT.get() {
+        Some(root) => Paths {
+            root: format!("{root}{DEFAULT_ROOT}"),
+            data: format!("{root}{DEFAULT_DATA}"),
+            termux: false,
+        },
+        None => match termux_prefix() {
+            Some(prefix) => Paths {
+                root: format!("{prefix}/opt/andstore"),
+                data: format!("{prefix}/var/lib/pie"),
+                termux: true,
+            },
+            None => Paths {
+                root: DEFAULT_ROOT.to_string(),
+                data: DEFAULT_DATA.to_string(),
+                termux: false,
+            },
+        },
+    })
+}
+
+// Termux exports PREFIX and TERMUX_VERSION to every shell it starts. Running
+// as root (tsu, su from Termux) keeps the regular locations
+fn termux_prefix() -> Option<String> {
+    let prefix = std::env::var("PREFIX").ok()?;
+    if !prefix.contains("/com.termux/") && std::env::var_os("TERMUX_VERSION").is_none() {
+        return None;
+    }
+    let uid = std::fs::metadata("/proc/self").ok()?.uid();
+    (uid != 0).then_some(prefix)
+}
+
+pub fn termux() -> bool {
+    paths().termux
+}
+
+// A path picked at runtime that reads like the constant it replaced: it
+// formats, derefs to &str and can be passed wherever a path is expected
+pub struct Location(fn(&'static Paths) -> &'static str);
+
+pub static ANDSTORE_ROOT: Location = Location(|paths| paths.root.as_str());
+pub static PIE_DATA: Location = Location(|paths| paths.data.as_str());
+
+impl Deref for Location {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        (self.0)(paths())
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self)
+    }
+}
+
+impl AsRef<str> for Location {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl AsRef<OsStr> for Location {
+    fn as_ref(&self) -> &OsStr {
+        OsStr::new(&**self)
+    }
+}
+
+impl AsRef<Path> for Location {
+    fn as_ref(&self) -> &Path {
+        Path::new(&**self)
+    }
+}
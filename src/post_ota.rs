@@ -0,0 +1,89 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::error::PieError;
+use crate::glyph::{CHECK, CROSS};
+use crate::{
+    alternatives, get_installed_packages, get_separator, init, logcat, package_root, service,
+    ANDSTORE_ROOT,
+};
+
+// Meant for a boot script after a system update: puts back what an OTA can
+// wipe or relabel, and only reports what needs a reinstall instead of
+// downloading anything. Returns false when packages are missing files
+pub fn post_ota() -> Result<bool, PieError> {
+    let installed = get_installed_packages()?;
+
+    println!("{}", get_separator());
+    println!("POST-OTA REPAIR");
+    println!("{}", get_separator());
+
+    print!("Checking {ANDSTORE_ROOT}... ");
+    io::stdout().flush()?;
+    let mut broken: Vec<(String, usize)> = installed
+        .packages
+        .values()
+        .filter_map(|pkg| {
+            let root = package_root(pkg.overlay);
+            let missing = pkg
+                .contents
+                .iter()
+                .filter(|file| fs::symlink_metadata(Path::new(&root).join(file)).is_err())
+                .count();
+            (missing > 0).then(|| (pkg.name.clone(), missing))
+        })
+        .collect();
+    broken.sort();
+    if broken.is_empty() {
+        println!("{CHECK} ({} packages intact)", installed.packages.len());
+    } else {
+        println!("{CROSS}");
+    }
+
+    print!("Re-applying PATH profile... ");
+    io::stdout().flush()?;
+    init::write_profile()?;
+    if init::install_profile_hook()? {
+        println!("{CHECK}");
+    } else {
+        println!("{CHECK} (profile only, no shell hook)");
+    }
+
+    print!("Relabeling files... ");
+    io::stdout().flush()?;
+    if init::relabel() {
+        println!("{CHECK}");
+    } else {
+        println!("skipped");
+    }
+
+    print!("Re-registering services... ");
+    io::stdout().flush()?;
+    if service::enabled_services()?.is_empty() {
+        println!("none enabled");
+    } else {
+        service::write_boot_script()?;
+        println!("{CHECK}");
+    }
+
+    print!("Refreshing alternatives... ");
+    io::stdout().flush()?;
+    alternatives::refresh(&installed)?;
+    println!("{CHECK}");
+
+    if !broken.is_empty() {
+        println!("\nPackages that need a reinstall ({}):", broken.len());
+        for (name, missing) in &broken {
+            println!("  {CROSS} {name} ({missing} files missing)");
+        }
+        let names: Vec<&str> = broken.iter().map(|(name, _)| name.as_str()).collect();
+        logcat::warn(&format!(
+            "post-ota: reinstall needed for {}",
+            names.join(", ")
+        ));
+    }
+    println!("{}", get_separator());
+
+    Ok(broken.is_empty())
+}
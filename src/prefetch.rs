@@ -0,0 +1,159 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Read, Seek, Write};
+use std::sync::{Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+
+use crate::config;
+use crate::error::PieError;
+use crate::github::{self, ReleaseAsset};
+use crate::net::{self, Auth};
+use crate::{package_architecture, signal, Architecture, Package, Repo, PIE_DATA};
+
+// Where an archive comes from, owned so a download thread can take it
+pub struct Source {
+    url: String,
+    github: Option<ReleaseAsset>,
+    ipfs: Option<String>,
+    auth: Option<Auth>,
+    size: u64,
+    sha256: String,
+}
+
+impl Source {
+    pub fn new(
+        repo: &Repo,
+        package: &Package,
+        architecture: &Architecture,
+    ) -> Result<Source, PieError> {
+        Ok(Source {
+            url: architecture.url.clone(),
+            github: architecture.github.clone(),
+            ipfs: architecture.ipfs.clone(),
+            auth: repo.auth_for(&package.repo, &architecture.url)?,
+            size: architecture.size,
+            sha256: architecture.sha256.clone(),
+        })
+    }
+
+    // For an index that isn't merged, where urls are still relative to it
+    pub fn unmerged(
+        base: &str,
+        auth: Option<&Auth>,
+        architecture: &Architecture,
+    ) -> Result<Source, PieError> {
+        let url = if architecture.url.is_empty() {
+            String::new()
+        } else {
+            net::resolve_url(base, &architecture.url)
+        };
+        Ok(Source {
+            auth: config::url_auth(base, auth, &url)?,
+            url,
+            github: architecture.github.clone(),
+            ipfs: architecture.ipfs.clone(),
+            size: architecture.size,
+            sha256: architecture.sha256.clone(),
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn open(&self) -> Result<Box<dyn Read>, PieError> {
+        if let Some(dir) = ARCHIVE_DIR.get() {
+            let path = format!("{dir}/{}", self.sha256);
+            if let Ok(file) = fs::File::open(path) {
+                return Ok(Box::new(file));
+            }
+        }
+        if let Some(cid) = &self.ipfs {
+            let gateway = config::load_config()
+                .map(|config| config.ipfs_gateway)
+                .unwrap_or_else(|_| config::DEFAULT_IPFS_GATEWAY.to_string());
+            let url = format!("{}/ipfs/{cid}", gateway.trim_end_matches('/'));
+            match net::open(&url, None, self.size) {
+                Ok(reader) => return Ok(reader),
+                Err(e) if self.url.is_empty() && self.github.is_none() => return Err(e),
+                Err(e) => eprintln!("Warning: IPFS gateway failed ({e}), using the HTTP mirror"),
+            }
+        }
+        match &self.github {
+            Some(asset) => {
+                let auth = github::auth()?;
+                let url = github::download_url(asset, auth.as_ref())?;
+                net::open(&url, auth.as_ref(), self.size)
+            }
+            None => net::open(&self.url, self.auth.as_ref(), self.size),
+        }
+    }
+}
+
+// Archives provided ahead of time and named by their SHA-256, e.g. pushed by
+// `pie remote`. Extraction verifies them like a download
+static ARCHIVE_DIR: OnceLock<String> = OnceLock::new();
+
+pub fn set_archive_dir(dir: &str) {
+    let _ = ARCHIVE_DIR.set(dir.to_string());
+}
+
+// The download of the package after the one being extracted, so the network
+// stays busy while the disk works. Only one runs ahead at a time
+static NEXT: Mutex<Option<(String, JoinHandle<Result<fs::File, PieError>>)>> = Mutex::new(None);
+
+pub fn start(repo: &Repo, name: &str) {
+    let Ok((package, architecture)) = package_architecture(repo, name) else {
+        return;
+    };
+    let Ok(source) = Source::new(repo, package, architecture) else {
+        return;
+    };
+    let handle = thread::spawn(move || download(&source));
+    if let Ok(mut next) = NEXT.lock() {
+        *next = Some((name.to_string(), handle));
+    }
+}
+
+// Waits for the prefetched archive if it is this package. A failed prefetch
+// is dropped, the regular download then reports the error
+pub fn take(name: &str) -> Option<Box<dyn Read>> {
+    let (prefetched, handle) = NEXT.lock().ok()?.take()?;
+    if prefetched != name {
+        return None;
+    }
+    let file = handle.join().ok()?.ok()?;
+    Some(Box::new(file))
+}
+
+// Verified here already, extraction hashes it again on the way through
+pub fn download(source: &Source) -> Result<fs::File, PieError> {
+    let dir = format!("{PIE_DATA}/cache");
+    fs::create_dir_all(&dir)?;
+    let mut file = tempfile::tempfile_in(&dir)?;
+    save(source, &mut file)?;
+    file.rewind()?;
+    Ok(file)
+}
+
+pub fn save(source: &Source, file: &mut impl Write) -> Result<(), PieError> {
+    let mut reader = source.open()?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        if signal::interrupted() {
+            return Err(PieError::Interrupted);
+        }
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        file.write_all(&buf[..n])?;
+    }
+
+    if hex::encode(hasher.finalize()) != source.sha256 {
+        return Err(PieError::Checksum(source.url.clone()));
+    }
+    Ok(())
+}
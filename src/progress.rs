@@ -0,0 +1,175 @@
+use serde_json::{json, Value};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::error::PieError;
+
+// Newline-delimited JSON events for frontends drawing their own progress UI,
+// enabled with --progress-json or subscribed to through `pie daemon`. Every
+// event has an "event" field:
+//   phase     phase: resolve, install, upgrade or remove
+//   package   action, package, index and total of the transaction
+//   download  package, bytes, total and percent, once per percent
+//   done      action and the packages it was applied to
+//   error     kind, code and message, like --error-format json
+enum Sink {
+    Stdout,
+    File(fs::File),
+    // A daemon client, sent each event as a JSON-RPC notification
+    Client(u64, UnixStream),
+}
+
+static SINKS: Mutex<Vec<Sink>> = Mutex::new(Vec::new());
+static NEXT_CLIENT: AtomicU64 = AtomicU64::new(0);
+
+// Set while a line of regular output is unfinished, e.g. during a download,
+// so an event written to stdout still starts on a line of its own
+static LINE_OPEN: AtomicBool = AtomicBool::new(false);
+
+pub fn enable(destination: &str) -> Result<(), PieError> {
+    let sink = if destination == "-" {
+        Sink::Stdout
+    } else {
+        // Opening a named pipe waits for the frontend to open it for reading
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(destination)
+            .map_err(|e| format!("Failed to open '{destination}' for progress events: {e}"))?;
+        Sink::File(file)
+    };
+    if let Ok(mut sinks) = SINKS.lock() {
+        sinks.push(sink);
+    }
+    Ok(())
+}
+
+pub fn subscribe(stream: UnixStream) -> u64 {
+    let id = NEXT_CLIENT.fetch_add(1, Ordering::Relaxed);
+    if let Ok(mut sinks) = SINKS.lock() {
+        sinks.push(Sink::Client(id, stream));
+    }
+    id
+}
+
+pub fn unsubscribe(id: u64) {
+    if let Ok(mut sinks) = SINKS.lock() {
+        sinks.retain(|sink| !matches!(sink, Sink::Client(client, _) if *client == id));
+    }
+}
+
+fn enabled() -> bool {
+    SINKS.lock().is_ok_and(|sinks| !sinks.is_empty())
+}
+
+fn emit(event: &str, mut fields: Value) {
+    let Ok(mut sinks) = SINKS.lock() else {
+        return;
+    };
+    if sinks.is_empty() {
+        return;
+    }
+    fields["event"] = json!(event);
+    let line = format!("{fields}\n");
+    // A frontend that went away must not fail the transaction, clients that
+    // hung up are dropped
+    sinks.retain_mut(|sink| match sink {
+        Sink::Stdout => {
+            let mut stdout = io::stdout().lock();
+            if LINE_OPEN.swap(false, Ordering::Relaxed) {
+                let _ = stdout.write_all(b"\n");
+            }
+            let _ = stdout
+                .write_all(line.as_bytes())
+                .and_then(|_| stdout.flush());
+            true
+        }
+        Sink::File(file) => {
+            let _ = file.write_all(line.as_bytes());
+            true
+        }
+        Sink::Client(_, stream) => {
+            let notification = json!({ "jsonrpc": "2.0", "method": "progress", "params": fields });
+            stream
+                .write_all(format!("{notification}\n").as_bytes())
+                .is_ok()
+        }
+    });
+}
+
+pub fn open_line() {
+    if enabled() {
+        LINE_OPEN.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn phase(phase: &str) {
+    emit("phase", json!({ "phase": phase }));
+}
+
+pub fn package(action: &str, name: &str, index: usize, total: usize) {
+    emit(
+        "package",
+        json!({ "action": action, "package": name, "index": index, "total": total }),
+    );
+}
+
+pub fn done(action: &str, packages: &[String]) {
+    emit("done", json!({ "action": action, "packages": packages }));
+}
+
+pub fn error(e: &PieError) {
+    emit(
+        "error",
+        json!({ "kind": e.kind(), "code": e.exit_code(), "message": e.to_string() }),
+    );
+}
+
+struct Tracked {
+    inner: Box<dyn Read>,
+    name: String,
+    bytes: u64,
+    total: u64,
+    percent: Option<u64>,
+}
+
+impl Read for Tracked {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes += n as u64;
+        let percent = (self.bytes * 100)
+            .checked_div(self.total)
+            .unwrap_or(100)
+            .min(100);
+        if self.percent != Some(percent) {
+            self.percent = Some(percent);
+            emit(
+                "download",
+                json!({
+                    "package": self.name,
+                    "bytes": self.bytes,
+                    "total": self.total,
+                    "percent": percent,
+                }),
+            );
+        }
+        Ok(n)
+    }
+}
+
+// Counts the archive of `name` as it streams through extraction
+pub fn track(name: &str, total: u64, reader: Box<dyn Read>) -> Box<dyn Read> {
+    if !enabled() {
+        return reader;
+    }
+    Box::new(Tracked {
+        inner: reader,
+        name: name.to_string(),
+        bytes: 0,
+        total,
+        percent: None,
+    })
+}
@@ -0,0 +1,62 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::OnceLock;
+
+// Set once from -y/--assume-no (or the config default) before any command runs
+static ASSUMED_ANSWER: OnceLock<Option<bool>> = OnceLock::new();
+
+pub fn set_assumed_answer(answer: Option<bool>) {
+    let _ = ASSUMED_ANSWER.set(answer);
+}
+
+pub fn assumed_answer() -> Option<bool> {
+    ASSUMED_ANSWER.get().copied().flatten()
+}
+
+// Under `adb shell pie ...`, cron or a pipe nobody can answer, and read_line
+// would block forever or take whatever the pipe holds
+pub fn interactive() -> bool {
+    io::stdin().is_terminal()
+}
+
+// For answers -y must never give, the user types the expected text out
+pub fn confirm_typed(question: &str, expected: &str) -> io::Result<bool> {
+    if assumed_answer().is_some() || !interactive() {
+        return Ok(false);
+    }
+
+    print!("{question} ({expected}): ");
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.split_whitespace().eq(expected.split_whitespace()))
+}
+
+pub fn confirm(question: &str, default: bool) -> io::Result<bool> {
+    let hint = if default { "[Y/n]" } else { "[y/N]" };
+
+    if let Some(answer) = assumed_answer() {
+        println!("{question} {hint}: {}", if answer { "y" } else { "n" });
+        return Ok(answer);
+    }
+
+    if !interactive() {
+        println!("{question} {hint}");
+        return Err(io::Error::other(
+            "Not running in a terminal, pass -y (--assume-yes) or --assume-no to answer prompts",
+        ));
+    }
+
+    print!("{question} {hint}: ");
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    let input = input.trim().to_lowercase();
+
+    if default {
+        Ok(input != "n" && input != "no")
+    } else {
+        Ok(input == "y" || input == "yes")
+    }
+}
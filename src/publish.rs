@@ -0,0 +1,335 @@
+use clap::Args;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::config;
+use crate::error::PieError;
+use crate::glyph::CHECK;
+use crate::net::{self, Auth};
+use crate::packaging::{self, PackageOptions};
+use crate::sha256_file;
+
+const INDEX: &str = "repo.json";
+
+#[derive(Args)]
+pub struct PublishOptions {
+    #[command(flatten)]
+    pub package: PackageOptions,
+    /// Where repo.json lives: a git checkout of the repo, a WebDAV directory
+    /// (https://...) or s3://bucket/prefix through the aws CLI
+    #[arg(long, value_name = "DEST")]
+    pub to: String,
+    /// Public URL of repo.json to verify the uploads through, defaults to
+    /// DEST/repo.json for WebDAV
+    #[arg(long, value_name = "URL")]
+    pub index_url: Option<String>,
+    /// Sign repo.json with this SSH key, written to repo.json.sig
+    #[arg(long, value_name = "KEY")]
+    pub sign_key: Option<PathBuf>,
+}
+
+// Archive urls in the index are relative, so every backend keeps them next
+// to repo.json
+enum Backend<'a> {
+    Git(&'a Path),
+    WebDav(&'a str),
+    S3(&'a str),
+}
+
+impl<'a> Backend<'a> {
+    fn parse(to: &'a str) -> Result<Self, PieError> {
+        if to.starts_with("s3://") {
+            return Ok(Backend::S3(to.trim_end_matches('/')));
+        }
+        if to.starts_with("http://") || to.starts_with("https://") {
+            return Ok(Backend::WebDav(to.trim_end_matches('/')));
+        }
+        let dir = Path::new(to);
+        if !dir.join(".git").exists() {
+            return Err(
+                format!("'{to}' is not a git checkout, a WebDAV url or an s3:// location").into(),
+            );
+        }
+        Ok(Backend::Git(dir))
+    }
+
+    fn read_index(&self, auth: Option<&Auth>) -> Result<Vec<u8>, PieError> {
+        match self {
+            Backend::Git(dir) => Ok(fs::read(dir.join(INDEX))?),
+            Backend::WebDav(url) => net::fetch_bytes(&format!("{url}/{INDEX}"), auth),
+            Backend::S3(url) => {
+                let output = Command::new("aws")
+                    .args(["s3", "cp", &format!("{url}/{INDEX}"), "-"])
+                    .stderr(Stdio::inherit())
+                    .output()
+                    .map_err(|e| PieError::Other(format!("Failed to run aws: {e}")))?;
+                if !output.status.success() {
+                    return Err(PieError::Network(format!(
+                        "Failed to download {url}/{INDEX}"
+                    )));
+                }
+                Ok(output.stdout)
+            }
+        }
+    }
+
+    fn upload(&self, path: &Path, name: &str, auth: Option<&Auth>) -> Result<(), PieError> {
+        match self {
+            Backend::Git(dir) => {
+                let target = dir.join(name);
+                if path != target {
+                    fs::copy(path, target)?;
+                }
+            }
+            Backend::WebDav(url) => net::put_file(&format!("{url}/{name}"), path, auth)?,
+            Backend::S3(url) => {
+                let path = path.to_string_lossy();
+                run(
+                    "aws",
+                    &["s3", "cp", "--quiet", &path, &format!("{url}/{name}")],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // The other backends are live as soon as the index is uploaded
+    fn commit(&self, files: &[String], message: &str) -> Result<(), PieError> {
+        let Backend::Git(dir) = self else {
+            return Ok(());
+        };
+        let dir = dir.to_string_lossy();
+        let mut add = vec!["-C", &*dir, "add", "--"];
+        add.extend(files.iter().map(String::as_str));
+        run("git", &add)?;
+        run("git", &["-C", &dir, "commit", "-q", "-m", message])?;
+        run("git", &["-C", &dir, "push", "-q"])
+    }
+}
+
+fn run(tool: &str, args: &[&str]) -> Result<(), PieError> {
+    let status = Command::new(tool)
+        .args(args)
+        .status()
+        .map_err(|e| PieError::Other(format!("Failed to run {tool}: {e}")))?;
+    if !status.success() {
+        return Err(PieError::Other(format!("{tool} {} failed", args.join(" "))));
+    }
+    Ok(())
+}
+
+// Builds the package, uploads the archives, then the updated index, and
+// downloads everything back through the public url
+pub fn publish(options: &PublishOptions) -> Result<(), PieError> {
+    let package = &options.package;
+    let backend = Backend::parse(&options.to)?;
+    let auth = match backend {
+        Backend::WebDav(url) => config::host_auth(url)?,
+        _ => None,
+    };
+
+    // Fails early when the destination is unreachable, before anything is
+    // built
+    let mut index: Value = serde_json::from_slice(&backend.read_index(auth.as_ref())?)
+        .map_err(|e| PieError::Schema(format!("Invalid index at {}: {e}", options.to)))?;
+
+    let mut fragment = packaging::build_fragment(package)?;
+    let uploads = artifacts(package, &fragment);
+    merge_fragment(&mut index, package, &mut fragment)?;
+
+    for (path, name) in &uploads {
+        print!("Uploading {name}... ");
+        io::stdout().flush()?;
+        backend.upload(path, name, auth.as_ref())?;
+        println!("{CHECK}");
+    }
+
+    let staging = tempfile::tempdir()?;
+    let index_path = match backend {
+        Backend::Git(dir) => dir.join(INDEX),
+        _ => staging.path().join(INDEX),
+    };
+    fs::write(&index_path, serde_json::to_string_pretty(&index)? + "\n")?;
+    let mut files: Vec<String> = uploads.iter().map(|(_, name)| name.clone()).collect();
+    files.push(INDEX.to_string());
+
+    if let Some(key) = &options.sign_key {
+        let signature = index_path.with_file_name(format!("{INDEX}.sig"));
+        if signature.exists() {
+            fs::remove_file(&signature)?;
+        }
+        let key = key.to_string_lossy();
+        let index_arg = index_path.to_string_lossy();
+        run(
+            "ssh-keygen",
+            &["-q", "-Y", "sign", "-n", "pie", "-f", &key, &index_arg],
+        )?;
+        if !matches!(backend, Backend::Git(_)) {
+            backend.upload(&signature, &format!("{INDEX}.sig"), auth.as_ref())?;
+        }
+        files.push(format!("{INDEX}.sig"));
+    }
+    if !matches!(backend, Backend::Git(_)) {
+        print!("Uploading {INDEX}... ");
+        io::stdout().flush()?;
+        backend.upload(&index_path, INDEX, auth.as_ref())?;
+        println!("{CHECK}");
+    }
+    backend.commit(
+        &files,
+        &format!("{} {} ({})", package.name, package.version, package.arch),
+    )?;
+
+    let index_url = match (&options.index_url, &backend) {
+        (Some(url), _) => url.clone(),
+        (None, Backend::WebDav(url)) => format!("{url}/{INDEX}"),
+        (None, _) => {
+            println!(
+                "Published {} v{}, pass --index-url to verify the uploads",
+                package.name, package.version
+            );
+            return Ok(());
+        }
+    };
+    verify(&index_url, &uploads, package)?;
+    println!("Published {} v{}", package.name, package.version);
+    Ok(())
+}
+
+// Archives written by the build, and the dictionary when it is published
+// next to them, with the names they get in the repo
+fn artifacts(package: &PackageOptions, fragment: &Value) -> Vec<(PathBuf, String)> {
+    let arch = package.arch.as_str();
+    let entries = std::iter::once(&fragment["architectures"][arch]).chain(
+        fragment["subpackages"]
+            .as_object()
+            .into_iter()
+            .flat_map(|subpackages| subpackages.values())
+            .map(|subpackage| &subpackage["architectures"][arch]),
+    );
+
+    let mut uploads = Vec::new();
+    for entry in entries {
+        if let Some(url) = entry["url"].as_str() {
+            uploads.push((package.output.join(url), url.to_string()));
+        }
+    }
+    if let (Some(path), Some(url)) = (
+        &package.dict,
+        fragment["architectures"][arch]["dictionary"]["url"].as_str(),
+    ) {
+        if !url.contains("://") {
+            uploads.push((path.clone(), url.to_string()));
+        }
+    }
+    uploads
+}
+
+// Replaces the build for the target ABI. Builds of an older version for other
+// ABIs are dropped, they would otherwise be installed as the new version
+fn merge_fragment(
+    index: &mut Value,
+    package: &PackageOptions,
+    fragment: &mut Value,
+) -> Result<(), PieError> {
+    let arch = package.arch.as_str();
+    let packages = index
+        .get_mut("packages")
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| PieError::Schema(format!("{INDEX} has no packages")))?;
+    let entry = packages.entry(package.name.clone()).or_insert_with(|| {
+        json!({
+            "version": package.version,
+            "min_api": null,
+            "dependencies": [],
+            "conflicts": [],
+            "architectures": {},
+        })
+    });
+    if !entry.is_object() {
+        return Err(PieError::Schema(format!(
+            "{} in {INDEX} is not an object",
+            package.name
+        )));
+    }
+
+    let previous = entry["version"].as_str().unwrap_or_default().to_string();
+    let upgraded = previous != package.version;
+    if upgraded {
+        let mut stale: Vec<&String> = entry["architectures"]
+            .as_object()
+            .into_iter()
+            .flat_map(|architectures| architectures.keys())
+            .filter(|other| *other != arch)
+            .collect();
+        stale.sort();
+        for other in stale {
+            eprintln!(
+                "Warning: dropping the {other} build of {} v{previous}, publish it again for v{}",
+                package.name, package.version
+            );
+        }
+        entry["version"] = json!(package.version);
+        entry["architectures"] = json!({});
+    }
+    entry["architectures"][arch] = fragment["architectures"][arch].take();
+
+    let Some(subpackages) = fragment["subpackages"].as_object_mut() else {
+        return Ok(());
+    };
+    for (name, subpackage) in subpackages {
+        match entry["subpackages"].get_mut(name.as_str()) {
+            Some(existing) => {
+                if upgraded {
+                    existing["architectures"] = json!({});
+                }
+                existing["architectures"][arch] = subpackage["architectures"][arch].take();
+            }
+            None => {
+                if entry.get("subpackages").is_none_or(Value::is_null) {
+                    entry["subpackages"] = json!({});
+                }
+                entry["subpackages"][name.as_str()] = subpackage.take();
+            }
+        }
+    }
+    Ok(())
+}
+
+// CDNs may serve the old index for a while, only the archives have to match
+fn verify(
+    index_url: &str,
+    uploads: &[(PathBuf, String)],
+    package: &PackageOptions,
+) -> Result<(), PieError> {
+    let auth = config::host_auth(index_url)?;
+    for (path, name) in uploads {
+        let url = net::resolve_url(index_url, name);
+        print!("Verifying {url}... ");
+        io::stdout().flush()?;
+        let content = net::fetch_bytes(&url, auth.as_ref())?;
+        if hex::encode(Sha256::digest(&content)) != sha256_file(path)? {
+            println!();
+            return Err(PieError::Checksum(url));
+        }
+        println!("{CHECK}");
+    }
+
+    let published = net::fetch_bytes(index_url, auth.as_ref())
+        .ok()
+        .and_then(|content| serde_json::from_slice::<Value>(&content).ok());
+    let version = published
+        .as_ref()
+        .and_then(|index| index["packages"][package.name.as_str()]["version"].as_str());
+    if version != Some(package.version.as_str()) {
+        eprintln!(
+            "Warning: {index_url} doesn't list v{} yet, it may be cached",
+            package.version
+        );
+    }
+    Ok(())
+}
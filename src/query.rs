@@ -0,0 +1,512 @@
+use serde_json::{Map, Value as Json};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::error::PieError;
+use crate::{fetch_repo, get_arch, get_installed_packages, glob, parse_size, version};
+
+// Filters packages with expressions like
+// 'installed && size > 10MB && repo == main'. Comparisons combine with &&,
+// || and ! and can be grouped in parentheses; a bare boolean attribute is
+// true when set. ~ matches a shell-style pattern, and on list attributes
+// == and != test membership
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Bool,
+    Size,
+    Text,
+    Version,
+    List,
+}
+
+const ATTRIBUTES: &[(&str, Kind)] = &[
+    ("name", Kind::Text),
+    ("version", Kind::Version),
+    ("description", Kind::Text),
+    ("license", Kind::Text),
+    ("repo", Kind::Text),
+    ("state", Kind::Text),
+    ("installed", Kind::Bool),
+    ("upgradable", Kind::Bool),
+    ("explicit", Kind::Bool),
+    ("available", Kind::Bool),
+    ("size", Kind::Size),
+    ("installed_size", Kind::Size),
+    ("arch", Kind::List),
+    ("depends", Kind::List),
+];
+
+enum Value {
+    Bool(bool),
+    Size(u64),
+    Text(String),
+    List(Vec<String>),
+}
+
+type Record = BTreeMap<&'static str, Value>;
+
+pub fn run_query(expression: &str, json: bool) -> Result<(), PieError> {
+    print_matches(&query(expression)?, json)
+}
+
+// Matching packages with all their attributes
+pub fn query(expression: &str) -> Result<Vec<Json>, PieError> {
+    let query = parse(expression)?;
+    Ok(records()?
+        .iter()
+        .filter(|record| query.matches(record))
+        .map(to_json)
+        .collect())
+}
+
+pub fn print_matches(matched: &[Json], json: bool) -> Result<(), PieError> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(matched)?);
+    } else {
+        for name in matched.iter().filter_map(|record| record["name"].as_str()) {
+            println!("{name}");
+        }
+    }
+    Ok(())
+}
+
+// Every package in the index plus installed ones no repo carries anymore.
+// Installed packages report the version on the device
+fn records() -> Result<Vec<Record>, PieError> {
+    let repo = fetch_repo()?;
+    let installed = get_installed_packages()?;
+    let arch = get_arch().ok();
+
+    let mut names: Vec<&String> = repo
+        .packages
+        .keys()
+        .chain(installed.packages.keys())
+        .collect();
+    names.sort();
+    names.dedup();
+
+    let records = names
+        .into_iter()
+        .map(|name| {
+            let package = repo.packages.get(name);
+            let local = installed.packages.get(name);
+            let architecture = package
+                .zip(arch.as_deref())
+                .and_then(|(package, arch)| package.architectures.get(arch));
+            let upgradable = match (package, local) {
+                (Some(package), Some(local)) => {
+                    version::compare_versions(&package.version, &local.version) == Ordering::Greater
+                }
+                _ => false,
+            };
+            let state = match local {
+                Some(_) if upgradable => "upgradable",
+                Some(_) => "installed",
+                None => "available",
+            };
+
+            let mut arches: Vec<String> = package
+                .map(|p| p.architectures.keys().cloned().collect())
+                .unwrap_or_default();
+            arches.sort();
+            let depends = match (local, package) {
+                (Some(local), _) if !local.dependencies.is_empty() => local.dependencies.clone(),
+                (_, Some(package)) => package.dependencies.clone(),
+                _ => Vec::new(),
+            };
+            let text = |value: Option<&String>| Value::Text(value.cloned().unwrap_or_default());
+
+            Record::from([
+                ("name", Value::Text(name.clone())),
+                (
+                    "version",
+                    text(local.map(|l| &l.version).or(package.map(|p| &p.version))),
+                ),
+                (
+                    "description",
+                    text(
+                        package
+                            .and_then(|p| p.description.as_ref())
+                            .or(local.and_then(|l| l.description.as_ref())),
+                    ),
+                ),
+                ("license", text(package.and_then(|p| p.license.as_ref()))),
+                (
+                    "repo",
+                    text(local.map(|l| &l.repo).or(package.map(|p| &p.repo))),
+                ),
+                ("state", Value::Text(state.to_string())),
+                ("installed", Value::Bool(local.is_some())),
+                ("upgradable", Value::Bool(upgradable)),
+                ("explicit", Value::Bool(local.is_some_and(|l| l.explicit))),
+                ("available", Value::Bool(architecture.is_some())),
+                ("size", Value::Size(architecture.map_or(0, |a| a.size))),
+                (
+                    "installed_size",
+                    Value::Size(match local {
+                        Some(local) => local.size,
+                        None => architecture.map_or(0, |a| a.uncompressed_size),
+                    }),
+                ),
+                ("arch", Value::List(arches)),
+                ("depends", Value::List(depends)),
+            ])
+        })
+        .collect();
+    Ok(records)
+}
+
+fn to_json(record: &Record) -> Json {
+    let object: Map<String, Json> = record
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Bool(b) => Json::from(*b),
+                Value::Size(n) => Json::from(*n),
+                Value::Text(s) => Json::from(s.as_str()),
+                Value::List(items) => Json::from(items.clone()),
+            };
+            (key.to_string(), value)
+        })
+        .collect();
+    Json::Object(object)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Match,
+}
+
+enum Operand {
+    Bool(bool),
+    Size(u64),
+    Text(String),
+}
+
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Flag(&'static str),
+    Compare(&'static str, Kind, Op, Operand),
+}
+
+impl Expr {
+    fn matches(&self, record: &Record) -> bool {
+        match self {
+            Expr::And(a, b) => a.matches(record) && b.matches(record),
+            Expr::Or(a, b) => a.matches(record) || b.matches(record),
+            Expr::Not(e) => !e.matches(record),
+            Expr::Flag(attr) => matches!(record.get(attr), Some(Value::Bool(true))),
+            Expr::Compare(attr, kind, op, operand) => match (record.get(attr), operand) {
+                (Some(Value::Bool(value)), Operand::Bool(expected)) => {
+                    (value == expected) == (*op == Op::Eq)
+                }
+                (Some(Value::Size(value)), Operand::Size(expected)) => {
+                    ordered(*op, value.cmp(expected))
+                }
+                (Some(Value::Text(value)), Operand::Text(expected)) => match op {
+                    Op::Match => glob::matches(expected, value),
+                    _ if *kind == Kind::Version => {
+                        ordered(*op, version::compare_versions(value, expected))
+                    }
+                    _ => ordered(*op, value.as_str().cmp(expected)),
+                },
+                (Some(Value::List(items)), Operand::Text(expected)) => match op {
+                    Op::Match => items.iter().any(|item| glob::matches(expected, item)),
+                    Op::Ne => !items.contains(expected),
+                    _ => items.contains(expected),
+                },
+                _ => false,
+            },
+        }
+    }
+}
+
+fn ordered(op: Op, ordering: Ordering) -> bool {
+    match op {
+        Op::Eq | Op::Match => ordering == Ordering::Equal,
+        Op::Ne => ordering != Ordering::Equal,
+        Op::Lt => ordering == Ordering::Less,
+        Op::Le => ordering != Ordering::Greater,
+        Op::Gt => ordering == Ordering::Greater,
+        Op::Ge => ordering != Ordering::Less,
+    }
+}
+
+#[derive(PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Op(Op),
+    Word(String),
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, PieError> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '=' => {
+                chars.next_if_eq(&'=');
+                Token::Op(Op::Eq)
+            }
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '~' => Token::Op(Op::Match),
+            '\'' | '"' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some(ch) => word.push(ch),
+                        None => return Err(invalid("unterminated quote")),
+                    }
+                }
+                Token::Word(word)
+            }
+            '&' | '|' => return Err(invalid(&format!("'{c}' must be doubled"))),
+            _ => {
+                let mut word = c.to_string();
+                while let Some(ch) =
+                    chars.next_if(|ch| !ch.is_whitespace() && !"()&|!=<>~'\"".contains(*ch))
+                {
+                    word.push(ch);
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn invalid(message: &str) -> PieError {
+    PieError::Other(format!("Invalid query: {message}"))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+fn parse(expression: &str) -> Result<Expr, PieError> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        pos: 0,
+    };
+    let expr = parser.or()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(invalid("unexpected input after the end of the expression"));
+    }
+    Ok(expr)
+}
+
+impl Parser {
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.pos) == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, PieError> {
+        let mut expr = self.and()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, PieError> {
+        let mut expr = self.unary()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, PieError> {
+        if self.eat(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat(&Token::Open) {
+            let expr = self.or()?;
+            if !self.eat(&Token::Close) {
+                return Err(invalid("missing ')'"));
+            }
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, PieError> {
+        let Some(Token::Word(word)) = self.tokens.get(self.pos) else {
+            return Err(invalid("expected an attribute"));
+        };
+        let Some(&(attr, kind)) = ATTRIBUTES.iter().find(|(name, _)| *name == word.as_str()) else {
+            let known: Vec<&str> = ATTRIBUTES.iter().map(|(name, _)| *name).collect();
+            return Err(invalid(&format!(
+                "unknown attribute '{word}', expected one of: {}",
+                known.join(", ")
+            )));
+        };
+        self.pos += 1;
+
+        let Some(&Token::Op(op)) = self.tokens.get(self.pos) else {
+            if kind == Kind::Bool {
+                return Ok(Expr::Flag(attr));
+            }
+            return Err(invalid(&format!("'{attr}' needs a comparison")));
+        };
+        self.pos += 1;
+        let Some(Token::Word(value)) = self.tokens.get(self.pos) else {
+            return Err(invalid(&format!(
+                "expected a value to compare '{attr}' with"
+            )));
+        };
+        self.pos += 1;
+
+        let allowed = match kind {
+            Kind::Bool => matches!(op, Op::Eq | Op::Ne),
+            Kind::Size => op != Op::Match,
+            Kind::Text | Kind::List => matches!(op, Op::Eq | Op::Ne | Op::Match),
+            Kind::Version => true,
+        };
+        if !allowed {
+            return Err(invalid(&format!("operator not supported for '{attr}'")));
+        }
+
+        let operand = match kind {
+            Kind::Bool => match value.as_str() {
+                "true" => Operand::Bool(true),
+                "false" => Operand::Bool(false),
+                _ => return Err(invalid(&format!("'{attr}' is true or false"))),
+            },
+            Kind::Size => Operand::Size(
+                parse_size(value).ok_or_else(|| invalid(&format!("'{value}' is not a size")))?,
+            ),
+            _ => Operand::Text(value.clone()),
+        };
+        Ok(Expr::Compare(attr, kind, op, operand))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> Record {
+        Record::from([
+            ("name", Value::Text("ripgrep".to_string())),
+            ("version", Value::Text("14.1.0".to_string())),
+            ("description", Value::Text("Recursive grep".to_string())),
+            ("repo", Value::Text("main".to_string())),
+            ("installed", Value::Bool(true)),
+            ("upgradable", Value::Bool(false)),
+            ("explicit", Value::Bool(false)),
+            ("size", Value::Size(2 << 20)),
+            ("depends", Value::List(vec!["libpcre2".to_string()])),
+        ])
+    }
+
+    fn eval(expression: &str) -> bool {
+        parse(expression)
+            .unwrap_or_else(|e| panic!("{expression}: {e}"))
+            .matches(&record())
+    }
+
+    fn error(expression: &str) -> String {
+        match parse(expression) {
+            Ok(_) => panic!("{expression} parsed"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        // (installed || upgradable) && explicit would be false
+        assert!(eval("installed || upgradable && explicit"));
+        assert!(!eval("upgradable && explicit || explicit"));
+        assert!(!eval("(installed || upgradable) && explicit"));
+    }
+
+    #[test]
+    fn not_and_parentheses() {
+        assert!(eval("!upgradable"));
+        assert!(!eval("!installed"));
+        assert!(eval("!!installed"));
+        assert!(eval("!(upgradable || explicit) && installed"));
+        assert!(!eval("!(installed && name == ripgrep)"));
+    }
+
+    #[test]
+    fn ne_is_not_negation() {
+        assert!(eval("repo != extra"));
+        assert!(!eval("repo != main"));
+        assert!(eval("repo!=extra"));
+        assert!(eval("!repo == extra"));
+        assert!(eval("depends != zlib"));
+        assert!(!eval("depends != libpcre2"));
+    }
+
+    #[test]
+    fn quoted_values() {
+        assert!(eval("description == 'Recursive grep'"));
+        assert!(eval("description == \"Recursive grep\""));
+        assert!(eval("description ~ '*grep'"));
+        assert!(eval("name == 'rip(grep)' || name == ripgrep"));
+    }
+
+    #[test]
+    fn comparisons() {
+        assert!(eval("version >= 14.0 && version < 14.10"));
+        assert!(eval("name ~ rip*"));
+        assert!(eval("installed == true && explicit == false"));
+        assert!(eval("depends == libpcre2"));
+    }
+
+    #[test]
+    fn size_units() {
+        assert!(eval("size == 2MB"));
+        assert!(eval("size == 2048K"));
+        assert!(eval("size > 1.5M && size < 0.01GB"));
+        assert!(eval("size == 2097152"));
+        assert!(error("size > 10XB").contains("not a size"));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(error("name == 'ripgrep").contains("unterminated quote"));
+        assert!(error("color == red").contains("unknown attribute 'color'"));
+        assert!(error("installed & explicit").contains("'&' must be doubled"));
+        assert!(error("installed | explicit").contains("'|' must be doubled"));
+        assert!(error("(installed").contains("missing ')'"));
+        assert!(error("name").contains("needs a comparison"));
+        assert!(error("name ==").contains("expected a value"));
+        assert!(error("installed explicit").contains("unexpected input"));
+        assert!(error("size ~ 1MB").contains("operator not supported"));
+        assert!(error("installed == yes").contains("true or false"));
+    }
+}
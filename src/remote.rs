@@ -0,0 +1,199 @@
+use clap::Subcommand;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use crate::error::PieError;
+use crate::explain;
+use crate::glyph::{CHECK, CROSS};
+use crate::{
+    expand_groups, fetch_repo, format_size, get_api_level, get_arch, get_separator,
+    package_architecture, prefetch, resolve_dependencies, sysprop, InstalledPackages,
+};
+
+// Where pushed archives wait until the device's pie installed them
+const REMOTE_DIR: &str = "/data/local/tmp/pie-remote";
+const DEVICE_PIE: &str = "/data/local/andstore/bin/pie";
+
+#[derive(Subcommand)]
+pub enum RemoteAction {
+    /// Download packages here, push them and install them with the device's pie
+    Install {
+        #[arg(required = true)]
+        packages: Vec<String>,
+        /// Install into a systemless Magisk/KernelSU module instead of the andstore root
+        #[arg(long)]
+        overlay: bool,
+        /// pie binary on the device
+        #[arg(long, default_value = DEVICE_PIE)]
+        pie: String,
+    },
+}
+
+pub fn run_remote(serial: Option<&str>, action: RemoteAction) -> Result<(), PieError> {
+    let adb = Adb { serial };
+    match action {
+        RemoteAction::Install {
+            packages,
+            overlay,
+            pie,
+        } => install(&adb, &packages, overlay, &pie),
+    }
+}
+
+// Config, repo indices and downloads of remote runs stay on the workstation,
+// laid out like a host mode target root
+pub fn workspace() -> Result<String, PieError> {
+    let base = match std::env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => dir,
+        _ => format!(
+            "{}/.local/share",
+            std::env::var("HOME").map_err(|_| "HOME is not set")?
+        ),
+    };
+    let workspace = format!("{base}/pie");
+    fs::create_dir_all(&workspace)?;
+    Ok(workspace)
+}
+
+struct Adb<'a> {
+    serial: Option<&'a str>,
+}
+
+impl Adb<'_> {
+    fn command(&self) -> Command {
+        let mut command = Command::new("adb");
+        if let Some(serial) = self.serial {
+            command.args(["-s", serial]);
+        }
+        command
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String, PieError> {
+        let output = self
+            .command()
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| PieError::Other(format!("Failed to run adb: {e}")))?;
+        if !output.status.success() {
+            return Err(PieError::Other(format!(
+                "adb {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+// The device's database isn't read, so dependencies it already has are
+// pushed as well and skipped by its pie
+fn install(adb: &Adb, packages: &[String], overlay: bool, pie: &str) -> Result<(), PieError> {
+    println!("\n{}", get_separator());
+    println!("REMOTE INSTALL");
+    println!("{}", get_separator());
+
+    print!("Connecting to the device... ");
+    io::stdout().flush()?;
+    let props = match adb.run(&["shell", "getprop"]) {
+        Ok(output) => parse_getprop(&output),
+        Err(e) => {
+            println!("{CROSS}");
+            return Err(e);
+        }
+    };
+    let model = props.get("ro.product.model").cloned().unwrap_or_default();
+    sysprop::use_props(props);
+    let (arch, api) = (get_arch()?, get_api_level()?);
+    println!("{CHECK} ({model}, {arch}, API {api})");
+
+    println!("Fetching repository information...");
+    let repo = fetch_repo()?;
+    let (names, _) = expand_groups(&repo, packages)?;
+    let none = InstalledPackages::default();
+    explain::check(&repo, &names, &none, &[])?;
+    let mut transaction: Vec<String> = Vec::new();
+    for name in &names {
+        for dep in resolve_dependencies(&repo, name, &none)? {
+            if !transaction.contains(&dep) {
+                transaction.push(dep);
+            }
+        }
+        if !transaction.contains(name) {
+            transaction.push(name.clone());
+        }
+    }
+
+    let dir = tempfile::tempdir()?;
+    for name in &transaction {
+        let (package, architecture) = package_architecture(&repo, name)?;
+        print!("Downloading {name} v{}... ", package.version);
+        io::stdout().flush()?;
+        let source = prefetch::Source::new(&repo, package, architecture)?;
+        let mut file = match prefetch::download(&source) {
+            Ok(file) => file,
+            Err(e) => {
+                println!("{CROSS}");
+                return Err(e);
+            }
+        };
+        io::copy(
+            &mut file,
+            &mut fs::File::create(dir.path().join(&architecture.sha256))?,
+        )?;
+        println!("{CHECK} ({})", format_size(architecture.size));
+    }
+
+    print!("Pushing {} archives... ", transaction.len());
+    io::stdout().flush()?;
+    adb.run(&[
+        "shell",
+        &format!("rm -rf {REMOTE_DIR} && mkdir -p {REMOTE_DIR}"),
+    ])?;
+    adb.run(&["push", &format!("{}/.", dir.path().display()), REMOTE_DIR])?;
+    println!("{CHECK}");
+
+    let mut command = format!("{pie} --assume-yes install --archives {REMOTE_DIR}");
+    if overlay {
+        command.push_str(" --overlay");
+    }
+    for name in &names {
+        command.push_str(&format!(" '{name}'"));
+    }
+    // adbd runs as root on userdebug builds, elsewhere pie needs su
+    if adb.run(&["shell", "id -u"])?.trim() != "0" {
+        command = format!("su -c \"{command}\"");
+    }
+
+    println!("Running pie on the device...");
+    let status = adb.command().args(["shell", &command]).status()?;
+    if let Err(e) = adb.run(&["shell", &format!("rm -rf {REMOTE_DIR}")]) {
+        eprintln!("Warning: could not clean up {REMOTE_DIR}: {e}");
+    }
+    if !status.success() {
+        return Err(PieError::Other(
+            "pie on the device failed to install the packages".to_string(),
+        ));
+    }
+
+    println!("{}", get_separator());
+    println!("Installed on the device: {}", names.join(", "));
+    println!("{}", get_separator());
+    Ok(())
+}
+
+// `getprop` without arguments prints "[name]: [value]" lines
+fn parse_getprop(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once("]: [")?;
+            Some((
+                name.strip_prefix('[')?.to_string(),
+                value.strip_suffix(']')?.to_string(),
+            ))
+        })
+        .collect()
+}
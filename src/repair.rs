@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::error::PieError;
+use crate::glyph::{BULLET, CHECK};
+use crate::{
+    fetch_repo, get_arch, hash_contents, package_contents, package_root, prompt,
+    save_installed_packages, InstalledPackage, InstalledPackages,
+};
+
+// Called when installed.json doesn't parse. Carrying on with an empty
+// database would make every installed package look absent, so the user
+// picks between the backup and a rebuild from the files on disk
+pub fn recover_database(
+    path: &str,
+    error: serde_json::Error,
+) -> Result<InstalledPackages, PieError> {
+    println!("The installed database {path} is corrupt: {error}");
+
+    let backup = format!("{path}.bak");
+    let restored = fs::read_to_string(&backup)
+        .ok()
+        .and_then(|content| serde_json::from_str::<InstalledPackages>(&content).ok());
+
+    let installed = match restored {
+        Some(installed)
+            if prompt::confirm(
+                &format!(
+                    "Restore the backup from the previous transaction ({} packages)?",
+                    installed.packages.len()
+                ),
+                true,
+            )? =>
+        {
+            installed
+        }
+        _ => {
+            if !prompt::confirm("Rebuild the database from the installed files?", true)? {
+                return Err(PieError::Schema(format!(
+                    "Invalid installed database '{path}': {error}"
+                )));
+            }
+            rebuild()?
+        }
+    };
+
+    // Kept for inspection, and so saving doesn't back up the corrupt copy
+    fs::rename(path, format!("{path}.corrupt"))?;
+    save_installed_packages(&installed)?;
+    println!("Recovered {} packages", installed.packages.len());
+    Ok(installed)
+}
+
+// A repo package whose every file is present is taken as installed. Which
+// packages were asked for is lost, so all of them count as explicit
+fn rebuild() -> Result<InstalledPackages, PieError> {
+    println!("Fetching repository information...");
+    let repo = fetch_repo()?;
+    let arch = get_arch()?;
+
+    print!("Matching files against the repo... ");
+    io::stdout().flush()?;
+    let mut names: Vec<&String> = repo.packages.keys().collect();
+    names.sort();
+    let mut packages = HashMap::new();
+    for name in names {
+        let package = &repo.packages[name];
+        let Some(architecture) = package.architectures.get(&arch) else {
+            continue;
+        };
+        if architecture.contents.is_empty() {
+            continue;
+        }
+        let Some(overlay) = [false, true].into_iter().find(|overlay| {
+            let root = package_root(*overlay);
+            package_contents(name, package, architecture, *overlay)
+                .iter()
+                .all(|file| fs::symlink_metadata(Path::new(&root).join(file)).is_ok())
+        }) else {
+            continue;
+        };
+
+        let root = package_root(overlay);
+        let contents = package_contents(name, package, architecture, overlay);
+        packages.insert(
+            name.clone(),
+            InstalledPackage {
+                name: name.clone(),
+                version: package.version.clone(),
+                description: package.description.clone(),
+                hashes: hash_contents(&root, &contents),
+                contents,
+                services: package.services.clone(),
+                overlay,
+                opt: package.opt && !overlay,
+                abi: package.abi.clone(),
+                explicit: true,
+                repo: package.repo.clone(),
+                size: architecture.uncompressed_size,
+                dependencies: package.dependencies.clone(),
+                conflicts: package.conflicts.clone(),
+                alternatives: package.alternatives.clone(),
+                scripts: package.scripts.clone(),
+                boot_scripts: Vec::new(),
+                xattrs: HashMap::new(),
+                hardlinks: HashMap::new(),
+            },
+        );
+    }
+    println!("{CHECK}");
+
+    let mut found: Vec<(&String, &InstalledPackage)> = packages.iter().collect();
+    found.sort_by_key(|(name, _)| *name);
+    for (name, package) in found {
+        println!("  {BULLET} {name} v{}", package.version);
+    }
+    println!("Versions are the repo's, files of older versions may not match them");
+    Ok(InstalledPackages { packages })
+}
@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::config::{self, Config};
+use crate::error::PieError;
+use crate::paths;
+use crate::{get_installed_packages, install_packages, InstalledPackages, PIE_DATA};
+
+// Internal storage survives ROM flashes and is the place users copy off the
+// device before a factory reset
+pub const MANIFEST_BACKUP: &str = "/sdcard/pie/manifest.json";
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    packages: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config: Option<Config>,
+}
+
+fn build_manifest(installed: &InstalledPackages) -> Result<Manifest, PieError> {
+    let mut packages: Vec<String> = installed
+        .packages
+        .values()
+        .filter(|pkg| pkg.explicit)
+        .map(|pkg| pkg.name.clone())
+        .collect();
+    packages.sort();
+
+    let config_file = format!("{PIE_DATA}/config.json");
+    let config = if Path::new(&config_file).exists() {
+        // The backup is world-readable and export prints it, so repo
+        // credentials stay on the device
+        let mut config = config::load_config()?;
+        for repo in &mut config.repos {
+            repo.username = None;
+            repo.password = None;
+            repo.token = None;
+        }
+        Some(config)
+    } else {
+        None
+    };
+
+    Ok(Manifest { packages, config })
+}
+
+// Called after every database write; failing to reach the sdcard must never
+// fail a transaction
+pub fn backup_manifest(installed: &InstalledPackages) {
+    let Ok(manifest) = build_manifest(installed) else {
+        return;
+    };
+    let Ok(content) = serde_json::to_string_pretty(&manifest) else {
+        return;
+    };
+
+    let backup = paths::on_device(MANIFEST_BACKUP);
+    if let Some(parent) = Path::new(&backup).parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let _ = fs::write(&backup, content);
+}
+
+pub fn export_manifest(path: Option<&str>) -> Result<(), PieError> {
+    let installed = get_installed_packages()?;
+    let content = serde_json::to_string_pretty(&build_manifest(&installed)?)?;
+
+    match path {
+        Some(path) => {
+            fs::write(path, content)?;
+            println!("Exported manifest to {path}");
+        }
+        None => println!("{content}"),
+    }
+
+    Ok(())
+}
+
+pub fn restore(path: Option<&str>) -> Result<(), PieError> {
+    let backup = paths::on_device(MANIFEST_BACKUP);
+    let path = path.unwrap_or(&backup);
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read manifest '{path}': {e}"))?;
+    let manifest: Manifest = serde_json::from_str(&content)
+        .map_err(|e| PieError::Schema(format!("Invalid manifest '{path}': {e}")))?;
+
+    // Never clobber a config the user already set up on this device
+    let config_file = format!("{PIE_DATA}/config.json");
+    if let Some(config) = &manifest.config {
+        if !Path::new(&config_file).exists() {
+            config::save_config(config)?;
+            println!("Restored config from {path}");
+        }
+    }
+
+    if manifest.packages.is_empty() {
+        println!("Manifest contains no packages");
+        return Ok(());
+    }
+
+    println!("Restoring {} packages from {path}", manifest.packages.len());
+    install_packages(&manifest.packages, config::overlay_default()?)
+}
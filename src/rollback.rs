@@ -0,0 +1,165 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::error::PieError;
+use crate::glyph::{ARROW, CHECK, CROSS};
+use crate::{
+    boot, cache, extract, get_installed_packages, get_separator, hooks, logcat, multilib, opt,
+    package_root, prompt, save_installed_packages, signal, upgrade, PIE_DATA,
+};
+
+// Reinstalls a cached version from the archive and the record kept with it,
+// without touching the network. Dependencies are taken as they are
+pub fn rollback(name: &str, version: Option<&str>) -> Result<(), PieError> {
+    let installed = get_installed_packages()?;
+    let Some(current) = installed.packages.get(name) else {
+        return Err(PieError::Resolution(format!(
+            "Package '{name}' is not installed"
+        )));
+    };
+
+    let cached = cache::cached_versions(name)?;
+    let target = match version {
+        Some(version) => cached
+            .into_iter()
+            .find(|cached| cached.package.version == version)
+            .ok_or_else(|| {
+                PieError::Resolution(format!("{name} v{version} is not in the cache"))
+            })?,
+        None => cached
+            .into_iter()
+            .find(|cached| cached.package.version != current.version)
+            .ok_or_else(|| {
+                PieError::Resolution(format!("No previous version of '{name}' is cached"))
+            })?,
+    };
+    let version = target.package.version.clone();
+    if version == current.version {
+        println!("{name} v{version} is already installed");
+        return Ok(());
+    }
+
+    println!("\n{}", get_separator());
+    println!("ROLLBACK");
+    println!("{}", get_separator());
+    println!("{name} v{} {ARROW} v{version}", current.version);
+
+    if !prompt::confirm("\nProceed with rollback?", true)? {
+        println!("Rollback cancelled");
+        return Ok(());
+    }
+
+    let hook_packages = vec![hooks::HookPackage {
+        name: name.to_string(),
+        version: version.clone(),
+    }];
+    hooks::run_hooks("pre-install", &hook_packages)?;
+
+    let _critical = signal::critical();
+    restore(name, target)?;
+
+    println!("Rolled back {name} to v{version}");
+    logcat::info(&format!("Rolled back {name} to v{version}"));
+    hooks::run_hooks("post-install", &hook_packages)?;
+    Ok(())
+}
+
+// Extracts a cached version over the installed one without asking, which is
+// also how an interrupted upgrade is undone
+pub fn restore(name: &str, target: cache::CachedVersion) -> Result<(), PieError> {
+    let mut installed = get_installed_packages()?;
+    let Some(current) = installed.packages.get(name) else {
+        return Err(PieError::Resolution(format!(
+            "Package '{name}' is not installed"
+        )));
+    };
+    let version = target.package.version.clone();
+
+    let dictionary = match &target.dictionary {
+        Some(hash) => Some(
+            fs::read(format!("{PIE_DATA}/cache/dict/{hash}")).map_err(|_| {
+                PieError::Other(format!(
+                    "The dictionary {name} v{version} was compressed with is no longer cached"
+                ))
+            })?,
+        ),
+        None => None,
+    };
+
+    let (overlay, explicit) = (current.overlay, current.explicit);
+    let old_contents = current.contents.clone();
+    let old_boot_scripts = current.boot_scripts.clone();
+    let root = package_root(overlay);
+    let scratch = match target.package.abi {
+        Some(_) => Some(
+            tempfile::Builder::new()
+                .prefix(extract::STAGING_PREFIX)
+                .tempdir_in(&root)?,
+        ),
+        None => None,
+    };
+    let package_dir = if target.package.opt {
+        format!("{root}/{}", opt::prefix(name))
+    } else if let Some(scratch) = &scratch {
+        scratch.path().to_string_lossy().into_owned()
+    } else {
+        root.clone()
+    };
+
+    print!("Extracting {name} v{version} from the cache... ");
+    io::stdout().flush()?;
+    let reader = Box::new(fs::File::open(cache::archive_path(name, &version))?);
+    let extracted = match extract::extract_verified(
+        name,
+        reader,
+        dictionary.as_deref(),
+        Path::new(&package_dir),
+        &target.sha256,
+    ) {
+        Ok(extracted) => extracted,
+        Err(e) => {
+            println!("{CROSS}");
+            return Err(e);
+        }
+    };
+    println!("{CHECK}");
+    cache::touch_archive(name, &version);
+
+    let mut package = target.package;
+    package.overlay = overlay;
+    package.explicit = explicit;
+    if package.opt {
+        opt::link_binaries(name, &package.contents, &root)?;
+    } else if let Some(scratch) = &scratch {
+        let (mut xattrs, mut hardlinks) = (extracted.xattrs, extracted.hardlinks);
+        multilib::relocate(scratch.path(), &root, &mut xattrs, &mut hardlinks)?;
+        package.xattrs = xattrs;
+        package.hardlinks = hardlinks;
+    } else {
+        package.xattrs = extracted.xattrs;
+        package.hardlinks = extracted.hardlinks;
+    }
+    // Boot scripts are named relative to the package's own files
+    let package_files: Vec<String> = if package.opt {
+        let prefix = format!("{}/", opt::prefix(name));
+        package
+            .contents
+            .iter()
+            .filter_map(|file| file.strip_prefix(&prefix).map(str::to_string))
+            .collect()
+    } else {
+        package.contents.clone()
+    };
+    package.boot_scripts =
+        boot::install_scripts(name, &target.boot_scripts, &package_files, &package_dir)?;
+    boot::remove_scripts(
+        old_boot_scripts
+            .iter()
+            .filter(|script| !package.boot_scripts.contains(script)),
+    )?;
+
+    installed.packages.insert(name.to_string(), package);
+    upgrade::remove_stale_files(name, &old_contents, &installed)?;
+    save_installed_packages(&installed)
+}
@@ -0,0 +1,157 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Component, Path};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::error::PieError;
+use crate::{exec, extract, paths, prompt, ANDSTORE_ROOT, PIE_DATA};
+
+// Maintainer scripts ship inside the package and are declared in the index,
+// e.g. "scripts": {"post-install": "share/foo/setup.sh"}. Unlike the user's
+// own hooks they come from whoever built the package, so they run with a
+// minimal environment in a scratch directory, without network or shared
+// mounts where the kernel allows, and only once the user approved them
+const APPROVED_FILE: &str = "scripts_approved.json";
+
+pub fn run_maintainer_script(
+    name: &str,
+    version: &str,
+    hook: &str,
+    scripts: &HashMap<String, String>,
+    contents: &[String],
+    root: &str,
+) -> Result<(), PieError> {
+    let Some(script) = scripts.get(hook) else {
+        return Ok(());
+    };
+
+    // Only files of the package itself may run
+    let relative = Path::new(script);
+    if !contents.contains(script)
+        || relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        eprintln!(
+            "Warning: {hook} script '{script}' of {name} is not part of the package, skipped"
+        );
+        return Ok(());
+    }
+    let path = Path::new(root).join(relative);
+
+    // Written for the device's shell and tools, there is nothing to run it on
+    if paths::host() {
+        println!("Skipping {hook} script of {name} in host mode");
+        return Ok(());
+    }
+
+    if !approve(name, hook, &path)? {
+        println!("Skipping {hook} script of {name}");
+        return Ok(());
+    }
+
+    // A staging directory, so crash recovery cleans it up like any other
+    let work = tempfile::Builder::new()
+        .prefix(extract::STAGING_PREFIX)
+        .tempdir_in(root)?;
+
+    println!("Running {hook} script of {name}...");
+    let mut command = sandboxed_command(&path, work.path());
+    command
+        .env("PIE_HOOK", hook)
+        .env("PIE_PACKAGE", name)
+        .env("PIE_VERSION", version);
+    let result = exec::run_script(command, &format!("{hook} script of {name}"), None);
+
+    match result {
+        Ok(true) => Ok(()),
+        Ok(false) | Err(_) if hook.starts_with("pre-") => {
+            Err(format!("{hook} script of {name} failed, aborting").into())
+        }
+        Ok(false) => {
+            eprintln!("Warning: {hook} script of {name} failed");
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Warning: {hook} script of {name} failed: {e}");
+            Ok(())
+        }
+    }
+}
+
+fn sandboxed_command(script: &Path, work: &Path) -> Command {
+    let mut command = if namespaces_available() {
+        let mut command = Command::new("unshare");
+        command.args(["--mount", "--net", "--", "sh"]);
+        command
+    } else {
+        Command::new("sh")
+    };
+
+    command
+        .arg(script)
+        .current_dir(work)
+        .env_clear()
+        .env(
+            "PATH",
+            format!("/system/bin:/system/xbin:{ANDSTORE_ROOT}/bin"),
+        )
+        .env("HOME", work)
+        .env("TMPDIR", work)
+        .env("ANDSTORE_ROOT", &ANDSTORE_ROOT);
+    command
+}
+
+// Needs root and a kernel built with mount and network namespaces
+fn namespaces_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        Command::new("unshare")
+            .args(["--mount", "--net", "--", "true"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    })
+}
+
+// Asked once per package, the answer is remembered across versions
+fn approve(name: &str, hook: &str, path: &Path) -> Result<bool, PieError> {
+    let approved_file = format!("{PIE_DATA}/{APPROVED_FILE}");
+    let mut approved: BTreeSet<String> = match fs::read_to_string(&approved_file) {
+        Ok(content) => serde_json::from_str(&content)?,
+        Err(_) => BTreeSet::new(),
+    };
+    if approved.contains(name) {
+        return Ok(true);
+    }
+
+    // Approval is permanent, so -y, unattended upgrades and the daemon never
+    // give it, someone has to read the script
+    if prompt::assumed_answer().is_some() || !prompt::interactive() {
+        println!("\n{name} ships a {hook} script, run pie in a terminal without -y to review it");
+        return Ok(false);
+    }
+
+    println!("\n{name} ships a {hook} script ({}):", path.display());
+    let content = fs::read_to_string(path).unwrap_or_default();
+    for line in content.lines().take(20) {
+        println!("  | {line}");
+    }
+    if content.lines().count() > 20 {
+        println!("  | ...");
+    }
+
+    if !prompt::confirm_typed(
+        &format!("Type the package name to allow {name} to run maintainer scripts"),
+        name,
+    )? {
+        return Ok(false);
+    }
+
+    approved.insert(name.to_string());
+    fs::create_dir_all(&PIE_DATA)?;
+    fs::write(&approved_file, serde_json::to_string_pretty(&approved)?)?;
+    Ok(true)
+}
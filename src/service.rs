@@ -0,0 +1,309 @@
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::PieError;
+use crate::glyph::BULLET;
+use crate::{
+    config, exec, get_installed_packages, paths, InstalledPackages, ANDSTORE_ROOT, PIE_DATA,
+};
+
+const BOOT_SCRIPT: &str = "/data/adb/service.d/pie-services.sh";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ServiceSpec {
+    pub command: String,
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    #[serde(default)]
+    pub restart: RestartPolicy,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Always,
+    #[default]
+    OnFailure,
+    Never,
+}
+
+#[derive(Subcommand)]
+pub enum ServiceAction {
+    /// Start a service
+    Start { name: String },
+    /// Stop a running service
+    Stop { name: String },
+    /// Stop and start a service again
+    Restart { name: String },
+    /// Show the state of one or all services
+    Status { name: Option<String> },
+    /// Start a service on every boot
+    Enable { name: String },
+    /// Stop starting a service on boot
+    Disable { name: String },
+    /// Start all enabled services, used by the generated boot script
+    #[command(hide = true)]
+    Boot,
+}
+
+pub fn run_service(action: ServiceAction) -> Result<(), PieError> {
+    let installed = get_installed_packages()?;
+
+    if paths::host()
+        && !matches!(
+            action,
+            ServiceAction::Enable { .. }
+                | ServiceAction::Disable { .. }
+                | ServiceAction::Status { .. }
+        )
+    {
+        return Err("Services can only be started on the device, not in host mode".into());
+    }
+
+    match action {
+        ServiceAction::Start { name } => start(&name, find_service(&installed, &name)?)?,
+        ServiceAction::Stop { name } => {
+            find_service(&installed, &name)?;
+            stop(&name)?;
+        }
+        ServiceAction::Restart { name } => {
+            let spec = find_service(&installed, &name)?;
+            stop(&name)?;
+            start(&name, spec)?;
+        }
+        ServiceAction::Status { name } => status(&installed, name.as_deref())?,
+        ServiceAction::Enable { name } => {
+            find_service(&installed, &name)?;
+            let mut enabled = enabled_services()?;
+            enabled.insert(name.clone());
+            save_enabled_services(&enabled)?;
+            write_boot_script()?;
+            println!("Service '{name}' will start on boot");
+        }
+        ServiceAction::Disable { name } => {
+            let mut enabled = enabled_services()?;
+            enabled.remove(&name);
+            save_enabled_services(&enabled)?;
+            println!("Service '{name}' will no longer start on boot");
+        }
+        ServiceAction::Boot => {
+            for name in enabled_services()? {
+                match find_service(&installed, &name) {
+                    Ok(spec) => {
+                        if let Err(e) = start(&name, spec) {
+                            eprintln!("Failed to start '{name}': {e}");
+                        }
+                    }
+                    Err(e) => eprintln!("{e}"),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn find_service<'a>(
+    installed: &'a InstalledPackages,
+    name: &str,
+) -> Result<&'a ServiceSpec, PieError> {
+    installed
+        .packages
+        .values()
+        .find_map(|pkg| pkg.services.get(name))
+        .ok_or_else(|| format!("No installed package provides service '{name}'").into())
+}
+
+fn service_dir() -> String {
+    format!("{PIE_DATA}/services")
+}
+
+fn running_pid(name: &str) -> Option<u32> {
+    let pid: u32 = fs::read_to_string(format!("{}/{name}.pid", service_dir()))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Path::new(&format!("/proc/{pid}")).exists().then_some(pid)
+}
+
+// The service runs under a small shell supervisor implementing the restart
+// policy; it leads its own process group so stop can take down the whole tree
+fn start(name: &str, spec: &ServiceSpec) -> Result<(), PieError> {
+    if let Some(pid) = running_pid(name) {
+        println!("Service '{name}' is already running (pid {pid})");
+        return Ok(());
+    }
+
+    fs::create_dir_all(service_dir())?;
+    let working_dir = spec.working_dir.as_deref().unwrap_or(&ANDSTORE_ROOT);
+    let restart_check = match spec.restart {
+        RestartPolicy::Always => "",
+        RestartPolicy::OnFailure => "[ $code -eq 0 ] && break",
+        RestartPolicy::Never => "break",
+    };
+    let supervisor = format!(
+        "cd '{working_dir}' || exit 1\n\
+         while true; do\n\
+         {command}\n\
+         code=$?\n\
+         {restart_check}\n\
+         sleep 1\n\
+         done\n",
+        command = spec.command
+    );
+
+    let log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(format!("{}/{name}.log", service_dir()))?;
+    let child = Command::new("sh")
+        .args(["-c", &supervisor])
+        .env(
+            "PATH",
+            format!(
+                "{ANDSTORE_ROOT}/bin:{}",
+                std::env::var("PATH").unwrap_or_default()
+            ),
+        )
+        .env(
+            "LD_LIBRARY_PATH",
+            format!("{ANDSTORE_ROOT}/lib:{ANDSTORE_ROOT}/lib64:{ANDSTORE_ROOT}/lib32"),
+        )
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .process_group(0)
+        .spawn()?;
+
+    fs::write(
+        format!("{}/{name}.pid", service_dir()),
+        child.id().to_string(),
+    )?;
+    println!("Started service '{name}' (pid {})", child.id());
+    Ok(())
+}
+
+pub fn stop(name: &str) -> Result<(), PieError> {
+    let Some(pid) = running_pid(name) else {
+        println!("Service '{name}' is not running");
+        return Ok(());
+    };
+
+    exec::kill_group(pid, "TERM");
+    let timeout = config::load_config()
+        .map(|config| config.script_timeout)
+        .unwrap_or(exec::DEFAULT_SCRIPT_TIMEOUT);
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+    while Path::new(&format!("/proc/{pid}")).exists() {
+        if Instant::now() >= deadline {
+            println!("Service '{name}' did not stop within {timeout} seconds, killing it");
+            exec::kill_group(pid, "KILL");
+            break;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    fs::remove_file(format!("{}/{name}.pid", service_dir()))?;
+    println!("Stopped service '{name}'");
+    Ok(())
+}
+
+fn status(installed: &InstalledPackages, name: Option<&str>) -> Result<(), PieError> {
+    let enabled = enabled_services()?;
+    let mut services: Vec<(&String, &String)> = installed
+        .packages
+        .iter()
+        .flat_map(|(pkg_name, pkg)| pkg.services.keys().map(move |svc| (svc, pkg_name)))
+        .filter(|(svc, _)| name.is_none_or(|name| svc.as_str() == name))
+        .collect();
+    services.sort();
+
+    if services.is_empty() {
+        match name {
+            Some(name) => {
+                return Err(format!("No installed package provides service '{name}'").into())
+            }
+            None => println!("No services installed"),
+        }
+        return Ok(());
+    }
+
+    for (svc, pkg_name) in services {
+        let state = match running_pid(svc) {
+            Some(pid) => format!("running (pid {pid})"),
+            None => "stopped".to_string(),
+        };
+        let boot = if enabled.contains(svc) {
+            ", enabled"
+        } else {
+            ""
+        };
+        println!("{BULLET} {svc} [{pkg_name}] {state}{boot}");
+    }
+
+    Ok(())
+}
+
+pub fn enabled_services() -> Result<BTreeSet<String>, PieError> {
+    let enabled_file = format!("{}/enabled.json", service_dir());
+    if !Path::new(&enabled_file).exists() {
+        return Ok(BTreeSet::new());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(&enabled_file)?)?)
+}
+
+fn save_enabled_services(enabled: &BTreeSet<String>) -> Result<(), PieError> {
+    fs::create_dir_all(service_dir())?;
+    fs::write(
+        format!("{}/enabled.json", service_dir()),
+        serde_json::to_string_pretty(enabled)?,
+    )?;
+    Ok(())
+}
+
+// Hands enabled services to the root manager's late-start boot stage
+pub fn write_boot_script() -> Result<(), PieError> {
+    // A workstation binary can't run on the device, the target's own pie can
+    let pie = if paths::host() {
+        Path::new(paths::device_path(&ANDSTORE_ROOT)).join("bin/pie")
+    } else {
+        std::env::current_exe()?
+    };
+    let script = format!(
+        "#!/system/bin/sh\n\
+         # generated by pie, do not edit\n\
+         until [ \"$(getprop sys.boot_completed)\" = 1 ]; do sleep 1; done\n\
+         {} service boot\n",
+        pie.display()
+    );
+
+    let boot_script = paths::on_device(BOOT_SCRIPT);
+    if let Some(parent) = Path::new(&boot_script).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&boot_script, script)?;
+    fs::set_permissions(&boot_script, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+// Called on uninstall so removed daemons don't keep running or restart on boot
+pub fn remove_services(names: &[String]) -> Result<(), PieError> {
+    if names.is_empty() {
+        return Ok(());
+    }
+
+    let mut enabled = enabled_services()?;
+    for name in names {
+        stop(name)?;
+        enabled.remove(name);
+    }
+    save_enabled_services(&enabled)
+}
@@ -0,0 +1,45 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// 128 + SIGINT, what shells report for a command stopped with Ctrl-C
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static CRITICAL: AtomicBool = AtomicBool::new(false);
+
+// Outside a transaction there is nothing to clean up, so SIGINT/SIGTERM exit
+// right away. Inside one the signal is only recorded and the transaction
+// unwinds at the next check
+pub fn install_handler() {
+    let _ = ctrlc::set_handler(|| {
+        if CRITICAL.load(Ordering::SeqCst) {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+        } else {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+    });
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub fn check() -> io::Result<()> {
+    if interrupted() {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "Interrupted"));
+    }
+    Ok(())
+}
+
+pub struct CriticalSection;
+
+pub fn critical() -> CriticalSection {
+    CRITICAL.store(true, Ordering::SeqCst);
+    CriticalSection
+}
+
+impl Drop for CriticalSection {
+    fn drop(&mut self) {
+        CRITICAL.store(false, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,160 @@
+use clap::Subcommand;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path};
+use tar::{Archive, Builder};
+use zstd::stream::read::Decoder;
+use zstd::stream::write::Encoder;
+
+use crate::error::PieError;
+use crate::glyph::{BULLET, CHECK};
+use crate::{
+    format_size, format_timestamp, get_separator, prompt, unix_now, ANDSTORE_ROOT, PIE_DATA,
+};
+
+const SNAPSHOT_ROOT: &str = "root";
+const SNAPSHOT_DB: &str = "installed.json";
+
+#[derive(Subcommand)]
+pub enum SnapshotAction {
+    /// List existing snapshots
+    List,
+    /// Archive the install root and database
+    Create,
+    /// Roll the install root and database back to a snapshot
+    Restore { id: String },
+    /// Delete a snapshot
+    Delete { id: String },
+}
+
+pub fn run_snapshot(action: SnapshotAction) -> Result<(), PieError> {
+    match action {
+        SnapshotAction::List => list_snapshots(),
+        SnapshotAction::Create => create_snapshot().map(|_| ()),
+        SnapshotAction::Restore { id } => restore_snapshot(&id),
+        SnapshotAction::Delete { id } => {
+            fs::remove_file(snapshot_path(&id)?)?;
+            println!("Deleted snapshot {id}");
+            Ok(())
+        }
+    }
+}
+
+fn snapshot_dir() -> String {
+    format!("{PIE_DATA}/snapshots")
+}
+
+fn snapshot_path(id: &str) -> Result<String, PieError> {
+    let path = format!("{}/{id}.tar.zst", snapshot_dir());
+    if !Path::new(&path).exists() {
+        return Err(format!("Snapshot '{id}' not found").into());
+    }
+    Ok(path)
+}
+
+// Ids are the UTC creation time, e.g. 20261015-233900, so they sort by age
+fn list_snapshots() -> Result<(), PieError> {
+    let mut snapshots: Vec<(String, u64)> = Vec::new();
+    if Path::new(&snapshot_dir()).exists() {
+        for entry in fs::read_dir(snapshot_dir())? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(id) = name.strip_suffix(".tar.zst") {
+                snapshots.push((id.to_string(), entry.metadata()?.len()));
+            }
+        }
+    }
+    snapshots.sort();
+
+    if snapshots.is_empty() {
+        println!("No snapshots");
+        return Ok(());
+    }
+
+    println!("Snapshots ({}):\n", snapshots.len());
+    for (id, size) in snapshots {
+        println!("{BULLET} {id} ({})", format_size(size));
+    }
+    Ok(())
+}
+
+pub fn create_snapshot() -> Result<String, PieError> {
+    let id = format_timestamp(unix_now())
+        .replace(['-', ':'], "")
+        .replace(' ', "-");
+    fs::create_dir_all(snapshot_dir())?;
+    let path = format!("{}/{id}.tar.zst", snapshot_dir());
+
+    print!("Creating snapshot {id}... ");
+    io::stdout().flush()?;
+
+    let file = fs::File::create(&path)?;
+    let encoder = Encoder::new(file, 3)?;
+    let mut builder = Builder::new(encoder);
+    builder.follow_symlinks(false);
+
+    if Path::new(&ANDSTORE_ROOT).exists() {
+        builder.append_dir_all(SNAPSHOT_ROOT, &ANDSTORE_ROOT)?;
+    }
+    let installed_file = format!("{PIE_DATA}/installed.json");
+    if Path::new(&installed_file).exists() {
+        builder.append_path_with_name(&installed_file, SNAPSHOT_DB)?;
+    }
+    builder.into_inner()?.finish()?;
+
+    println!("{CHECK} ({})", format_size(fs::metadata(&path)?.len()));
+    Ok(id)
+}
+
+fn restore_snapshot(id: &str) -> Result<(), PieError> {
+    let path = snapshot_path(id)?;
+
+    println!("\n{}", get_separator());
+    println!("RESTORING SNAPSHOT {id}");
+    println!("{}", get_separator());
+    println!("All files under {ANDSTORE_ROOT} will be replaced by the snapshot contents.");
+
+    if !prompt::confirm("\nContinue?", false)? {
+        println!("Restore cancelled");
+        return Ok(());
+    }
+
+    print!("Restoring files... ");
+    io::stdout().flush()?;
+    if Path::new(&ANDSTORE_ROOT).exists() {
+        fs::remove_dir_all(&ANDSTORE_ROOT)?;
+    }
+    fs::create_dir_all(&ANDSTORE_ROOT)?;
+
+    // A snapshot without a database was taken with nothing installed
+    let installed_file = format!("{PIE_DATA}/installed.json");
+    if Path::new(&installed_file).exists() {
+        fs::remove_file(&installed_file)?;
+    }
+
+    let decoder = Decoder::new(fs::File::open(&path)?)?;
+    let mut archive = Archive::new(decoder);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+
+        if entry_path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            continue;
+        }
+
+        if let Ok(relative) = entry_path.strip_prefix(SNAPSHOT_ROOT) {
+            entry.unpack(Path::new(&ANDSTORE_ROOT).join(relative))?;
+        } else if entry_path == Path::new(SNAPSHOT_DB) {
+            fs::create_dir_all(&PIE_DATA)?;
+            entry.unpack(&installed_file)?;
+        }
+    }
+    println!("{CHECK}");
+
+    println!("Restored snapshot {id}");
+    println!("{}", get_separator());
+    Ok(())
+}
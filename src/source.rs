@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+
+use crate::error::PieError;
+use crate::glyph::{CHECK, CROSS};
+use crate::{fetch_repo, format_size, get_separator, net};
+
+// Where the sources of a package come from. A tarball is preferred since it
+// can be pinned by hash, the VCS reference is for projects without releases
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SourceSpec {
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub vcs: Option<Vcs>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Vcs {
+    #[serde(rename = "type", default = "default_vcs_type")]
+    pub kind: String,
+    pub url: String,
+    #[serde(default)]
+    pub rev: Option<String>,
+}
+
+fn default_vcs_type() -> String {
+    "git".to_string()
+}
+
+pub fn fetch_source(name: &str, output: &str) -> Result<(), PieError> {
+    let repo = fetch_repo()?;
+    let package = repo
+        .packages
+        .get(name)
+        .ok_or_else(|| PieError::Resolution(format!("Package '{name}' not found")))?;
+    let source = package.source.as_ref().ok_or_else(|| {
+        PieError::Resolution(format!("Package '{name}' has no source information"))
+    })?;
+
+    println!("{}", get_separator());
+    println!("SOURCES OF {name} v{}", package.version);
+    println!("{}", get_separator());
+    fs::create_dir_all(output)?;
+
+    if let Some(url) = &source.url {
+        fetch_tarball(url, source.sha256.as_deref(), output)?;
+    }
+    if let Some(vcs) = &source.vcs {
+        let dir = format!("{output}/{name}-{}", package.version);
+        clone_vcs(vcs, &dir)?;
+    }
+    if source.url.is_none() && source.vcs.is_none() {
+        println!("No source url or VCS reference is published for {name}");
+    }
+
+    println!("{}", get_separator());
+    Ok(())
+}
+
+// Upstream hosts never get the repo's credentials
+fn fetch_tarball(url: &str, sha256: Option<&str>, output: &str) -> Result<(), PieError> {
+    let file_name = url
+        .rsplit('/')
+        .next()
+        .filter(|segment| !segment.is_empty())
+        .unwrap_or("source.tar");
+    let path = format!("{output}/{file_name}");
+
+    print!("Downloading {url}... ");
+    io::stdout().flush()?;
+    let content = net::fetch_bytes(url, None)?;
+    println!("{CHECK} ({})", format_size(content.len() as u64));
+
+    match sha256 {
+        Some(expected) => {
+            print!("Verifying checksum... ");
+            io::stdout().flush()?;
+            if hex::encode(Sha256::digest(&content)) != expected.to_lowercase() {
+                println!("{CROSS}");
+                return Err(PieError::Checksum(url.to_string()));
+            }
+            println!("{CHECK}");
+        }
+        None => println!("Warning: no checksum published, the tarball is not verified"),
+    }
+
+    fs::write(&path, content)?;
+    println!("Saved to {path}");
+    Ok(())
+}
+
+fn clone_vcs(vcs: &Vcs, dir: &str) -> Result<(), PieError> {
+    if vcs.kind != "git" {
+        return Err(format!("Unsupported VCS type '{}'", vcs.kind).into());
+    }
+    if Path::new(dir).exists() {
+        return Err(format!("{dir} already exists").into());
+    }
+
+    print!("Cloning {}... ", vcs.url);
+    io::stdout().flush()?;
+    git(&["clone", "--quiet", &vcs.url, dir])?;
+    println!("{CHECK}");
+
+    let Some(rev) = &vcs.rev else {
+        println!("Warning: no revision published, {dir} is at the default branch");
+        return Ok(());
+    };
+
+    print!("Checking out {rev}... ");
+    io::stdout().flush()?;
+    git(&["-C", dir, "checkout", "--quiet", "--detach", rev])?;
+
+    // A full commit hash pins the tree, a tag or branch could have moved
+    if rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit()) {
+        let head = git(&["-C", dir, "rev-parse", "HEAD"])?;
+        if head.trim() != rev.to_lowercase() {
+            println!("{CROSS}");
+            return Err(PieError::Checksum(format!("{} at {rev}", vcs.url)));
+        }
+        println!("{CHECK} (verified)");
+    } else {
+        println!("{CHECK} (not a commit hash, unverified)");
+    }
+    println!("Sources in {dir}");
+    Ok(())
+}
+
+fn git(args: &[&str]) -> Result<String, PieError> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run git, is it installed? {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+
+use crate::error::PieError;
+
+// Android system properties. Read-only ones like the ABI and API level can't
+// change while pie runs, so every property is looked up once per process
+pub trait PropertySource: Sync {
+    fn get(&self, name: &str) -> Option<String>;
+}
+
+// Bionic's property API, works without a getprop binary on the PATH
+#[cfg(target_os = "android")]
+struct Bionic;
+
+#[cfg(target_os = "android")]
+impl PropertySource for Bionic {
+    fn get(&self, name: &str) -> Option<String> {
+        let name = std::ffi::CString::new(name).ok()?;
+        let mut value = [0 as libc::c_char; libc::PROP_VALUE_MAX as usize];
+        // SAFETY: name is NUL terminated and value holds PROP_VALUE_MAX bytes,
+        // the most the call writes including the terminator
+        let len = unsafe { libc::__system_property_get(name.as_ptr(), value.as_mut_ptr()) };
+        if len <= 0 {
+            return None;
+        }
+        // SAFETY: a positive length means value was NUL terminated
+        let value = unsafe { std::ffi::CStr::from_ptr(value.as_ptr()) };
+        Some(value.to_string_lossy().into_owned())
+    }
+}
+
+struct Getprop;
+
+impl PropertySource for Getprop {
+    fn get(&self, name: &str) -> Option<String> {
+        let output = Command::new("getprop")
+            .arg(name)
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        let value = String::from_utf8(output.stdout).ok()?.trim().to_string();
+        (!value.is_empty()).then_some(value)
+    }
+}
+
+#[cfg(target_os = "android")]
+static SOURCE: &dyn PropertySource = &Bionic;
+#[cfg(not(target_os = "android"))]
+static SOURCE: &dyn PropertySource = &Getprop;
+
+// Host and remote mode read the target's properties instead, the
+// workstation's own (if any) say nothing about it
+struct BuildProps(HashMap<String, String>);
+
+impl PropertySource for BuildProps {
+    fn get(&self, name: &str) -> Option<String> {
+        self.0.get(name).filter(|value| !value.is_empty()).cloned()
+    }
+}
+
+static TARGET: OnceLock<BuildProps> = OnceLock::new();
+
+// A props file given explicitly must exist, the image's own are optional so
+// a bare staging directory works with --abi and --api alone
+pub fn use_host(
+    root: &str,
+    props_file: Option<&str>,
+    abi: Option<&str>,
+    api: Option<u32>,
+) -> Result<(), PieError> {
+    let mut props = HashMap::new();
+    match props_file {
+        Some(file) => parse_build_prop(&std::fs::read_to_string(file)?, &mut props),
+        None => {
+            for file in ["system/build.prop", "vendor/build.prop"] {
+                if let Ok(content) = std::fs::read_to_string(format!("{root}/{file}")) {
+                    parse_build_prop(&content, &mut props);
+                }
+            }
+        }
+    }
+
+    // Newer images only list the ABIs
+    if !props.contains_key("ro.product.cpu.abi") {
+        let first = ["ro.product.cpu.abilist", "ro.system.product.cpu.abilist"]
+            .iter()
+            .find_map(|key| props.get(*key)?.split(',').next().map(str::to_string));
+        if let Some(abi) = first {
+            props.insert("ro.product.cpu.abi".to_string(), abi);
+        }
+    }
+    if let Some(abi) = abi {
+        props.insert("ro.product.cpu.abi".to_string(), abi.to_string());
+    }
+    if let Some(api) = api {
+        props.insert("ro.build.version.sdk".to_string(), api.to_string());
+    }
+
+    for (key, flag) in [
+        ("ro.product.cpu.abi", "--abi"),
+        ("ro.build.version.sdk", "--api"),
+    ] {
+        if !props.contains_key(key) {
+            return Err(PieError::Other(format!(
+                "The target has no {key}, pass it with {flag}"
+            )));
+        }
+    }
+
+    use_props(props);
+    Ok(())
+}
+
+pub fn use_props(props: HashMap<String, String>) {
+    let _ = TARGET.set(BuildProps(props));
+}
+
+// Later files override earlier ones, like init does
+fn parse_build_prop(content: &str, props: &mut HashMap<String, String>) {
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            props.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+}
+
+static CACHE: Mutex<Option<HashMap<String, Option<String>>>> = Mutex::new(None);
+
+// Unset and empty properties are both None
+pub fn get(name: &str) -> Option<String> {
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache
+        .get_or_insert_with(HashMap::new)
+        .entry(name.to_string())
+        .or_insert_with(|| match TARGET.get() {
+            Some(target) => target.get(name),
+            None => SOURCE.get(name),
+        })
+        .clone()
+}
@@ -0,0 +1,46 @@
+use serde::Serialize;
+
+use crate::config;
+use crate::net;
+use crate::Repo;
+
+#[derive(Serialize)]
+struct Report<'a> {
+    event: &'a str,
+    packages: Vec<ReportPackage<'a>>,
+}
+
+// Only what the repo already publishes, nothing that identifies the device
+#[derive(Serialize)]
+struct ReportPackage<'a> {
+    name: &'a str,
+    version: &'a str,
+    repo: &'a str,
+}
+
+// Strictly opt-in: nothing is sent unless the user set telemetry_endpoint.
+// A failed ping is ignored, statistics never hold up a transaction
+pub fn report(event: &str, names: &[String], repo: &Repo) {
+    let Some(endpoint) = config::load_config()
+        .ok()
+        .and_then(|config| config.telemetry_endpoint)
+    else {
+        return;
+    };
+
+    let packages = names
+        .iter()
+        .filter_map(|name| {
+            repo.packages.get(name).map(|package| ReportPackage {
+                name,
+                version: &package.version,
+                repo: &package.repo,
+            })
+        })
+        .collect::<Vec<_>>();
+    if packages.is_empty() {
+        return;
+    }
+
+    let _ = net::post_json(&endpoint, &Report { event, packages });
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::config::{self, UnattendedPolicy};
+use crate::error::PieError;
+use crate::{
+    fetch_repo, format_timestamp, get_installed_packages, glob, logcat, unix_now, upgrade, PIE_DATA,
+};
+
+const LOG_FILE: &str = "unattended.log";
+
+// Meant for cron or a service.d script: every decision and its outcome goes
+// to the log, and the result to a notification since nobody watches the run
+pub fn run_unattended(security_only: bool) -> Result<(), PieError> {
+    let policy = config::load_config()?.unattended;
+    log("Unattended upgrade started")?;
+
+    if let Some(window) = &policy.window {
+        if !in_window(window, local_minutes())? {
+            return log(&format!(
+                "Outside the maintenance window {window}, nothing to do"
+            ));
+        }
+    }
+
+    match metered() {
+        Some(true) if !policy.allow_metered => {
+            return log("On a metered network, skipped");
+        }
+        None if !policy.allow_metered => {
+            log("Could not tell whether the network is metered, continuing")?;
+        }
+        _ => {}
+    }
+
+    let names = eligible_packages(&policy)?;
+    if names.is_empty() {
+        return log("No packages are eligible under the policy");
+    }
+
+    let before = installed_versions()?;
+    let result = upgrade::upgrade(&names, security_only || policy.security_only, false);
+    let after = installed_versions()?;
+
+    let mut names: Vec<&String> = after.keys().collect();
+    names.sort();
+    for name in names {
+        match before.get(name) {
+            Some(old) if *old != after[name] => {
+                log(&format!("Upgraded {name} v{old} -> v{}", after[name]))?
+            }
+            None => log(&format!(
+                "Installed {name} v{} as a new dependency",
+                after[name]
+            ))?,
+            _ => {}
+        }
+    }
+
+    match result {
+        Ok(()) => log("Unattended upgrade finished"),
+        Err(e) => {
+            log(&format!("Unattended upgrade failed: {e}"))?;
+            Err(e)
+        }
+    }
+}
+
+fn eligible_packages(policy: &UnattendedPolicy) -> Result<Vec<String>, PieError> {
+    let repo = fetch_repo()?;
+    let installed = get_installed_packages()?;
+
+    let mut names = Vec::new();
+    for name in installed.packages.keys() {
+        if policy
+            .exclude
+            .iter()
+            .any(|pattern| glob::matches(pattern, name))
+        {
+            log(&format!("{name} is held by the policy"))?;
+            continue;
+        }
+        let Some(package) = repo.packages.get(name) else {
+            continue;
+        };
+        if !policy.repos.is_empty() && !policy.repos.contains(&package.repo) {
+            log(&format!(
+                "{name} comes from repo '{}', which the policy does not allow",
+                package.repo
+            ))?;
+            continue;
+        }
+        names.push(name.clone());
+    }
+    names.sort();
+    Ok(names)
+}
+
+fn installed_versions() -> Result<HashMap<String, String>, PieError> {
+    Ok(get_installed_packages()?
+        .packages
+        .into_iter()
+        .map(|(name, pkg)| (name, pkg.version))
+        .collect())
+}
+
+// "02:00-05:00", a window may wrap around midnight
+fn in_window(window: &str, now: u32) -> Result<bool, PieError> {
+    let parse = |time: &str| -> Option<u32> {
+        let (hours, minutes) = time.trim().split_once(':')?;
+        let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+        (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+    };
+    let (start, end) = window
+        .split_once('-')
+        .and_then(|(start, end)| Some((parse(start)?, parse(end)?)))
+        .ok_or_else(|| PieError::Other(format!("Invalid maintenance window '{window}'")))?;
+
+    Ok(if start <= end {
+        (start..end).contains(&now)
+    } else {
+        now >= start || now < end
+    })
+}
+
+// Minutes since local midnight. Only the system's date knows the device
+// timezone, UTC is the fallback
+fn local_minutes() -> u32 {
+    Command::new("date")
+        .arg("+%H:%M")
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .and_then(|output| {
+            let time = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            let (hours, minutes) = time.split_once(':')?;
+            Some(hours.parse::<u32>().ok()? * 60 + minutes.parse::<u32>().ok()?)
+        })
+        .unwrap_or((unix_now() % 86400 / 60) as u32)
+}
+
+// From the capabilities of the default network in `dumpsys connectivity`,
+// None when that is unavailable, e.g. without root
+fn metered() -> Option<bool> {
+    let output = Command::new("dumpsys")
+        .arg("connectivity")
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+
+    let network = text
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Active default network:"))?
+        .trim();
+    let agent = text.lines().find(|line| {
+        line.contains("NetworkAgentInfo{") && line.contains(&format!("network{{{network}}}"))
+    })?;
+    Some(!agent.contains("NOT_METERED"))
+}
+
+fn log(message: &str) -> Result<(), PieError> {
+    println!("{message}");
+    logcat::info(message);
+
+    fs::create_dir_all(&PIE_DATA)?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(format!("{PIE_DATA}/{LOG_FILE}"))?;
+    writeln!(file, "{} {message}", format_timestamp(unix_now()))?;
+    Ok(())
+}
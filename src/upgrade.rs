@@ -0,0 +1,306 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use crate::audit;
+use crate::config;
+use crate::error::PieError;
+use crate::explain;
+use crate::glyph::{ARROW, BRANCH, BULLET};
+use crate::journal;
+use crate::{
+    check_file_conflicts, fetch_repo, find_conflicts, find_file_conflicts, format_size,
+    get_installed_packages, get_separator, hook_packages_repo, hooks, install_single_package,
+    notify, order_transaction, package_architecture, package_contents, package_root, prefetch,
+    print_conflicts, print_notes, progress, prompt, remove_conflicts, resolve_dependencies,
+    save_installed_packages, signal, telemetry, version, InstalledPackages, Repo,
+};
+
+// Installed packages with a newer version in the repo. Packages that are
+// newer than the repo are reported instead of being downgraded, unless
+// --force-downgrade asks for the repo's version
+fn upgrade_candidates(repo: &Repo, installed: &InstalledPackages, names: &[String]) -> Vec<String> {
+    let mut candidates = Vec::new();
+
+    // Subpackages move in lockstep with the package that defines them
+    let origin = |name: &str| {
+        repo.packages
+            .get(name)
+            .map(|package| package.origin.as_deref().unwrap_or(name).to_string())
+    };
+    let origins: Vec<String> = names.iter().filter_map(|name| origin(name)).collect();
+
+    let mut packages: Vec<_> = installed
+        .packages
+        .values()
+        .filter(|pkg| {
+            names.is_empty()
+                || names.contains(&pkg.name)
+                || origin(&pkg.name).is_some_and(|o| origins.contains(&o))
+        })
+        .collect();
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for pkg in packages {
+        let Some(available) = repo.packages.get(&pkg.name) else {
+            continue;
+        };
+        if config::ignored(&pkg.name) {
+            if available.version != pkg.version {
+                println!(
+                    "Holding back {} v{}, it is on the ignore list",
+                    pkg.name, available.version
+                );
+            }
+            continue;
+        }
+        match version::compare_versions(&available.version, &pkg.version) {
+            Ordering::Greater => candidates.push(pkg.name.clone()),
+            Ordering::Less if config::force().downgrade => {
+                println!(
+                    "{} v{} is newer than the repo's v{}, downgrading (--force-downgrade)",
+                    pkg.name, pkg.version, available.version
+                );
+                candidates.push(pkg.name.clone());
+            }
+            Ordering::Less => println!(
+                "Warning: {} v{} is newer than the repo's v{}, not downgrading",
+                pkg.name, pkg.version, available.version
+            ),
+            Ordering::Equal => {}
+        }
+    }
+
+    candidates
+}
+
+// Compares the recorded contents with the new version's file list from the
+// index, before anything is downloaded
+fn print_file_changes(
+    repo: &Repo,
+    installed: &InstalledPackages,
+    upgrades: &[String],
+) -> Result<(), PieError> {
+    for name in upgrades {
+        let (package, architecture) = package_architecture(repo, name)?;
+        let old = &installed.packages[name];
+        let new_contents = package_contents(name, package, architecture, old.overlay);
+        let old_files: BTreeSet<&String> = old.contents.iter().collect();
+        let new_files: BTreeSet<&String> = new_contents.iter().collect();
+
+        println!("\n{name} v{} {ARROW} v{}:", old.version, package.version);
+        for file in new_files.difference(&old_files) {
+            println!("  + {file}");
+        }
+        for file in old_files.difference(&new_files) {
+            println!("  - {file}");
+        }
+        for file in old_files.intersection(&new_files) {
+            println!("  ~ {file}");
+        }
+    }
+    Ok(())
+}
+
+pub fn upgrade(names: &[String], security_only: bool, show_files: bool) -> Result<(), PieError> {
+    progress::phase("resolve");
+    println!("Fetching repository information...");
+    let repo = fetch_repo()?;
+    let mut installed = get_installed_packages()?;
+
+    for name in names {
+        if !installed.packages.contains_key(name) {
+            return Err(PieError::Resolution(format!(
+                "Package '{name}' is not installed"
+            )));
+        }
+    }
+
+    let mut upgrades = upgrade_candidates(&repo, &installed, names);
+    if security_only {
+        let available = upgrades.len();
+        upgrades.retain(|name| {
+            !audit::fixed_advisories(&repo, name, &installed.packages[name].version).is_empty()
+        });
+        if upgrades.len() < available {
+            println!(
+                "Holding back {} updates without security fixes",
+                available - upgrades.len()
+            );
+        }
+    }
+    if upgrades.is_empty() {
+        if security_only {
+            println!("No security updates available");
+        } else {
+            println!("All packages are up to date");
+        }
+        return Ok(());
+    }
+
+    // New versions may pull in dependencies that aren't installed yet
+    explain::check(&repo, &upgrades, &installed, &[])?;
+    let mut dependencies: Vec<String> = Vec::new();
+    for name in &upgrades {
+        for dep in resolve_dependencies(&repo, name, &installed)? {
+            if !dependencies.contains(&dep) && !upgrades.contains(&dep) {
+                dependencies.push(dep);
+            }
+        }
+    }
+
+    let mut transaction = dependencies.clone();
+    transaction.extend(upgrades.iter().cloned());
+    let transaction = order_transaction(&repo, transaction)?;
+    let conflicts = find_conflicts(&repo, &transaction, &installed)?;
+    check_file_conflicts(&find_file_conflicts(
+        &repo,
+        &transaction,
+        &installed,
+        &conflicts,
+        config::overlay_default()?,
+    )?)?;
+
+    println!("\n{}", get_separator());
+    println!("UPGRADE SUMMARY");
+    println!("{}", get_separator());
+
+    let mut total_download = 0u64;
+    if !dependencies.is_empty() {
+        println!("New dependencies ({}):", dependencies.len());
+        for dep in &dependencies {
+            let (package, architecture) = package_architecture(&repo, dep)?;
+            total_download += architecture.size;
+            println!("  {BRANCH} {} v{}", dep, package.version);
+        }
+    }
+    println!("Packages to upgrade ({}):", upgrades.len());
+    for name in &upgrades {
+        let (package, architecture) = package_architecture(&repo, name)?;
+        total_download += architecture.size;
+        let current = &installed.packages[name].version;
+        let fixes: Vec<&str> = audit::fixed_advisories(&repo, name, current)
+            .iter()
+            .map(|advisory| advisory.id.as_str())
+            .collect();
+        let fixes = if fixes.is_empty() {
+            String::new()
+        } else {
+            format!(" (fixes {})", fixes.join(", "))
+        };
+        println!(
+            "  {BULLET} {} v{} {ARROW} v{}{}",
+            name, current, package.version, fixes
+        );
+    }
+    print_conflicts(&conflicts, &installed);
+    if show_files {
+        print_file_changes(&repo, &installed, &upgrades)?;
+    }
+    println!("\nTotal download size: {}", format_size(total_download));
+
+    if !prompt::confirm("\nProceed with upgrade?", true)? {
+        println!("Upgrade cancelled");
+        return Ok(());
+    }
+    let summary: Vec<String> = upgrades
+        .iter()
+        .map(|name| {
+            format!(
+                "{name} v{} {ARROW} v{}",
+                installed.packages[name].version, repo.packages[name].version
+            )
+        })
+        .chain(
+            dependencies
+                .iter()
+                .map(|dep| format!("{dep} v{} (new)", repo.packages[dep].version)),
+        )
+        .collect();
+
+    remove_conflicts(&repo, &conflicts, &mut installed)?;
+    let hook_packages = hook_packages_repo(&transaction, &repo);
+    hooks::run_hooks("pre-upgrade", &hook_packages)?;
+
+    println!("\n{}", get_separator());
+    println!("UPGRADING PACKAGES");
+    println!("{}", get_separator());
+    progress::phase("upgrade");
+
+    let total = transaction.len();
+    // As for installs, with the version each upgrade replaces so a rollback
+    // can restore it from the cache
+    let overlay = config::overlay_default()?;
+    let planned = transaction
+        .iter()
+        .map(|name| {
+            let old = installed.packages.get(name);
+            let overlay = old.map_or(overlay, |old| old.overlay);
+            package_architecture(&repo, name).map(|(p, a)| {
+                (
+                    name.clone(),
+                    package_contents(name, p, a, overlay),
+                    old.map(|old| old.version.clone()),
+                )
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    let _critical = signal::critical();
+    journal::begin_upgrade(&upgrades, overlay, planned)?;
+
+    for (i, name) in transaction.iter().enumerate() {
+        signal::check()?;
+        println!("[{}/{}] {}", i + 1, total, name);
+        progress::package("upgrade", name, i + 1, total);
+        if let Some(next) = transaction.get(i + 1) {
+            prefetch::start(&repo, next);
+        }
+
+        match installed.packages.get(name) {
+            Some(old) => {
+                let (overlay, explicit) = (old.overlay, old.explicit);
+                let old_contents = old.contents.clone();
+                install_single_package(&repo, name, &mut installed, overlay, explicit)?;
+                remove_stale_files(name, &old_contents, &installed)?;
+            }
+            None => {
+                install_single_package(&repo, name, &mut installed, overlay, false)?;
+            }
+        }
+        save_installed_packages(&installed)?;
+        journal::mark_done(name)?;
+    }
+    journal::finish()?;
+
+    hooks::run_hooks("post-upgrade", &hook_packages)?;
+    telemetry::report("upgrade", &transaction, &repo);
+    notify::completed("upgraded", &summary);
+    progress::done("upgrade", &transaction);
+    print_notes(&repo, &transaction);
+
+    println!("{}", get_separator());
+    println!("Upgrade completed successfully!");
+    println!("{}", get_separator());
+
+    Ok(())
+}
+
+// Files the old version shipped that the new one no longer does
+pub fn remove_stale_files(
+    name: &str,
+    old_contents: &[String],
+    installed: &InstalledPackages,
+) -> Result<(), PieError> {
+    let Some(pkg) = installed.packages.get(name) else {
+        return Ok(());
+    };
+    let root = package_root(pkg.overlay);
+    for file in old_contents.iter().filter(|f| !pkg.contents.contains(f)) {
+        let path = format!("{root}/{file}");
+        if Path::new(&path).exists() {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
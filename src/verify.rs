@@ -0,0 +1,125 @@
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::error::PieError;
+use crate::glyph::{CHECK, CROSS};
+use crate::{get_installed_packages, get_separator, package_root, sha256_file};
+
+enum Problem {
+    Missing,
+    Modified,
+    Xattr(String),
+    // A hardlinked name is gone or was replaced, the data is still there
+    // under the name it was linked to
+    Unlinked(String),
+}
+
+struct FileCheck<'a> {
+    package: &'a str,
+    path: String,
+    hash: Option<&'a String>,
+    xattrs: Option<&'a HashMap<String, String>>,
+    link: Option<String>,
+}
+
+// Files of packages installed before hashes were recorded are only checked
+// for existence
+pub fn verify_packages(names: &[String]) -> Result<bool, PieError> {
+    let installed = get_installed_packages()?;
+
+    for name in names {
+        if !installed.packages.contains_key(name) {
+            return Err(PieError::Resolution(format!(
+                "Package '{name}' is not installed"
+            )));
+        }
+    }
+
+    let mut packages: Vec<_> = installed
+        .packages
+        .values()
+        .filter(|pkg| names.is_empty() || names.contains(&pkg.name))
+        .collect();
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let files: Vec<FileCheck> = packages
+        .iter()
+        .flat_map(|pkg| {
+            let root = package_root(pkg.overlay);
+            pkg.contents.iter().map(move |file| FileCheck {
+                package: &pkg.name,
+                path: format!("{root}/{file}"),
+                hash: pkg.hashes.get(file),
+                xattrs: pkg.xattrs.get(file),
+                link: pkg
+                    .hardlinks
+                    .get(file)
+                    .map(|target| format!("{root}/{target}")),
+            })
+        })
+        .collect();
+
+    println!(
+        "Verifying {} files from {} packages...",
+        files.len(),
+        packages.len()
+    );
+
+    let mut problems: Vec<(&str, &String, Problem)> = files
+        .par_iter()
+        .filter_map(|file| {
+            let path = &file.path;
+            if let Some(target) = &file.link {
+                let linked = fs::symlink_metadata(path)
+                    .ok()
+                    .zip(fs::symlink_metadata(target).ok())
+                    .is_some_and(|(meta, target)| {
+                        meta.dev() == target.dev() && meta.ino() == target.ino()
+                    });
+                if !linked && Path::new(target).exists() {
+                    return Some((file.package, path, Problem::Unlinked(target.clone())));
+                }
+            }
+            if fs::symlink_metadata(Path::new(path)).is_err() {
+                return Some((file.package, path, Problem::Missing));
+            }
+            if let Some(expected) = file.hash {
+                if !sha256_file(Path::new(path)).is_ok_and(|hash| hash == *expected) {
+                    return Some((file.package, path, Problem::Modified));
+                }
+            }
+            // Lost capabilities break binaries without changing their hash
+            let changed = file.xattrs?.iter().find(|(name, value)| {
+                xattr::get(path, name)
+                    .ok()
+                    .flatten()
+                    .is_none_or(|actual| hex::encode(actual) != **value)
+            });
+            changed.map(|(name, _)| (file.package, path, Problem::Xattr(name.clone())))
+        })
+        .collect();
+    problems.sort_by(|a, b| a.1.cmp(b.1));
+
+    if problems.is_empty() {
+        println!("{CHECK} All files are intact");
+        return Ok(true);
+    }
+
+    println!("\n{}", get_separator());
+    println!("PROBLEMS FOUND ({})", problems.len());
+    println!("{}", get_separator());
+    for (pkg, path, problem) in &problems {
+        let problem = match problem {
+            Problem::Missing => "missing".to_string(),
+            Problem::Modified => "modified".to_string(),
+            Problem::Xattr(name) => format!("{name} changed"),
+            Problem::Unlinked(target) => format!("no longer a hardlink of {target}"),
+        };
+        println!("  {CROSS} {path} ({problem}, {pkg})");
+    }
+
+    Ok(false)
+}
@@ -0,0 +1,224 @@
+use std::cmp::Ordering;
+
+// Versions are compared the way dpkg does, with two extensions for what
+// upstreams commonly use:
+//   1.2.3-r2     Alpine style package revision, compared numerically last
+//   1.0-beta.1   semver pre-release, treated like the tilde form 1.0~beta.1
+// so 1.0~beta1 < 1.0-rc.2 < 1.0 < 1.0a < 1.0.1 < 1.0.1-r1 < 1.10
+//
+// An epoch prefix like 1:2.0 overrides everything else, so maintainers can
+// fix a botched upstream scheme (e.g. 2024.1 followed by 1.0) by bumping it
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a_epoch, a) = split_epoch(a);
+    let (b_epoch, b) = split_epoch(b);
+    let (a_upstream, a_revision) = split_version(a);
+    let (b_upstream, b_revision) = split_version(b);
+
+    a_epoch
+        .cmp(&b_epoch)
+        .then_with(|| compare_upstream(&a_upstream, &b_upstream))
+        .then(a_revision.cmp(&b_revision))
+}
+
+// Versions without an epoch are epoch 0
+fn split_epoch(version: &str) -> (u64, &str) {
+    match version.split_once(':') {
+        Some((epoch, rest)) if !epoch.is_empty() && epoch.bytes().all(|c| c.is_ascii_digit()) => {
+            (epoch.parse().unwrap_or(0), rest)
+        }
+        _ => (0, version),
+    }
+}
+
+fn split_version(version: &str) -> (String, u64) {
+    // Build metadata never affects ordering
+    let version = version.split('+').next().unwrap_or(version).trim();
+
+    let (version, revision) = match version.rsplit_once("-r") {
+        Some((base, revision))
+            if !revision.is_empty() && revision.bytes().all(|c| c.is_ascii_digit()) =>
+        {
+            (base, revision.parse().unwrap_or(0))
+        }
+        _ => (version, 0),
+    };
+
+    let upstream = match version.split_once('-') {
+        Some((base, pre)) if pre.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+            format!("{base}~{pre}")
+        }
+        _ => version.to_string(),
+    };
+
+    (upstream, revision)
+}
+
+// Tilde sorts before everything, even the end of the string, then letters,
+// then other symbols
+fn char_order(c: Option<&u8>) -> i32 {
+    match c {
+        None => 0,
+        Some(b'~') => -1,
+        Some(c) if c.is_ascii_digit() => 0,
+        Some(c) if c.is_ascii_alphabetic() => i32::from(*c),
+        Some(c) => i32::from(*c) + 256,
+    }
+}
+
+// Alternates between non-digit runs, compared character by character, and
+// digit runs, compared numerically
+fn compare_upstream(a: &str, b: &str) -> Ordering {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() || j < b.len() {
+        while (i < a.len() && !a[i].is_ascii_digit()) || (j < b.len() && !b[j].is_ascii_digit()) {
+            let ordering = char_order(a.get(i)).cmp(&char_order(b.get(j)));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            i += 1;
+            j += 1;
+        }
+
+        while i < a.len() && a[i] == b'0' {
+            i += 1;
+        }
+        while j < b.len() && b[j] == b'0' {
+            j += 1;
+        }
+
+        let mut first_difference = Ordering::Equal;
+        while i < a.len() && a[i].is_ascii_digit() && j < b.len() && b[j].is_ascii_digit() {
+            if first_difference == Ordering::Equal {
+                first_difference = a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+        if i < a.len() && a[i].is_ascii_digit() {
+            return Ordering::Greater;
+        }
+        if j < b.len() && b[j].is_ascii_digit() {
+            return Ordering::Less;
+        }
+        if first_difference != Ordering::Equal {
+            return first_difference;
+        }
+    }
+
+    Ordering::Equal
+}
+
+// Comma separated comparators that must all hold, e.g. ">=1.0, <1.2.3". A
+// bare version means an exact match
+pub fn matches_range(version: &str, range: &str) -> bool {
+    range
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .all(|comparator| {
+            let (op, bound) = [">=", "<=", "!=", ">", "<", "="]
+                .iter()
+                .find_map(|op| comparator.strip_prefix(op).map(|rest| (*op, rest.trim())))
+                .unwrap_or(("=", comparator));
+            let ordering = compare_versions(version, bound);
+            match op {
+                ">=" => ordering != Ordering::Less,
+                "<=" => ordering != Ordering::Greater,
+                "!=" => ordering != Ordering::Equal,
+                ">" => ordering == Ordering::Greater,
+                "<" => ordering == Ordering::Less,
+                _ => ordering == Ordering::Equal,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ascending(versions: &[&str]) {
+        for pair in versions.windows(2) {
+            let (lower, higher) = (pair[0], pair[1]);
+            assert_eq!(
+                compare_versions(lower, higher),
+                Ordering::Less,
+                "{lower} < {higher}"
+            );
+            assert_eq!(
+                compare_versions(higher, lower),
+                Ordering::Greater,
+                "{higher} > {lower}"
+            );
+        }
+    }
+
+    #[test]
+    fn documented_order() {
+        assert_ascending(&[
+            "1.0~beta1",
+            "1.0-rc.2",
+            "1.0",
+            "1.0a",
+            "1.0.1",
+            "1.0.1-r1",
+            "1.10",
+        ]);
+    }
+
+    #[test]
+    fn revisions_compare_numerically() {
+        assert_ascending(&["1.0", "1.0-r1", "1.0-r2", "1.0-r10", "1.0.1"]);
+    }
+
+    #[test]
+    fn prereleases_sort_before_the_release() {
+        assert_ascending(&["2.0-alpha", "2.0-alpha.2", "2.0-beta", "2.0~rc1", "2.0"]);
+    }
+
+    #[test]
+    fn leading_zeros_are_ignored() {
+        assert_eq!(compare_versions("1.01", "1.1"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0010", "1.10"), Ordering::Equal);
+        assert_eq!(compare_versions("01.2", "1.2"), Ordering::Equal);
+        assert_ascending(&["1.09", "1.10"]);
+    }
+
+    #[test]
+    fn build_metadata_is_ignored() {
+        assert_eq!(compare_versions("1.0+build5", "1.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0+a", "1.0+b"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0-r2+git", "1.0-r2"), Ordering::Equal);
+        assert_ascending(&["1.0+build9", "1.0.1+build1"]);
+    }
+
+    #[test]
+    fn epoch_takes_precedence() {
+        assert_ascending(&["2024.1", "1:1.0", "1:1.1", "2:0.1"]);
+        assert_eq!(compare_versions("0:1.0", "1.0"), Ordering::Equal);
+        assert_eq!(compare_versions("01:1.0", "1:1.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn non_numeric_prefix_is_not_an_epoch() {
+        assert_eq!(split_epoch("a:1"), (0, "a:1"));
+        assert_eq!(split_epoch(":1"), (0, ":1"));
+        assert_eq!(split_epoch("1a:2"), (0, "1a:2"));
+        assert_eq!(split_epoch("3:2.0"), (3, "2.0"));
+        // Compared as an upstream version, so it sorts with other letters
+        assert_ascending(&["a:1", "a:2", "b:1"]);
+    }
+
+    #[test]
+    fn ranges() {
+        assert!(matches_range("1.5", ">=1.0, <2.0"));
+        assert!(!matches_range("2.0", ">=1.0, <2.0"));
+        assert!(matches_range("1.2", "1.2"));
+        assert!(matches_range("1.2", "=1.2"));
+        assert!(!matches_range("1.2.1", "1.2"));
+        assert!(matches_range("1.3", "!=1.2"));
+        assert!(!matches_range("1.2", ">1.0, != 1.2"));
+        assert!(matches_range("1.0", ""));
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use crate::error::PieError;
+use crate::glyph::BACK_ARROW;
+use crate::{cache, get_installed_packages, merge_indices, InstalledPackages};
+
+// Dependencies of each installed package. Entries from before dependencies
+// were recorded fall back to the cached index
+pub fn installed_dependencies(installed: &InstalledPackages) -> HashMap<String, Vec<String>> {
+    let repo = cache::load_cached_indices()
+        .ok()
+        .and_then(|indices| merge_indices(&indices).ok());
+
+    installed
+        .packages
+        .values()
+        .map(|pkg| {
+            let mut dependencies = pkg.dependencies.clone();
+            if dependencies.is_empty() {
+                if let Some(package) = repo.as_ref().and_then(|r| r.packages.get(&pkg.name)) {
+                    dependencies = package.dependencies.clone();
+                }
+            }
+            dependencies.retain(|dep| installed.packages.contains_key(dep));
+            (pkg.name.clone(), dependencies)
+        })
+        .collect()
+}
+
+pub fn explain(name: &str) -> Result<(), PieError> {
+    let installed = get_installed_packages()?;
+    if !installed.packages.contains_key(name) {
+        return Err(PieError::Resolution(format!(
+            "Package '{name}' is not installed"
+        )));
+    }
+
+    let dependencies = installed_dependencies(&installed);
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (pkg, deps) in &dependencies {
+        for dep in deps {
+            dependents
+                .entry(dep.as_str())
+                .or_default()
+                .push(pkg.as_str());
+        }
+    }
+    for list in dependents.values_mut() {
+        list.sort();
+    }
+
+    let mut chains = Vec::new();
+    walk(name, &installed, &dependents, &mut vec![name], &mut chains);
+
+    if chains.is_empty() {
+        println!("{name} {BACK_ARROW} nothing, it is an orphaned dependency");
+    }
+    for chain in chains {
+        println!("{chain}");
+    }
+    Ok(())
+}
+
+// Follows reverse dependencies up to explicitly installed packages, one
+// printed chain per path
+fn walk<'a>(
+    name: &'a str,
+    installed: &InstalledPackages,
+    dependents: &HashMap<&'a str, Vec<&'a str>>,
+    path: &mut Vec<&'a str>,
+    chains: &mut Vec<String>,
+) {
+    if installed.packages.get(name).is_some_and(|pkg| pkg.explicit) {
+        let mut chain = path[0].to_string();
+        for pkg in &path[1..] {
+            chain.push_str(&format!(" {BACK_ARROW} required by {pkg}"));
+        }
+        chain.push_str(&format!(" {BACK_ARROW} requested"));
+        chains.push(chain);
+    }
+
+    for &dependent in dependents.get(name).into_iter().flatten() {
+        if path.contains(&dependent) {
+            continue;
+        }
+        path.push(dependent);
+        walk(dependent, installed, dependents, path, chains);
+        path.pop();
+    }
+}